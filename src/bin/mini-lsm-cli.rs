@@ -11,7 +11,7 @@ use mini_lsm_wrapper::compact::{
     TieredCompactionOptions,
 };
 use mini_lsm_wrapper::iterators::StorageIterator;
-use mini_lsm_wrapper::lsm_storage::{LsmStorageOptions, MiniLsm};
+use mini_lsm_wrapper::lsm_storage::{LsmStorageOptions, MiniLsm, DEFAULT_BLOCK_CACHE_CAPACITY};
 use std::path::PathBuf;
 use std::sync::Arc;
 
@@ -320,6 +320,8 @@ fn main() -> Result<()> {
             block_size: 4096,
             target_sst_size: 2 << 20, // 2MB
             num_memtable_limit: 3,
+            bloom_options: Default::default(),
+            block_cache_capacity: DEFAULT_BLOCK_CACHE_CAPACITY,
             compaction_options: match args.compaction {
                 CompactionStrategy::None => CompactionOptions::NoCompaction,
                 CompactionStrategy::Simple => {
@@ -5,10 +5,16 @@ pub use builder::BlockBuilder;
 use bytes::{Buf, BufMut, Bytes};
 pub use iterator::BlockIterator;
 
+use crate::key::KeySlice;
 /// A block is the smallest unit of read and caching in LSM tree. It is a collection of sorted key-value pairs.
 pub struct Block {
     pub(crate) data: Vec<u8>,
     pub(crate) offsets: Vec<u16>,
+    /// Whether each entry stores a timestamp after its key, i.e., the block is of format version 2.
+    pub(crate) has_ts: bool,
+    /// The indexes of every K-th entry from the first one, which seeks binary search to start
+    /// scanning close to the key. Empty if the block was built without a sparse index.
+    pub(crate) sparse_index: Vec<u16>,
 }
 
 // impl Block {
@@ -47,31 +53,110 @@ pub struct Block {
 // }
 
 pub(crate) const SIZEOF_U16: usize = std::mem::size_of::<u16>();
+pub(crate) const SIZEOF_U64: usize = std::mem::size_of::<u64>();
+
+/// Set in the number of offsets at the end of a block of format version 2, whose entries are
+/// `key_len | user key | ts (u64) | value_len | value`. `key_len` only counts the user key, and the
+/// key of the entry is the user key followed by the timestamp, the same raw key with a timestamp
+/// suffix that `split_ts` and `compare_with_ts` work on. Entries of a version 1 block store the raw
+/// key as it is. A block holds less than 2^15 offsets, so the bit is always free.
+pub(crate) const BLOCK_TS_FLAG: u16 = 1 << 15;
+
+/// Set in the number of offsets at the end of a block with a sparse index, which is stored between
+/// the data and the offsets as the entry indexes (u16 each) followed by their number (u16). Every
+/// entry takes at least 5 bytes of the 64 KiB addressed by the offsets, so a block holds less than
+/// 2^14 offsets and the bit is always free.
+pub(crate) const BLOCK_SPARSE_INDEX_FLAG: u16 = 1 << 14;
+
+/// The location of an entry in the data of a block.
+pub(crate) struct BlockEntry {
+    pub(crate) key: (usize, usize),
+    pub(crate) ts: u64,
+    pub(crate) value: (usize, usize),
+}
 
 impl Block {
+    /// The memory used by the decoded block in bytes, which weighs it in the block cache.
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.data.len()
+            + (self.offsets.len() + self.sparse_index.len()) * SIZEOF_U16
+    }
+
     pub fn encode(&self) -> Bytes {
         let mut buf = self.data.clone();
+        let mut flags = 0;
+        if self.has_ts {
+            flags |= BLOCK_TS_FLAG;
+        }
+        if !self.sparse_index.is_empty() {
+            for idx in &self.sparse_index {
+                buf.put_u16(*idx);
+            }
+            buf.put_u16(self.sparse_index.len() as u16);
+            flags |= BLOCK_SPARSE_INDEX_FLAG;
+        }
         let offsets_len = self.offsets.len();
         for offset in &self.offsets {
             buf.put_u16(*offset);
         }
         // Adds number of elements at the end of the block
-        buf.put_u16(offsets_len as u16);
+        buf.put_u16(offsets_len as u16 | flags);
         buf.into()
     }
 
     pub fn decode(data: &[u8]) -> Self {
+        let get_u16s =
+            |raw: &[u8]| -> Vec<u16> { raw.chunks(SIZEOF_U16).map(|mut x| x.get_u16()).collect() };
         // get number of elements in the block
-        let entry_offsets_len = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
-        let data_end = data.len() - SIZEOF_U16 - entry_offsets_len * SIZEOF_U16;
-        let offsets_raw = &data[data_end..data.len() - SIZEOF_U16];
+        let entry_offsets_len = (&data[data.len() - SIZEOF_U16..]).get_u16();
+        let has_ts = entry_offsets_len & BLOCK_TS_FLAG != 0;
+        let has_sparse_index = entry_offsets_len & BLOCK_SPARSE_INDEX_FLAG != 0;
+        let entry_offsets_len =
+            (entry_offsets_len & !(BLOCK_TS_FLAG | BLOCK_SPARSE_INDEX_FLAG)) as usize;
+        let offsets_start = data.len() - SIZEOF_U16 - entry_offsets_len * SIZEOF_U16;
         // get offset array
-        let offsets = offsets_raw
-            .chunks(SIZEOF_U16)
-            .map(|mut x| x.get_u16())
-            .collect();
+        let offsets = get_u16s(&data[offsets_start..data.len() - SIZEOF_U16]);
+        let (data_end, sparse_index) = if has_sparse_index {
+            let sparse_len = (&data[offsets_start - SIZEOF_U16..]).get_u16() as usize;
+            let sparse_start = offsets_start - SIZEOF_U16 - sparse_len * SIZEOF_U16;
+            (
+                sparse_start,
+                get_u16s(&data[sparse_start..offsets_start - SIZEOF_U16]),
+            )
+        } else {
+            (offsets_start, Vec::new())
+        };
         // retrieve data
         let data = data[0..data_end].to_vec();
-        Self { data, offsets }
+        Self {
+            data,
+            offsets,
+            has_ts,
+            sparse_index,
+        }
+    }
+
+    /// Decode the key and value ranges and the timestamp of the entry at `offset` in the data. The
+    /// key range covers the timestamp that follows the user key in a version 2 block.
+    pub(crate) fn entry_at(&self, offset: usize) -> BlockEntry {
+        let read_u16 =
+            |pos: usize| u16::from_be_bytes([self.data[pos], self.data[pos + 1]]) as usize;
+        let key_start = offset + SIZEOF_U16;
+        let mut key_end = key_start + read_u16(offset);
+        let ts = if self.has_ts {
+            let ts = (&self.data[key_end..key_end + SIZEOF_U64]).get_u64();
+            key_end += SIZEOF_U64;
+            ts
+        } else {
+            KeySlice::from_slice(&self.data[key_start..key_end]).ts()
+        };
+        let value_len_pos = key_end;
+        let value_start = value_len_pos + SIZEOF_U16;
+        BlockEntry {
+            key: (key_start, key_end),
+            ts,
+            value: (value_start, value_start + read_u16(value_len_pos)),
+        }
     }
 }
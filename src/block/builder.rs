@@ -1,6 +1,6 @@
-use crate::key::{KeySlice, KeyVec};
+use crate::key::{split_ts, KeySlice, KeyVec, TS_LEN};
 
-use super::Block;
+use super::{Block, SIZEOF_U16, SIZEOF_U64};
 
 /// Builds a block.
 pub struct BlockBuilder {
@@ -12,6 +12,10 @@ pub struct BlockBuilder {
     block_size: usize,
     /// The first key in the block
     first_key: KeyVec,
+    /// Whether each entry stores a timestamp, i.e., the block is of format version 2.
+    with_ts: bool,
+    /// The interval between the entries of the sparse index, if the block gets one.
+    sparse_index_interval: Option<usize>,
 }
 
 impl BlockBuilder {
@@ -22,21 +26,60 @@ impl BlockBuilder {
             data: Vec::new(),
             block_size,
             first_key: KeyVec::new(),
+            with_ts: false,
+            sparse_index_interval: None,
         }
     }
 
-    /// Adds a key-value pair to the block. Returns false when the block is full.
+    /// Creates a new block builder whose entries store the timestamp suffix of their raw keys after
+    /// the user key, as a block of format version 2.
+    pub fn new_with_ts(block_size: usize) -> Self {
+        BlockBuilder {
+            with_ts: true,
+            ..Self::new(block_size)
+        }
+    }
+
+    /// Store a sparse index of every `interval`-th entry in the block, so that seeks binary search
+    /// it and then scan at most `interval` entries, instead of scanning from the first entry.
+    pub fn with_sparse_index(mut self, interval: usize) -> Self {
+        assert!(interval > 0, "the sparse index interval must be positive");
+        self.sparse_index_interval = Some(interval);
+        self
+    }
+
+    /// The size of the sparse index of a block with `num_entries` entries, with its length.
+    fn sparse_index_size(&self, num_entries: usize) -> usize {
+        self.sparse_index_interval.map_or(0, |interval| {
+            (num_entries.div_ceil(interval) + 1) * SIZEOF_U16
+        })
+    }
+
+    /// Adds a key-value pair to the block. Returns false when the block is full. A builder from
+    /// `new_with_ts` takes raw keys ending with their timestamp.
     #[must_use]
     pub fn add(&mut self, key: KeySlice, value: &[u8]) -> bool {
+        assert!(
+            !self.with_ts || key.len() >= TS_LEN,
+            "key of {} bytes added to a block builder with timestamps has no timestamp",
+            key.len()
+        );
         if self.data.is_empty() {
             self.first_key = KeyVec::from_vec(Vec::from(key.raw_ref()));
         } else {
             let add_len = key.len() + value.len();
-            if self.data.len() + self.offsets.len() * 2 + add_len >= self.block_size {
+            // The sparse index of the block with the new entry, which has `offsets.len()` entries.
+            let sparse_index_size = self.sparse_index_size(self.offsets.len());
+            if self.data.len() + self.offsets.len() * 2 + sparse_index_size + add_len
+                >= self.block_size
+            {
                 return false;
             }
         }
-        let key_len = (key.len() as u16).to_be_bytes();
+        // In a version 2 block, the key length only counts the user key, which the timestamp
+        // follows.
+        let ts_len = if self.with_ts { SIZEOF_U64 } else { 0 };
+        let key_len = ((key.len() - ts_len) as u16).to_be_bytes();
         let value_len = (value.len() as u16).to_be_bytes();
         let mut entry = Vec::new();
         entry.extend_from_slice(&key_len);
@@ -51,6 +94,15 @@ impl BlockBuilder {
         true
     }
 
+    /// Adds a key-value pair to the block, for the user key `key` at timestamp `ts`, i.e., the raw
+    /// key `key` followed by `ts`. Returns false when the block is full.
+    #[must_use]
+    pub fn add_with_ts(&mut self, key: &[u8], ts: u64, value: &[u8]) -> bool {
+        let raw_key = [key, &ts.to_be_bytes()].concat();
+        debug_assert_eq!(split_ts(&raw_key), (key, ts));
+        self.add(KeySlice::from_slice(&raw_key), value)
+    }
+
     pub fn first_key(&self) -> Vec<u8> {
         self.first_key.raw_ref().to_vec()
     }
@@ -61,7 +113,15 @@ impl BlockBuilder {
         }
         let offset = self.offsets[self.offsets.len() - 2] as usize;
         let key_len = u16::from_be_bytes([self.data[offset], self.data[offset + 1]]) as usize;
-        self.data[offset + 2..offset + 2 + key_len].to_vec()
+        let ts_len = if self.with_ts { SIZEOF_U64 } else { 0 };
+        self.data[offset + 2..offset + 2 + key_len + ts_len].to_vec()
+    }
+
+    /// The approximate size of the block once encoded.
+    pub fn estimated_size(&self) -> usize {
+        self.data.len()
+            + (self.offsets.len() + 1) * SIZEOF_U16
+            + self.sparse_index_size(self.offsets.len() - 1)
     }
 
     /// Check if there is no key-value pair in the block.
@@ -72,10 +132,20 @@ impl BlockBuilder {
     /// Finalize the block.
     pub fn build(&mut self) -> Block {
         self.offsets.pop();
-        self.offsets.push(self.offsets.len() as u16);
+        let num_entries = self.offsets.len();
+        let sparse_index = match self.sparse_index_interval {
+            Some(interval) if !self.data.is_empty() => (0..num_entries)
+                .step_by(interval)
+                .map(|idx| idx as u16)
+                .collect(),
+            _ => Vec::new(),
+        };
+        self.offsets.push(num_entries as u16);
         Block {
             data: std::mem::take(&mut self.data),
             offsets: std::mem::take(&mut self.offsets),
+            has_ts: self.with_ts,
+            sparse_index,
         }
     }
 }
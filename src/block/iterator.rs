@@ -12,6 +12,8 @@ pub struct BlockIterator {
     key: KeyVec,
     /// the current value range in the block.data, corresponds to the current key
     value_range: (usize, usize),
+    /// The timestamp of the current entry, the timestamp suffix of its key.
+    ts: u64,
     /// Current index of the key-value pair, should be in range of [0, num_of_elements)
     idx: usize,
     /// The first key in the block
@@ -24,11 +26,22 @@ impl BlockIterator {
             block,
             key: KeyVec::new(),
             value_range: (0, 0),
+            ts: 0,
             idx: 0,
             first_key: KeyVec::new(),
         }
     }
 
+    /// Creates an iterator that is never valid, e.g., for an SST without data blocks.
+    pub(crate) fn create_empty() -> Self {
+        Self::new(Arc::new(Block {
+            data: Vec::new(),
+            offsets: Vec::new(),
+            has_ts: false,
+            sparse_index: Vec::new(),
+        }))
+    }
+
     /// Creates a block iterator and seek to the first entry.
     pub fn create_and_seek_to_first(block: Arc<Block>) -> Self {
         let mut block_iterator = BlockIterator::new(block);
@@ -43,16 +56,39 @@ impl BlockIterator {
         block_iterator
     }
 
+    /// Creates a block iterator and seek to the last entry.
+    pub fn create_and_seek_to_last(block: Arc<Block>) -> Self {
+        let mut block_iterator = BlockIterator::create_and_seek_to_first(block);
+        block_iterator.seek_to_last();
+        block_iterator
+    }
+
+    /// The block being iterated.
+    pub(crate) fn block(&self) -> &Arc<Block> {
+        &self.block
+    }
+
     /// Returns the key of the current entry.
     pub fn key(&self) -> KeySlice {
         self.key.as_key_slice()
     }
 
+    /// Returns the timestamp of the current entry: the timestamp suffix of its key in a block with
+    /// timestamps, and `key().ts()` otherwise.
+    pub fn ts(&self) -> u64 {
+        self.ts
+    }
+
     /// Returns the value of the current entry.
     pub fn value(&self) -> &[u8] {
         &self.block.data[self.value_range.0..self.value_range.1]
     }
 
+    /// Returns true if the current entry is a delete tombstone, i.e., has an empty value.
+    pub fn is_tombstone(&self) -> bool {
+        self.value_range.0 == self.value_range.1
+    }
+
     /// Returns true if the iterator is valid.
     /// Note: You may want to make use of `key`
     pub fn is_valid(&self) -> bool {
@@ -61,76 +97,100 @@ impl BlockIterator {
 
     /// Seeks to the first key in the block.
     pub fn seek_to_first(&mut self) {
-        let block = self.block.clone();
-        let key_len = u16::from_be_bytes([block.data[0], block.data[1]]) as usize;
-        let key = KeyVec::from_vec(Vec::from(&block.data[2..2 + key_len]));
-        let value_len =
-            u16::from_be_bytes([block.data[2 + key_len], block.data[2 + key_len + 1]]) as usize;
-        self.key = key.clone();
-        self.value_range = (2 + key_len + 2, 2 + key_len + 2 + value_len);
-        self.idx = 1;
-        self.first_key = key;
-    }
-
-    /// Move to the next key in the block.
+        self.seek_to_idx(0);
+        self.first_key = self.key.clone();
+    }
+
+    /// Move to the next key in the block. Does nothing if the iterator is invalid.
     pub fn next(&mut self) {
+        if !self.is_valid() {
+            return;
+        }
         if self.idx == self.block.offsets.len() - 1 {
             self.key = KeyVec::new();
             return;
         }
-        let block = self.block.clone();
-        let offset = block.offsets[self.idx] as usize;
-        let key_len = u16::from_be_bytes([block.data[offset], block.data[offset + 1]]) as usize;
-        let key = KeySlice::from_slice(&block.data[(offset + 2)..(offset + 2 + key_len)]);
-        let value_len = u16::from_be_bytes([
-            block.data[offset + 2 + key_len],
-            block.data[offset + 2 + key_len + 1],
-        ]) as usize;
-
-        self.key.set_from_slice(key);
-        self.value_range = (
-            offset + 2 + key_len + 2,
-            offset + 2 + key_len + 2 + value_len,
-        );
-        self.idx += 1;
+        self.seek_to_idx(self.idx);
+    }
+
+    /// Move `n` entries forward without decoding the entries in between. The iterator becomes
+    /// invalid when moving past the last entry. Does nothing if the iterator is invalid.
+    pub fn advance_by(&mut self, n: usize) {
+        if !self.is_valid() || n == 0 {
+            return;
+        }
+        // `idx` is the index of the entry after the current one.
+        let target = self.idx - 1 + n;
+        if target >= self.block.offsets.len() - 1 {
+            self.key = KeyVec::new();
+            return;
+        }
+        self.seek_to_idx(target);
+    }
+
+    /// The number of entries after the current one, 0 if the iterator is invalid.
+    pub fn remaining(&self) -> usize {
+        if !self.is_valid() {
+            return 0;
+        }
+        self.block.offsets.len() - 1 - self.idx
+    }
+
+    /// Seeks to the last key in the block.
+    pub fn seek_to_last(&mut self) {
+        self.seek_to_idx(self.block.offsets.len() - 2);
+    }
+
+    /// Move to the previous key in the block, which must be called on a valid iterator. The
+    /// iterator becomes invalid when moving before the first key.
+    pub fn prev(&mut self) {
+        if !self.is_valid() {
+            return;
+        }
+        if self.idx <= 1 {
+            self.key = KeyVec::new();
+            return;
+        }
+        self.seek_to_idx(self.idx - 2);
+    }
+
+    /// Seek to the `idx`-th entry of the block.
+    fn seek_to_idx(&mut self, idx: usize) {
+        let entry = self.block.entry_at(self.block.offsets[idx] as usize);
+        self.key.set_from_slice(KeySlice::from_slice(
+            &self.block.data[entry.key.0..entry.key.1],
+        ));
+        self.ts = entry.ts;
+        self.value_range = entry.value;
+        self.idx = idx + 1;
     }
 
     /// Seek to the first key that >= `key`.
     /// Note: You should assume the key-value pairs in the block are sorted when being added by
     /// callers.
     pub fn seek_to_key(&mut self, key: KeySlice) {
-        let block = self.block.clone();
-        let mut final_idx = 0;
-        let mut final_key = KeyVec::new();
-        let mut final_key_len = 0;
-        let mut final_offset = 0;
-        for (i, offset) in block.offsets.iter().enumerate() {
-            if i == block.offsets.len() - 1 {
-                // invalid the iter
-                self.key = KeyVec::new();
+        for idx in self.seek_start(key)..self.block.offsets.len() - 1 {
+            if self.key_at(idx) >= key {
+                self.seek_to_idx(idx);
                 return;
             }
-            let offset = *offset as usize;
-            let key_len = u16::from_be_bytes([block.data[offset], block.data[offset + 1]]) as usize;
-            let iter_key = KeySlice::from_slice(&block.data[(offset + 2)..(offset + 2 + key_len)]);
-            if iter_key >= key {
-                final_key.set_from_slice(iter_key);
-                final_idx = i;
-                final_key_len = key_len;
-                final_offset = offset;
-                break;
-            }
         }
-        let final_value_len = u16::from_be_bytes([
-            block.data[final_offset + 2 + final_key_len],
-            block.data[final_offset + 2 + final_key_len + 1],
-        ]) as usize;
-
-        self.key = final_key;
-        self.value_range = (
-            final_offset + 2 + final_key_len + 2,
-            final_offset + 2 + final_key_len + 2 + final_value_len,
-        );
-        self.idx = final_idx;
+        // invalid the iter
+        self.key = KeyVec::new();
+    }
+
+    /// The key of the `idx`-th entry of the block.
+    fn key_at(&self, idx: usize) -> KeySlice<'_> {
+        let entry = self.block.entry_at(self.block.offsets[idx] as usize);
+        KeySlice::from_slice(&self.block.data[entry.key.0..entry.key.1])
+    }
+
+    /// The entry a seek to `key` scans from: the last entry of the sparse index before `key`, or
+    /// the first entry if there is none.
+    fn seek_start(&self, key: KeySlice) -> usize {
+        let sparse_index = &self.block.sparse_index;
+        let pos = sparse_index.partition_point(|&idx| self.key_at(idx as usize) < key);
+        pos.checked_sub(1)
+            .map_or(0, |pos| sparse_index[pos] as usize)
     }
 }
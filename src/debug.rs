@@ -2,6 +2,9 @@ use crate::lsm_storage::{LsmStorageInner, MiniLsm};
 
 impl LsmStorageInner {
     pub fn dump_structure(&self) {
+        let bloom_stats = self.bloom_stats();
+        let meta_memory_usage = self.meta_memory_usage();
+        let read_metrics = self.read_metrics();
         let snapshot = self.state.read();
         if !snapshot.l0_sstables.is_empty() {
             println!(
@@ -13,6 +16,26 @@ impl LsmStorageInner {
         for (level, files) in &snapshot.levels {
             println!("L{level} ({}): {:?}", files.len(), files);
         }
+        println!(
+            "bloom: {} checks, {} negatives, {} false positives, {} bytes",
+            bloom_stats.checks,
+            bloom_stats.negatives,
+            bloom_stats.false_positives,
+            bloom_stats.memory_usage
+        );
+        println!(
+            "reads: {} gets probing {} tables, {} scans merging {} tables, max read amp {}",
+            read_metrics.gets,
+            read_metrics.get_probes,
+            read_metrics.scans,
+            read_metrics.scan_probes,
+            read_metrics.max_read_amp
+        );
+        println!(
+            "memory: {} bytes of SST metadata, {} cached blocks",
+            meta_memory_usage,
+            self.block_cache.entry_count()
+        );
     }
 }
 
@@ -1,7 +1,15 @@
+pub mod bounded_iterator;
+pub mod boxed_iterator;
 pub mod concat_iterator;
+pub mod instrumented_iterator;
+pub mod loser_tree_iterator;
 pub mod merge_iterator;
+pub mod mvcc_iterator;
+pub mod prefix_iterator;
 pub mod two_merge_iterator;
 
+use crate::key::KeySlice;
+
 pub trait StorageIterator {
     type KeyType<'a>: PartialEq + Eq + PartialOrd + Ord
     where
@@ -13,14 +21,66 @@ pub trait StorageIterator {
     /// Get the current key.
     fn key(&self) -> Self::KeyType<'_>;
 
+    /// Whether the current entry is a delete tombstone, i.e., has an empty value. Iterators that
+    /// may skip values override this, so that deletes are still detected without them.
+    fn is_tombstone(&self) -> bool {
+        self.value().is_empty()
+    }
+
     /// Check if the current iterator is valid.
     fn is_valid(&self) -> bool;
 
+    /// Get the current key without moving the iterator, or `None` if the iterator is not valid.
+    fn peek_key(&self) -> Option<Self::KeyType<'_>> {
+        if self.is_valid() {
+            Some(self.key())
+        } else {
+            None
+        }
+    }
+
     /// Move to the next position.
     fn next(&mut self) -> anyhow::Result<()>;
 
+    /// Move `n` positions forward, stopping early if the iterator is exhausted. Iterators that can
+    /// skip entries without decoding each of them override this.
+    fn advance_by(&mut self, n: usize) -> anyhow::Result<()> {
+        for _ in 0..n {
+            if !self.is_valid() {
+                break;
+            }
+            self.next()?;
+        }
+        Ok(())
+    }
+
     /// Number of underlying active iterators for this iterator.
     fn num_active_iterators(&self) -> usize {
         1
     }
+
+    /// Move to the end and return the number of entries from the current one on.
+    fn count(mut self) -> anyhow::Result<usize>
+    where
+        Self: Sized,
+    {
+        let mut count = 0;
+        while self.is_valid() {
+            count += 1;
+            self.next()?;
+        }
+        Ok(count)
+    }
+
+    /// An upper bound of the number of remaining entries, or `None` if it cannot be computed
+    /// without moving the iterator.
+    fn remaining_upper_bound(&self) -> Option<usize> {
+        None
+    }
+
+    /// Move to the first entry with a key >= `key`, which may be before the current one. Iterators
+    /// that cannot seek return an error.
+    fn seek_to_key(&mut self, _key: KeySlice) -> anyhow::Result<()> {
+        anyhow::bail!("seek_to_key is not supported by this iterator")
+    }
 }
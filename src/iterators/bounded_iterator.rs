@@ -0,0 +1,113 @@
+use std::ops::Bound;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::StorageIterator;
+use crate::key::KeySlice;
+
+/// Restricts an iterator to the keys within `lower` and `upper`. The entries before the lower
+/// bound are skipped on creation, and the iterator becomes invalid once the key passes the upper
+/// bound. Bounds are user keys, compared against the user key of each entry, so that every version
+/// of a key is either in or out of the range.
+pub struct BoundedIterator<I: StorageIterator> {
+    inner: I,
+    lower: Bound<Bytes>,
+    upper: Bound<Bytes>,
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> BoundedIterator<I> {
+    pub fn new(inner: I, lower: Bound<Bytes>, upper: Bound<Bytes>) -> Result<Self> {
+        let mut iter = Self {
+            inner,
+            lower,
+            upper,
+        };
+        while iter.inner.is_valid() && !iter.above_lower(iter.inner.key().key_ref()) {
+            iter.inner.next()?;
+        }
+        Ok(iter)
+    }
+
+    fn above_lower(&self, key: &[u8]) -> bool {
+        match &self.lower {
+            Bound::Unbounded => true,
+            Bound::Included(lower) => key >= lower,
+            Bound::Excluded(lower) => key > lower,
+        }
+    }
+
+    fn below_upper(&self, key: &[u8]) -> bool {
+        match &self.upper {
+            Bound::Unbounded => true,
+            Bound::Included(upper) => key <= upper,
+            Bound::Excluded(upper) => key < upper,
+        }
+    }
+
+    /// The lower bound the iterator was created with.
+    pub fn lower(&self) -> Bound<&[u8]> {
+        self.lower.as_ref().map(|lower| &lower[..])
+    }
+
+    /// The upper bound the iterator stops at.
+    pub fn upper(&self) -> Bound<&[u8]> {
+        self.upper.as_ref().map(|upper| &upper[..])
+    }
+
+    /// Narrow the upper bound to `upper` if it is tighter than the current one, e.g., for a nested
+    /// scan of part of the range. A looser bound is ignored.
+    pub fn tighten_upper(&mut self, upper: Bound<Bytes>) {
+        // An upper bound as the key it stops at and whether it includes the key, `None` if unbounded.
+        let position = |bound: &Bound<Bytes>| match bound {
+            Bound::Included(key) => Some((key.clone(), true)),
+            Bound::Excluded(key) => Some((key.clone(), false)),
+            Bound::Unbounded => None,
+        };
+        let tighter = match (position(&upper), position(&self.upper)) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(new), Some(old)) => new < old,
+        };
+        if tighter {
+            self.upper = upper;
+        }
+    }
+
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIterator
+    for BoundedIterator<I>
+{
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn key(&self) -> Self::KeyType<'_> {
+        self.inner.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.inner.value()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.inner.is_tombstone()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.inner.is_valid() && self.below_upper(self.inner.key().key_ref())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if !self.is_valid() {
+            return Ok(());
+        }
+        self.inner.next()
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.inner.num_active_iterators()
+    }
+}
@@ -0,0 +1,118 @@
+use anyhow::Result;
+
+use super::StorageIterator;
+use crate::key::KeySlice;
+
+/// The object-safe part of `StorageIterator` for iterators over `KeySlice` keys, which
+/// `StorageIterator` itself is not because of its generic key type. Every such iterator
+/// implements it.
+pub trait DynStorageIterator {
+    fn key(&self) -> KeySlice<'_>;
+
+    fn value(&self) -> &[u8];
+
+    fn is_tombstone(&self) -> bool;
+
+    fn is_valid(&self) -> bool;
+
+    fn next(&mut self) -> Result<()>;
+
+    fn advance_by(&mut self, n: usize) -> Result<()>;
+
+    fn num_active_iterators(&self) -> usize;
+
+    fn remaining_upper_bound(&self) -> Option<usize>;
+
+    fn seek_to_key(&mut self, key: KeySlice) -> Result<()>;
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> DynStorageIterator for I {
+    fn key(&self) -> KeySlice<'_> {
+        StorageIterator::key(self)
+    }
+
+    fn value(&self) -> &[u8] {
+        StorageIterator::value(self)
+    }
+
+    fn is_tombstone(&self) -> bool {
+        StorageIterator::is_tombstone(self)
+    }
+
+    fn is_valid(&self) -> bool {
+        StorageIterator::is_valid(self)
+    }
+
+    fn next(&mut self) -> Result<()> {
+        StorageIterator::next(self)
+    }
+
+    fn advance_by(&mut self, n: usize) -> Result<()> {
+        StorageIterator::advance_by(self, n)
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        StorageIterator::num_active_iterators(self)
+    }
+
+    fn remaining_upper_bound(&self) -> Option<usize> {
+        StorageIterator::remaining_upper_bound(self)
+    }
+
+    fn seek_to_key(&mut self, key: KeySlice<'_>) -> Result<()> {
+        StorageIterator::seek_to_key(self, key)
+    }
+}
+
+/// An iterator of any type behind a trait object, so that iterators of different types can be
+/// merged by one `MergeIterator<BoxedStorageIterator>`, and nested iterators do not show up in
+/// the type. Each call costs a virtual call.
+pub struct BoxedStorageIterator(Box<dyn DynStorageIterator + Send>);
+
+impl BoxedStorageIterator {
+    pub fn new(
+        iter: impl 'static + Send + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
+    ) -> Self {
+        Self(Box::new(iter))
+    }
+}
+
+impl StorageIterator for BoxedStorageIterator {
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn key(&self) -> Self::KeyType<'_> {
+        self.0.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.0.value()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.0.is_tombstone()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.0.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.0.next()
+    }
+
+    fn advance_by(&mut self, n: usize) -> Result<()> {
+        self.0.advance_by(n)
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.0.num_active_iterators()
+    }
+
+    fn remaining_upper_bound(&self) -> Option<usize> {
+        self.0.remaining_upper_bound()
+    }
+
+    fn seek_to_key(&mut self, key: KeySlice<'_>) -> Result<()> {
+        self.0.seek_to_key(key)
+    }
+}
@@ -1,6 +1,3 @@
-#![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
-#![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
-
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -20,12 +17,71 @@ pub struct SstConcatIterator {
 }
 
 impl SstConcatIterator {
+    fn check_sst_valid(sstables: &[Arc<SsTable>]) {
+        debug_assert!(
+            sstables
+                .windows(2)
+                .all(|w| w[0].last_key() < w[1].first_key()),
+            "SSTs of a concat iterator must be sorted and must not overlap"
+        );
+    }
+
     pub fn create_and_seek_to_first(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
-        unimplemented!()
+        Self::check_sst_valid(&sstables);
+        let Some(first) = sstables.first() else {
+            return Ok(Self {
+                current: None,
+                next_sst_idx: 0,
+                sstables,
+            });
+        };
+        let mut iter = Self {
+            current: Some(SsTableIterator::create_and_seek_to_first(first.clone())?),
+            next_sst_idx: 1,
+            sstables,
+        };
+        iter.move_until_valid()?;
+        Ok(iter)
     }
 
+    /// Create an iterator that seeks to the first key >= `key`. Only the SST that may contain the
+    /// key, found by binary search over the first keys, is opened.
     pub fn create_and_seek_to_key(sstables: Vec<Arc<SsTable>>, key: KeySlice) -> Result<Self> {
-        unimplemented!()
+        Self::check_sst_valid(&sstables);
+        let idx = sstables
+            .partition_point(|table| table.first_key().as_key_slice() <= key)
+            .saturating_sub(1);
+        let Some(table) = sstables.get(idx) else {
+            return Ok(Self {
+                current: None,
+                next_sst_idx: 0,
+                sstables,
+            });
+        };
+        let mut iter = Self {
+            current: Some(SsTableIterator::create_and_seek_to_key(table.clone(), key)?),
+            next_sst_idx: idx + 1,
+            sstables,
+        };
+        iter.move_until_valid()?;
+        Ok(iter)
+    }
+
+    /// Open the following SSTs until the current iterator is valid or all SSTs are exhausted.
+    fn move_until_valid(&mut self) -> Result<()> {
+        while let Some(iter) = self.current.as_ref() {
+            if iter.is_valid() {
+                break;
+            }
+            match self.sstables.get(self.next_sst_idx) {
+                Some(table) => {
+                    self.current = Some(SsTableIterator::create_and_seek_to_first(table.clone())?);
+                    self.next_sst_idx += 1;
+                }
+                None => self.current = None,
+            }
+        }
+        Ok(())
     }
 }
 
@@ -33,22 +89,46 @@ impl StorageIterator for SstConcatIterator {
     type KeyType<'a> = KeySlice<'a>;
 
     fn key(&self) -> KeySlice {
-        unimplemented!()
+        self.current.as_ref().unwrap().key()
     }
 
     fn value(&self) -> &[u8] {
-        unimplemented!()
+        self.current.as_ref().unwrap().value()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.current.as_ref().unwrap().is_tombstone()
     }
 
     fn is_valid(&self) -> bool {
-        unimplemented!()
+        self.current.as_ref().is_some_and(|iter| iter.is_valid())
     }
 
     fn next(&mut self) -> Result<()> {
-        unimplemented!()
+        let Some(current) = self.current.as_mut() else {
+            return Ok(());
+        };
+        current.next()?;
+        self.move_until_valid()
     }
 
     fn num_active_iterators(&self) -> usize {
         1
     }
+
+    fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
+        *self = Self::create_and_seek_to_key(std::mem::take(&mut self.sstables), key)?;
+        Ok(())
+    }
+
+    fn remaining_upper_bound(&self) -> Option<usize> {
+        let Some(current) = &self.current else {
+            return Some(0);
+        };
+        let following = self.sstables[self.next_sst_idx..]
+            .iter()
+            .map(|table| table.properties().num_entries as usize)
+            .sum::<usize>();
+        Some(current.remaining_upper_bound()? + following)
+    }
 }
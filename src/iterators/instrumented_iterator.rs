@@ -0,0 +1,173 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use super::StorageIterator;
+use crate::block::Block;
+use crate::key::KeySlice;
+
+/// Statistics on the work done by a scan, to tell whether a slow scan read 10 blocks or 10,000.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IterStats {
+    /// Number of entries the scan returned and moved past with `next`.
+    pub entries_yielded: u64,
+    /// Number of entries read from the memtables and the SSTs that the scan did not yield, i.e.,
+    /// delete tombstones and older versions of keys.
+    pub entries_skipped: u64,
+    /// Number of SST blocks the scan loaded, from the block cache or the disk.
+    pub blocks_read: u64,
+    /// Size of those blocks in bytes, as decoded.
+    pub bytes_read: u64,
+    /// Number of seeks into the SSTs.
+    pub seek_count: u64,
+    /// Number of memtables and SSTs holding entries of the scan when it was created, each of which
+    /// the scan merges.
+    pub read_amplification: usize,
+}
+
+#[derive(Default)]
+struct IterCounters {
+    entries_yielded: AtomicU64,
+    entries_read: AtomicU64,
+    blocks_read: AtomicU64,
+    bytes_read: AtomicU64,
+    seek_count: AtomicU64,
+    read_amplification: AtomicUsize,
+}
+
+/// A handle to the counters of a scan, shared by the iterators doing its work. Cloning it is cheap,
+/// and the clones update the same counters.
+#[derive(Clone, Default)]
+pub struct IterStatsHandle(Arc<IterCounters>);
+
+impl IterStatsHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Account for a block loaded by an SST iterator.
+    pub(crate) fn record_block(&self, block: &Block) {
+        self.0.blocks_read.fetch_add(1, Ordering::Relaxed);
+        let size = block.data.len() + std::mem::size_of_val(&block.offsets[..]);
+        self.0.bytes_read.fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    /// Account for a seek into an SST.
+    pub(crate) fn record_seek(&self) {
+        self.0.seek_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_read_amplification(&self, read_amplification: usize) {
+        self.0
+            .read_amplification
+            .store(read_amplification, Ordering::Relaxed);
+    }
+
+    /// Take a snapshot of the counters.
+    pub fn stats(&self) -> IterStats {
+        let entries_yielded = self.0.entries_yielded.load(Ordering::Relaxed);
+        IterStats {
+            entries_yielded,
+            entries_skipped: self
+                .0
+                .entries_read
+                .load(Ordering::Relaxed)
+                .saturating_sub(entries_yielded),
+            blocks_read: self.0.blocks_read.load(Ordering::Relaxed),
+            bytes_read: self.0.bytes_read.load(Ordering::Relaxed),
+            seek_count: self.0.seek_count.load(Ordering::Relaxed),
+            read_amplification: self.0.read_amplification.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counts the entries an iterator moves past into an `IterStatsHandle`. Wrapping the iterator
+/// returned by a scan counts the yielded entries, and wrapping the memtable and SST iterators the
+/// scan merges, with `new_sources`, counts the entries read, so that the difference is what the
+/// scan skipped.
+pub struct InstrumentedIterator<I: StorageIterator> {
+    inner: I,
+    stats: IterStatsHandle,
+    /// Whether the entries are counted as read by the scan rather than yielded by it.
+    source: bool,
+}
+
+impl<I: StorageIterator> InstrumentedIterator<I> {
+    pub fn new(inner: I, stats: IterStatsHandle) -> Self {
+        Self {
+            inner,
+            stats,
+            source: false,
+        }
+    }
+
+    /// Wrap the memtable or SST iterators merged by a scan.
+    pub(crate) fn new_sources(iters: Vec<Box<I>>, stats: &IterStatsHandle) -> Vec<Box<Self>> {
+        iters
+            .into_iter()
+            .map(|inner| {
+                Box::new(Self {
+                    inner: *inner,
+                    stats: stats.clone(),
+                    source: true,
+                })
+            })
+            .collect()
+    }
+
+    pub fn stats(&self) -> &IterStatsHandle {
+        &self.stats
+    }
+
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+}
+
+impl<I: StorageIterator> StorageIterator for InstrumentedIterator<I> {
+    type KeyType<'a>
+        = I::KeyType<'a>
+    where
+        Self: 'a;
+
+    fn key(&self) -> Self::KeyType<'_> {
+        self.inner.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.inner.value()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.inner.is_tombstone()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.inner.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if self.inner.is_valid() {
+            let counter = if self.source {
+                &self.stats.0.entries_read
+            } else {
+                &self.stats.0.entries_yielded
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        self.inner.next()
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.inner.num_active_iterators()
+    }
+
+    fn remaining_upper_bound(&self) -> Option<usize> {
+        self.inner.remaining_upper_bound()
+    }
+
+    fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
+        self.inner.seek_to_key(key)
+    }
+}
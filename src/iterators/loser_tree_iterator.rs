@@ -0,0 +1,138 @@
+use anyhow::{bail, Result};
+
+use super::StorageIterator;
+use crate::key::{KeySlice, KeyVec};
+
+/// Merge multiple iterators of the same type with a loser tree (tournament tree), which takes
+/// `log2(n)` comparisons per `next` along a single leaf-to-root path and does not reshuffle a heap.
+/// It yields the same entries as `MergeIterator::create`: if the same key occurs in several
+/// iterators, the one with the smaller index wins. Prefer it over `MergeIterator` for wide merges,
+/// such as those of tiered compaction.
+pub struct LoserTreeIterator<I: StorageIterator> {
+    iters: Vec<Box<I>>,
+    /// `tree[0]` is the index of the winner, the iterator holding the current entry. For the other
+    /// nodes, `tree[node]` is the loser of the match at that node. The children of `node` are
+    /// `2 * node` and `2 * node + 1`, and iterator `i` is the leaf `iters.len() + i`.
+    tree: Vec<usize>,
+    /// The key of the entry before the current one, reused across calls to skip the duplicates.
+    prev_key: KeyVec,
+    /// The error that failed the merge, returned again by every later call to `next`.
+    poisoned: Option<String>,
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> LoserTreeIterator<I> {
+    pub fn create(iters: Vec<Box<I>>) -> Self {
+        let mut iter = Self {
+            tree: vec![0; iters.len().max(1)],
+            iters,
+            prev_key: KeyVec::new(),
+            poisoned: None,
+        };
+        if !iter.iters.is_empty() {
+            iter.tree[0] = iter.build(1);
+        }
+        iter
+    }
+
+    /// Whether iterator `a` wins over iterator `b`: exhausted iterators lose, and smaller keys win,
+    /// with ties going to the smaller index.
+    fn beats(&self, a: usize, b: usize) -> bool {
+        let (iter_a, iter_b) = (&self.iters[a], &self.iters[b]);
+        match (iter_a.is_valid(), iter_b.is_valid()) {
+            (true, true) => (iter_a.key(), a) < (iter_b.key(), b),
+            (valid_a, valid_b) => !valid_b && (valid_a || a < b),
+        }
+    }
+
+    /// Play the matches of the subtree rooted at `node`, and return its winner.
+    fn build(&mut self, node: usize) -> usize {
+        let n = self.iters.len();
+        if node >= n {
+            return node - n;
+        }
+        let left = self.build(2 * node);
+        let right = self.build(2 * node + 1);
+        let (winner, loser) = if self.beats(left, right) {
+            (left, right)
+        } else {
+            (right, left)
+        };
+        self.tree[node] = loser;
+        winner
+    }
+
+    /// Replay the matches from the leaf of iterator `idx`, which moved, up to the root.
+    fn replay(&mut self, idx: usize) {
+        let mut winner = idx;
+        let mut node = (idx + self.iters.len()) / 2;
+        while node > 0 {
+            if self.beats(self.tree[node], winner) {
+                std::mem::swap(&mut self.tree[node], &mut winner);
+            }
+            node /= 2;
+        }
+        self.tree[0] = winner;
+    }
+
+    /// Move the winner to its next entry.
+    fn advance_winner(&mut self) -> Result<()> {
+        let winner = self.tree[0];
+        if let Err(e) = self.iters[winner].next() {
+            let e = e.context(format!("iterator {} of the merge failed", winner));
+            self.poisoned = Some(format!("{:#}", e));
+            return Err(e);
+        }
+        self.replay(winner);
+        Ok(())
+    }
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIterator
+    for LoserTreeIterator<I>
+{
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn key(&self) -> Self::KeyType<'_> {
+        self.iters[self.tree[0]].key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.iters[self.tree[0]].value()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.iters[self.tree[0]].is_tombstone()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.poisoned.is_none()
+            && self
+                .iters
+                .get(self.tree[0])
+                .is_some_and(|iter| iter.is_valid())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if let Some(error) = &self.poisoned {
+            bail!("the merge failed earlier: {}", error);
+        }
+        if !self.is_valid() {
+            return Ok(());
+        }
+        self.prev_key.set_from_slice(self.iters[self.tree[0]].key());
+        self.advance_winner()?;
+        // The other iterators holding the same key now win in index order, so skip them.
+        while self.is_valid() && self.key() == self.prev_key.as_key_slice() {
+            self.advance_winner()?;
+        }
+        Ok(())
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.iters
+            .iter()
+            .filter(|iter| iter.is_valid())
+            .map(|iter| iter.num_active_iterators())
+            .sum()
+    }
+}
@@ -1,11 +1,13 @@
 use super::StorageIterator;
-use crate::key::KeySlice;
-use anyhow::Result;
+use crate::key::{KeyBytes, KeySlice};
+use anyhow::{bail, Result};
 use std::cmp;
 use std::collections::binary_heap::PeekMut;
 use std::collections::BinaryHeap;
+use std::ops::Bound;
 
-struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>);
+/// An iterator with its index, and whether the heap yields the largest key first.
+struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>, pub bool);
 
 impl<I: StorageIterator> PartialEq for HeapWrapper<I> {
     fn eq(&self, other: &Self) -> bool {
@@ -18,7 +20,13 @@ impl<I: StorageIterator> Eq for HeapWrapper<I> {}
 impl<I: StorageIterator> PartialOrd for HeapWrapper<I> {
     #[allow(clippy::non_canonical_partial_ord_impl)]
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        match self.1.key().cmp(&other.1.key()) {
+        let key_order = self.1.key().cmp(&other.1.key());
+        let key_order = if self.2 {
+            key_order.reverse()
+        } else {
+            key_order
+        };
+        match key_order {
             cmp::Ordering::Greater => Some(cmp::Ordering::Greater),
             cmp::Ordering::Less => Some(cmp::Ordering::Less),
             cmp::Ordering::Equal => self.0.partial_cmp(&other.0),
@@ -34,25 +42,154 @@ impl<I: StorageIterator> Ord for HeapWrapper<I> {
 }
 
 /// Merge multiple iterators of the same type. If the same key occurs multiple times in some
-/// iterators, prefer the one with smaller index.
+/// iterators, prefer the one with smaller index. A reverse merge iterator yields the keys in
+/// descending order, from iterators that each yield their keys in descending order.
 pub struct MergeIterator<I: StorageIterator> {
     iters: BinaryHeap<HeapWrapper<I>>,
     current: Option<HeapWrapper<I>>,
+    /// The iterators that are exhausted, kept so that `seek_to_key` can move them back.
+    exhausted: Vec<HeapWrapper<I>>,
+    /// The keys below this bound are skipped, also by `seek_to_key`.
+    lower: Bound<KeyBytes>,
+    /// The iterator becomes invalid once the key passes this bound.
+    upper: Bound<KeyBytes>,
+    /// Whether an iterator failing on `next` is dropped instead of failing the merge, and the last
+    /// error skipped this way.
+    skip_errors: bool,
+    last_error: Option<anyhow::Error>,
+    /// The error that failed the merge. Entries may have been skipped before the failure, so the
+    /// merge stays invalid and every later call to `next` fails with it.
+    poisoned: Option<String>,
 }
 
 impl<I: StorageIterator> MergeIterator<I> {
     pub fn create(iters: Vec<Box<I>>) -> Self {
+        Self::create_inner(iters, false)
+    }
+
+    /// Merge iterators that yield their keys in descending order, e.g.,
+    /// `ReverseSsTableIterator`s, into an iterator over all the keys in descending order.
+    pub fn create_reverse(iters: Vec<Box<I>>) -> Self {
+        Self::create_inner(iters, true)
+    }
+
+    /// Merge the iterators on a best-effort basis: an iterator failing on `next` is logged and
+    /// dropped, and the merge continues with the remaining ones. The last error is kept in
+    /// `last_error`.
+    pub fn create_skip_errors(iters: Vec<Box<I>>) -> Self {
+        let mut iter = Self::create(iters);
+        iter.skip_errors = true;
+        iter
+    }
+
+    /// The last error of an iterator dropped by a merge created with `create_skip_errors`.
+    pub fn last_error(&self) -> Option<&anyhow::Error> {
+        self.last_error.as_ref()
+    }
+
+    fn create_inner(iters: Vec<Box<I>>, reverse: bool) -> Self {
         let mut binary_heap = BinaryHeap::new();
+        let mut exhausted = Vec::new();
         for (id, iter) in iters.into_iter().enumerate() {
             if iter.is_valid() {
-                binary_heap.push(HeapWrapper(id, iter))
+                binary_heap.push(HeapWrapper(id, iter, reverse))
+            } else {
+                exhausted.push(HeapWrapper(id, iter, reverse))
             }
         }
         let current = binary_heap.pop();
         MergeIterator {
             iters: binary_heap,
             current,
+            exhausted,
+            lower: Bound::Unbounded,
+            upper: Bound::Unbounded,
+            skip_errors: false,
+            last_error: None,
+            poisoned: None,
+        }
+    }
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> MergeIterator<I> {
+    /// Merge the iterators, only surfacing the keys within `lower` and `upper`. Every iterator is
+    /// first advanced past the keys below `lower`, and the merged iterator becomes invalid once the
+    /// key passes `upper`.
+    pub fn create_bounded(
+        iters: Vec<Box<I>>,
+        lower: Bound<KeyBytes>,
+        upper: Bound<KeyBytes>,
+    ) -> Result<Self> {
+        let mut bounded_iters = Vec::with_capacity(iters.len());
+        for mut iter in iters {
+            Self::skip_below_lower(&mut iter, &lower)?;
+            bounded_iters.push(iter);
+        }
+        let mut iter = Self::create(bounded_iters);
+        iter.lower = lower;
+        iter.upper = upper;
+        Ok(iter)
+    }
+
+    /// Advance `iter` past the keys below `lower`.
+    fn skip_below_lower(iter: &mut I, lower: &Bound<KeyBytes>) -> Result<()> {
+        while iter.is_valid()
+            && match lower {
+                Bound::Unbounded => false,
+                Bound::Included(lower) => iter.key() < lower.as_key_slice(),
+                Bound::Excluded(lower) => iter.key() <= lower.as_key_slice(),
+            }
+        {
+            iter.next()?;
+        }
+        Ok(())
+    }
+
+    /// Move to the next entry without skipping the duplicates of the current key, which are
+    /// yielded in the order of the iterators, e.g., for the MVCC layer to pick the version to keep.
+    pub fn next_no_dedup(&mut self) -> Result<()> {
+        if let Some(error) = &self.poisoned {
+            bail!("the merge failed earlier: {}", error);
+        }
+        if !self.is_valid() {
+            return Ok(());
+        }
+        self.advance_current()
+    }
+
+    /// Advance the current iterator, and make the iterator with the smallest key the current one.
+    fn advance_current(&mut self) -> Result<()> {
+        let current = self.current.as_mut().unwrap();
+        let result = current.1.next();
+        if let Err(e) = result {
+            if !self.skip_errors {
+                let e = e.context(format!("iterator {} of the merge failed", current.0));
+                self.poisoned = Some(format!("{:#}", e));
+                return Err(e);
+            }
+            eprintln!("skipping a failed iterator in the merge: {:#}", e);
+            self.last_error = Some(e);
+            self.current = self.iters.pop();
+            return Ok(());
+        }
+
+        // If the current iterator is invalid, pop it out of the heap and select the next one.
+        if !current.1.is_valid() {
+            let next = self.iters.pop();
+            if let Some(iter) = std::mem::replace(&mut self.current, next) {
+                self.exhausted.push(iter);
+            }
+            return Ok(());
+        }
+
+        // Otherwise, compare with heap top and swap if necessary.
+        if let Some(mut inner_iter) = self.iters.peek_mut() {
+            if *current < *inner_iter {
+                std::mem::swap(&mut *inner_iter, current);
+            }
         }
+
+        Ok(())
     }
 }
 
@@ -61,25 +198,47 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
 {
     type KeyType<'a> = KeySlice<'a>;
 
+    /// The key of the current entry. The iterator must be valid; in release builds, an exhausted
+    /// merge returns an empty key.
     fn key(&self) -> KeySlice {
+        debug_assert!(self.current.is_some(), "key() called on an exhausted merge");
         match &self.current {
             Some(cur) => cur.1.key(),
             None => KeySlice::from_slice([].as_ref()),
         }
     }
 
+    /// The value of the current entry. The iterator must be valid; in release builds, an exhausted
+    /// merge returns an empty value.
     fn value(&self) -> &[u8] {
+        debug_assert!(
+            self.current.is_some(),
+            "value() called on an exhausted merge"
+        );
         match &self.current {
             Some(cur) => cur.1.value(),
             None => [].as_ref(),
         }
     }
 
-    fn is_valid(&self) -> bool {
+    fn is_tombstone(&self) -> bool {
         self.current
             .as_ref()
-            .map(|x| x.1.is_valid())
-            .unwrap_or(false)
+            .is_some_and(|cur| cur.1.is_tombstone())
+    }
+
+    fn is_valid(&self) -> bool {
+        if self.poisoned.is_some() {
+            return false;
+        }
+        let Some(current) = self.current.as_ref().filter(|x| x.1.is_valid()) else {
+            return false;
+        };
+        match &self.upper {
+            Bound::Unbounded => true,
+            Bound::Included(upper) => current.1.key() <= upper.as_key_slice(),
+            Bound::Excluded(upper) => current.1.key() < upper.as_key_slice(),
+        }
     }
 
     fn next(&mut self) -> Result<()> {
@@ -109,47 +268,48 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
         //     self.current = self.iters.pop();
         //     Ok(())
         // }
+        if let Some(error) = &self.poisoned {
+            bail!("the merge failed earlier: {}", error);
+        }
+        if !self.is_valid() {
+            return Ok(());
+        }
         let current = self.current.as_mut().unwrap();
         // Pop the item out of the heap if they have the same value.
         while let Some(mut inner_iter) = self.iters.peek_mut() {
             debug_assert!(
-                inner_iter.1.key() >= current.1.key(),
+                if current.2 {
+                    inner_iter.1.key() <= current.1.key()
+                } else {
+                    inner_iter.1.key() >= current.1.key()
+                },
                 "heap invariant violated"
             );
             if inner_iter.1.key() == current.1.key() {
                 // Case 1: an error occurred when calling `next`.
-                if let e @ Err(_) = inner_iter.1.next() {
+                if let Err(e) = inner_iter.1.next() {
+                    let id = inner_iter.0;
                     PeekMut::pop(inner_iter);
-                    return e;
+                    if !self.skip_errors {
+                        let e = e.context(format!("iterator {} of the merge failed", id));
+                        self.poisoned = Some(format!("{:#}", e));
+                        return Err(e);
+                    }
+                    eprintln!("skipping a failed iterator in the merge: {:#}", e);
+                    self.last_error = Some(e);
+                    continue;
                 }
 
                 // Case 2: iter is no longer valid.
                 if !inner_iter.1.is_valid() {
-                    PeekMut::pop(inner_iter);
+                    self.exhausted.push(PeekMut::pop(inner_iter));
                 }
             } else {
                 break;
             }
         }
 
-        current.1.next()?;
-
-        // If the current iterator is invalid, pop it out of the heap and select the next one.
-        if !current.1.is_valid() {
-            if let Some(iter) = self.iters.pop() {
-                *current = iter;
-            }
-            return Ok(());
-        }
-
-        // Otherwise, compare with heap top and swap if necessary.
-        if let Some(mut inner_iter) = self.iters.peek_mut() {
-            if *current < *inner_iter {
-                std::mem::swap(&mut *inner_iter, current);
-            }
-        }
-
-        Ok(())
+        self.advance_current()
     }
 
     fn num_active_iterators(&self) -> usize {
@@ -163,4 +323,61 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
                 .map(|x| x.1.num_active_iterators())
                 .unwrap_or(0)
     }
+
+    /// Seek every iterator of the merge to `key`, including the exhausted ones, and rebuild the
+    /// heap in place. A key below the lower bound of a merge created with `create_bounded` seeks to
+    /// the bound instead. Iterators dropped after failing are not brought back. An iterator failing
+    /// to seek fails the merge, or is dropped by a merge created with `create_skip_errors`.
+    fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
+        if let Some(error) = &self.poisoned {
+            bail!("the merge failed earlier: {}", error);
+        }
+        let lower = self.lower.clone();
+        let key = match &lower {
+            Bound::Included(lower) | Bound::Excluded(lower) if key < lower.as_key_slice() => {
+                lower.as_key_slice()
+            }
+            _ => key,
+        };
+        let iters = std::mem::take(&mut self.iters)
+            .into_vec()
+            .into_iter()
+            .chain(self.current.take())
+            .chain(std::mem::take(&mut self.exhausted))
+            .collect::<Vec<_>>();
+        for mut iter in iters {
+            let result = iter
+                .1
+                .seek_to_key(key)
+                .and_then(|()| Self::skip_below_lower(&mut iter.1, &lower));
+            if let Err(e) = result {
+                if !self.skip_errors {
+                    let e = e.context(format!("iterator {} of the merge failed", iter.0));
+                    self.poisoned = Some(format!("{:#}", e));
+                    return Err(e);
+                }
+                eprintln!("skipping a failed iterator in the merge: {:#}", e);
+                self.last_error = Some(e);
+                continue;
+            }
+            if iter.1.is_valid() {
+                self.iters.push(iter);
+            } else {
+                self.exhausted.push(iter);
+            }
+        }
+        self.current = self.iters.pop();
+        Ok(())
+    }
+
+    fn remaining_upper_bound(&self) -> Option<usize> {
+        if !self.is_valid() {
+            return Some(0);
+        }
+        self.iters
+            .iter()
+            .chain(self.current.as_ref())
+            .map(|x| x.1.remaining_upper_bound())
+            .sum()
+    }
 }
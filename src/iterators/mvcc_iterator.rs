@@ -0,0 +1,126 @@
+use anyhow::Result;
+
+use super::StorageIterator;
+use crate::key::KeySlice;
+
+/// Produces, for each user key, only the newest version visible at the snapshot `read_ts`, i.e.,
+/// the first version with `ts <= read_ts`. The inner iterator must be ordered by user key
+/// ascending and then by timestamp descending. Versions are compared by user key only, so a run of
+/// versions may span blocks, SSTs and the iterators of a merge.
+pub struct MvccIterator<I: StorageIterator> {
+    inner: I,
+    read_ts: u64,
+    /// The user key of the version produced last, whose older versions are skipped.
+    prev_key: Vec<u8>,
+}
+
+/// The iterator for user scans, which only see the latest version of each key.
+pub type LatestVersionIterator<I> = MvccIterator<I>;
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> MvccIterator<I> {
+    pub fn new(inner: I, read_ts: u64) -> Result<Self> {
+        let mut iter = Self {
+            inner,
+            read_ts,
+            prev_key: Vec::new(),
+        };
+        iter.skip_invisible(false)?;
+        Ok(iter)
+    }
+
+    /// Skip the versions newer than the snapshot and, if `skip_prev` is set, the older versions of
+    /// the previous user key.
+    fn skip_invisible(&mut self, skip_prev: bool) -> Result<()> {
+        while self.inner.is_valid() {
+            let key = self.inner.key();
+            if key.ts() <= self.read_ts && !(skip_prev && key.key_ref() == self.prev_key) {
+                break;
+            }
+            self.inner.next()?;
+        }
+        Ok(())
+    }
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIterator
+    for MvccIterator<I>
+{
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn key(&self) -> Self::KeyType<'_> {
+        self.inner.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.inner.value()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.inner.is_tombstone()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.inner.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if !self.is_valid() {
+            return Ok(());
+        }
+        self.prev_key.clear();
+        self.prev_key.extend(self.inner.key().key_ref());
+        self.inner.next()?;
+        self.skip_invisible(true)
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.inner.num_active_iterators()
+    }
+}
+
+/// Produces every version of every key, newest first within a user key, e.g., for compaction,
+/// which has to decide on each version. It makes no changes to the inner iterator, but marks the
+/// places where all versions are wanted, as opposed to a `LatestVersionIterator`.
+pub struct AllVersionsIterator<I: StorageIterator> {
+    inner: I,
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> AllVersionsIterator<I> {
+    pub fn new(inner: I) -> Self {
+        Self { inner }
+    }
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIterator
+    for AllVersionsIterator<I>
+{
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn key(&self) -> Self::KeyType<'_> {
+        self.inner.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.inner.value()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.inner.is_tombstone()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.inner.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.inner.next()
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.inner.num_active_iterators()
+    }
+
+    fn remaining_upper_bound(&self) -> Option<usize> {
+        self.inner.remaining_upper_bound()
+    }
+}
@@ -0,0 +1,61 @@
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::StorageIterator;
+use crate::key::KeySlice;
+
+/// Yields the keys starting with `prefix` from an iterator positioned at the first key >= `prefix`,
+/// and becomes invalid at the first key without it. An empty prefix matches every key.
+pub struct PrefixIterator<I: StorageIterator> {
+    inner: I,
+    prefix: Bytes,
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> PrefixIterator<I> {
+    pub fn new(inner: I, prefix: Bytes) -> Self {
+        Self { inner, prefix }
+    }
+
+    /// Seek `inner` to `prefix`, and iterate over the keys starting with it.
+    pub fn create_and_seek(mut inner: I, prefix: Bytes) -> Result<Self> {
+        inner.seek_to_key(KeySlice::from_slice(&prefix))?;
+        Ok(Self::new(inner, prefix))
+    }
+
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIterator
+    for PrefixIterator<I>
+{
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn key(&self) -> Self::KeyType<'_> {
+        self.inner.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.inner.value()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.inner.is_tombstone()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.inner.is_valid() && self.inner.key().key_ref().starts_with(&self.prefix)
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if !self.is_valid() {
+            return Ok(());
+        }
+        self.inner.next()
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.inner.num_active_iterators()
+    }
+}
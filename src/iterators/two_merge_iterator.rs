@@ -29,6 +29,8 @@ impl<
         }
         Ok(())
     }
+    /// Create a merged iterator over `a` and `b`, either of which may start out invalid. If both
+    /// start at the same key, B is moved past it.
     pub fn create(a: A, b: B) -> Result<Self> {
         let mut iter = TwoMergeIterator {
             a,
@@ -64,6 +66,14 @@ impl<
         }
     }
 
+    fn is_tombstone(&self) -> bool {
+        if self.is_current_a {
+            self.a.is_tombstone()
+        } else {
+            self.b.is_tombstone()
+        }
+    }
+
     fn is_valid(&self) -> bool {
         if self.is_current_a {
             self.a.is_valid()
@@ -73,6 +83,9 @@ impl<
     }
 
     fn next(&mut self) -> Result<()> {
+        if !self.is_valid() {
+            return Ok(());
+        }
         if self.is_current_a {
             self.a.next()?;
         } else {
@@ -84,7 +97,12 @@ impl<
 
         Ok(())
     }
+
     fn num_active_iterators(&self) -> usize {
         self.a.num_active_iterators() + self.b.num_active_iterators()
     }
+
+    fn remaining_upper_bound(&self) -> Option<usize> {
+        Some(self.a.remaining_upper_bound()? + self.b.remaining_upper_bound()?)
+    }
 }
@@ -1,3 +1,4 @@
+use std::cmp::Ordering;
 use std::fmt::Debug;
 
 use bytes::Bytes;
@@ -10,6 +11,44 @@ pub type KeySlice<'a> = Key<&'a [u8]>;
 pub type KeyVec = Key<Vec<u8>>;
 pub type KeyBytes = Key<Bytes>;
 
+/// Number of bytes of the timestamp at the end of a raw key, once timestamps are enabled.
+pub const TS_LEN: usize = std::mem::size_of::<u64>();
+
+/// Split a raw key into the user key and its trailing big-endian timestamp. A raw key too short to
+/// hold a timestamp is a user key with timestamp 0.
+pub fn split_ts(raw: &[u8]) -> (&[u8], u64) {
+    match raw.len().checked_sub(TS_LEN) {
+        Some(split) => (
+            &raw[..split],
+            u64::from_be_bytes(raw[split..].try_into().unwrap()),
+        ),
+        None => (raw, 0),
+    }
+}
+
+/// Compare two raw keys with trailing timestamps by user key ascending and then by timestamp
+/// descending, so that the newest version of a user key comes first. Comparing the raw bytes
+/// instead would order the timestamp of `a` against the user key bytes of `ab`.
+pub fn compare_with_ts(a: &[u8], b: &[u8]) -> Ordering {
+    let (key_a, ts_a) = split_ts(a);
+    let (key_b, ts_b) = split_ts(b);
+    key_a.cmp(key_b).then(ts_b.cmp(&ts_a))
+}
+
+/// The length of the common prefix of two byte strings.
+pub fn shared_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// Compare two raw keys, with `compare_with_ts` if timestamps are enabled, or bytewise otherwise.
+fn compare_raw(a: &[u8], b: &[u8]) -> Ordering {
+    if TS_ENABLED {
+        compare_with_ts(a, b)
+    } else {
+        a.cmp(b)
+    }
+}
+
 impl<T: AsRef<[u8]>> Key<T> {
     pub fn into_inner(self) -> T {
         self.0
@@ -23,6 +62,15 @@ impl<T: AsRef<[u8]>> Key<T> {
         self.0.as_ref().is_empty()
     }
 
+    /// The timestamp of the key. Always 0 until timestamps are encoded in keys in week 3.
+    pub fn ts(&self) -> u64 {
+        if TS_ENABLED {
+            split_ts(self.0.as_ref()).1
+        } else {
+            0
+        }
+    }
+
     pub fn for_testing_ts(self) -> u64 {
         0
     }
@@ -115,6 +163,29 @@ impl<'a> Key<&'a [u8]> {
         self.0
     }
 
+    /// The user key, without the timestamp. The same as `raw_ref` until timestamps are encoded in
+    /// keys in week 3.
+    pub fn key_ref(self) -> &'a [u8] {
+        if TS_ENABLED {
+            split_ts(self.0).0
+        } else {
+            self.0
+        }
+    }
+
+    /// The first `len` bytes of the user key, or the whole user key if it is shorter, e.g., the
+    /// prefix of a prefix bloom filter. It borrows from the same buffer as the key.
+    pub fn prefix(self, len: usize) -> KeySlice<'a> {
+        let key = self.key_ref();
+        Key(&key[..len.min(key.len())])
+    }
+
+    /// The length of the common prefix of the raw keys, i.e., the bytes a key can share with the
+    /// previous one under prefix compression.
+    pub fn shared_prefix_len(self, other: KeySlice) -> usize {
+        shared_prefix_len(self.0, other.0)
+    }
+
     pub fn for_testing_key_ref(self) -> &'a [u8] {
         self.0
     }
@@ -156,14 +227,16 @@ impl<T: AsRef<[u8]> + Clone> Clone for Key<T> {
 
 impl<T: AsRef<[u8]> + Copy> Copy for Key<T> {}
 
+/// Keys are ordered by `compare_raw`, which seeks in blocks, block lookups in SSTs and the heap
+/// of `MergeIterator` all go through.
 impl<T: AsRef<[u8]> + PartialOrd> PartialOrd for Key<T> {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.0.partial_cmp(&other.0)
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(compare_raw(self.0.as_ref(), other.0.as_ref()))
     }
 }
 
 impl<T: AsRef<[u8]> + Ord> Ord for Key<T> {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.0.cmp(&other.0)
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_raw(self.0.as_ref(), other.0.as_ref())
     }
 }
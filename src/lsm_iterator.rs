@@ -1,53 +1,72 @@
 use std::ops::Bound;
 
 use crate::{
-    iterators::{
-        merge_iterator::MergeIterator, two_merge_iterator::TwoMergeIterator, StorageIterator,
-    },
-    mem_table::MemTableIterator,
-    table::SsTableIterator,
+    iterators::{boxed_iterator::BoxedStorageIterator, StorageIterator},
+    key::KeySlice,
 };
 use anyhow::{bail, Result};
 use bytes::Bytes;
 
-/// Represents the internal type for an LSM iterator. This type will be changed across the tutorial for multiple times.
-type LsmIteratorInner =
-    TwoMergeIterator<MergeIterator<MemTableIterator>, MergeIterator<SsTableIterator>>;
+/// Represents the internal type for an LSM iterator. The scan assembles the merge of the memtables
+/// and the SSTs, and the type of the resulting stack is erased so that it may change with the
+/// shape of the LSM tree.
+type LsmIteratorInner = BoxedStorageIterator;
 
+/// The user-visible iterator over the storage. The inner merge iterators keep only the newest
+/// entry of each key, and this iterator skips the entries that are delete tombstones, i.e., have an
+/// empty value.
 pub struct LsmIterator {
     inner: LsmIteratorInner,
     end_bound: Bound<Bytes>,
     is_valid: bool,
+    /// Whether `value` returns an empty slice, for scans that only need the keys.
+    keys_only: bool,
 }
 
 impl LsmIterator {
-    pub(crate) fn new(iter: LsmIteratorInner, end_bound: Bound<Bytes>) -> Result<Self> {
+    pub(crate) fn new(
+        iter: impl 'static + Send + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
+        end_bound: Bound<Bytes>,
+    ) -> Result<Self> {
         let mut iter = Self {
-            is_valid: iter.is_valid(),
-            inner: iter,
+            is_valid: false,
+            inner: BoxedStorageIterator::new(iter),
             end_bound,
+            keys_only: false,
         };
-        let _ = iter.move_to_non_delete();
+        iter.update_is_valid();
+        iter.move_to_non_delete()?;
         Ok(iter)
     }
+    /// Set whether the iterator only yields keys, with `value` returning an empty slice. Deletes are
+    /// still skipped.
+    pub(crate) fn set_keys_only(&mut self, keys_only: bool) {
+        self.keys_only = keys_only;
+    }
+
     fn move_to_non_delete(&mut self) -> Result<()> {
-        while self.is_valid() && self.inner.value().is_empty() {
+        while self.is_valid() && self.inner.is_tombstone() {
             self.inner_next()?;
         }
         Ok(())
     }
     fn inner_next(&mut self) -> Result<()> {
         self.inner.next()?;
+        self.update_is_valid();
+        Ok(())
+    }
+    /// The iterator is valid while the inner iterator is and the key is within the end bound.
+    fn update_is_valid(&mut self) {
         if !self.inner.is_valid() {
             self.is_valid = false;
-            return Ok(());
+            return;
         }
-        match &self.end_bound {
-            Bound::Unbounded => {}
-            Bound::Included(upper) => self.is_valid = self.key() <= upper,
-            Bound::Excluded(upper) => self.is_valid = self.key() < upper,
-        }
-        Ok(())
+        let key = self.inner.key().raw_ref();
+        self.is_valid = match &self.end_bound {
+            Bound::Unbounded => true,
+            Bound::Included(upper) => key <= upper,
+            Bound::Excluded(upper) => key < upper,
+        };
     }
 }
 
@@ -63,10 +82,20 @@ impl StorageIterator for LsmIterator {
     }
 
     fn value(&self) -> &[u8] {
+        if self.keys_only {
+            return &[];
+        }
         self.inner.value()
     }
 
+    fn is_tombstone(&self) -> bool {
+        false
+    }
+
     fn next(&mut self) -> Result<()> {
+        if !self.is_valid() {
+            return Ok(());
+        }
         self.inner_next()?;
         self.move_to_non_delete()?;
         Ok(())
@@ -82,23 +111,24 @@ impl StorageIterator for LsmIterator {
 /// `is_valid` should return false, and `next` should always return an error.
 pub struct FusedIterator<I: StorageIterator> {
     iter: I,
-    has_errored: bool,
+    /// The error returned by the underlying iterator, repeated by every later call to `next`.
+    error: Option<String>,
 }
 
 impl<I: StorageIterator> FusedIterator<I> {
     pub fn new(iter: I) -> Self {
-        Self {
-            iter,
-            has_errored: false,
-        }
+        Self { iter, error: None }
     }
 }
 
 impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
-    type KeyType<'a> = I::KeyType<'a> where Self: 'a;
+    type KeyType<'a>
+        = I::KeyType<'a>
+    where
+        Self: 'a;
 
     fn is_valid(&self) -> bool {
-        if self.has_errored {
+        if self.error.is_some() {
             false
         } else {
             self.iter.is_valid()
@@ -106,26 +136,33 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
     }
 
     fn key(&self) -> Self::KeyType<'_> {
-        if self.has_errored || !self.iter.is_valid() {
+        if self.error.is_some() || !self.iter.is_valid() {
             panic!("invalid access to the underlying iterator")
         }
         self.iter.key()
     }
 
     fn value(&self) -> &[u8] {
-        if self.has_errored || !self.iter.is_valid() {
+        if self.error.is_some() || !self.iter.is_valid() {
             panic!("invalid access to the underlying iterator")
         }
         self.iter.value()
     }
 
+    fn is_tombstone(&self) -> bool {
+        if self.error.is_some() || !self.iter.is_valid() {
+            panic!("invalid access to the underlying iterator")
+        }
+        self.iter.is_tombstone()
+    }
+
     fn next(&mut self) -> Result<()> {
-        if self.has_errored {
-            bail!("Error occurred in the Iterator");
+        if let Some(error) = &self.error {
+            bail!("the iterator failed earlier: {}", error);
         }
         if self.is_valid() {
             if let Err(e) = self.iter.next() {
-                self.has_errored = true;
+                self.error = Some(format!("{:#}", e));
                 return Err(e);
             }
         }
@@ -3,7 +3,7 @@
 use std::collections::HashMap;
 use std::ops::Bound;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -15,6 +15,7 @@ use crate::compact::{
     CompactionController, CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
     SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, TieredCompactionController,
 };
+use crate::iterators::instrumented_iterator::{InstrumentedIterator, IterStatsHandle};
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
@@ -23,10 +24,24 @@ use crate::lsm_iterator::{FusedIterator, LsmIterator};
 use crate::manifest::Manifest;
 use crate::mem_table::MemTable;
 use crate::mvcc::LsmMvccInner;
-use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+use crate::table::bloom::BloomCounters;
+use crate::table::{BloomOptions, BloomStats, SsTable, SsTableBuilder, SsTableIterator};
 
 pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
 
+/// The default capacity of the block cache of the storage in bytes.
+pub const DEFAULT_BLOCK_CACHE_CAPACITY: u64 = 4 << 20;
+
+/// Create a block cache holding up to `capacity_bytes` bytes of decoded blocks. Each block is
+/// weighted by its `Block::memory_usage`, so the capacity bounds the memory of the cache rather
+/// than the number of blocks in it.
+pub fn block_cache_with_capacity_bytes(capacity_bytes: u64) -> BlockCache {
+    BlockCache::builder()
+        .weigher(|_, block: &Arc<Block>| block.memory_usage().try_into().unwrap_or(u32::MAX))
+        .max_capacity(capacity_bytes)
+        .build()
+}
+
 /// Represents the state of the storage engine.
 #[derive(Clone)]
 pub struct LsmStorageState {
@@ -80,6 +95,10 @@ pub struct LsmStorageOptions {
     pub compaction_options: CompactionOptions,
     pub enable_wal: bool,
     pub serializable: bool,
+    // Bloom filter options of the flushed SSTs
+    pub bloom_options: BloomOptions,
+    // Capacity of the block cache in bytes of decoded blocks
+    pub block_cache_capacity: u64,
 }
 
 impl LsmStorageOptions {
@@ -91,6 +110,8 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 50,
             serializable: false,
+            bloom_options: BloomOptions::default(),
+            block_cache_capacity: DEFAULT_BLOCK_CACHE_CAPACITY,
         }
     }
 
@@ -102,6 +123,8 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 2,
             serializable: false,
+            bloom_options: BloomOptions::default(),
+            block_cache_capacity: DEFAULT_BLOCK_CACHE_CAPACITY,
         }
     }
 
@@ -113,6 +136,8 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 2,
             serializable: false,
+            bloom_options: BloomOptions::default(),
+            block_cache_capacity: DEFAULT_BLOCK_CACHE_CAPACITY,
         }
     }
 }
@@ -151,6 +176,70 @@ pub enum CompactionFilter {
     Prefix(Bytes),
 }
 
+/// Counters of the memtables and SSTs probed by reads, after the SSTs are pruned by key range and
+/// bloom filters.
+#[derive(Debug, Default)]
+struct ReadCounters {
+    gets: AtomicU64,
+    get_probes: AtomicU64,
+    scans: AtomicU64,
+    scan_probes: AtomicU64,
+    max_read_amp: AtomicUsize,
+}
+
+impl ReadCounters {
+    fn record_get(&self, probes: usize) {
+        self.gets.fetch_add(1, Ordering::Relaxed);
+        self.get_probes.fetch_add(probes as u64, Ordering::Relaxed);
+        self.max_read_amp.fetch_max(probes, Ordering::Relaxed);
+    }
+
+    fn record_scan(&self, probes: usize) {
+        self.scans.fetch_add(1, Ordering::Relaxed);
+        self.scan_probes.fetch_add(probes as u64, Ordering::Relaxed);
+        self.max_read_amp.fetch_max(probes, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> ReadMetrics {
+        ReadMetrics {
+            gets: self.gets.load(Ordering::Relaxed),
+            get_probes: self.get_probes.load(Ordering::Relaxed),
+            scans: self.scans.load(Ordering::Relaxed),
+            scan_probes: self.scan_probes.load(Ordering::Relaxed),
+            max_read_amp: self.max_read_amp.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The read amplification of the storage: how many memtables and SSTs the reads probed, once the
+/// SSTs that cannot hold the keys are pruned by their key range and bloom filters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadMetrics {
+    /// Number of point lookups.
+    pub gets: u64,
+    /// Number of memtables and SSTs probed by the point lookups. A lookup stops probing at the
+    /// first memtable holding the key.
+    pub get_probes: u64,
+    /// Number of scans created.
+    pub scans: u64,
+    /// Number of memtables and SSTs merged by the scans.
+    pub scan_probes: u64,
+    /// The largest number of memtables and SSTs probed by a single read.
+    pub max_read_amp: usize,
+}
+
+impl ReadMetrics {
+    /// The average number of memtables and SSTs probed by a point lookup, `None` before any.
+    pub fn avg_get_read_amp(&self) -> Option<f64> {
+        (self.gets > 0).then(|| self.get_probes as f64 / self.gets as f64)
+    }
+
+    /// The average number of memtables and SSTs merged by a scan, `None` before any.
+    pub fn avg_scan_read_amp(&self) -> Option<f64> {
+        (self.scans > 0).then(|| self.scan_probes as f64 / self.scans as f64)
+    }
+}
+
 /// The storage interface of the LSM tree.
 pub(crate) struct LsmStorageInner {
     pub(crate) state: Arc<RwLock<Arc<LsmStorageState>>>,
@@ -163,6 +252,10 @@ pub(crate) struct LsmStorageInner {
     pub(crate) manifest: Option<Manifest>,
     pub(crate) mvcc: Option<LsmMvccInner>,
     pub(crate) compaction_filters: Arc<Mutex<Vec<CompactionFilter>>>,
+    /// Counters of the bloom filter checks made by point lookups on all SSTs.
+    bloom_counters: BloomCounters,
+    /// Counters of the memtables and SSTs probed by reads.
+    read_counters: ReadCounters,
 }
 
 /// A thin wrapper for `LsmStorageInner` and the user interface for MiniLSM.
@@ -243,6 +336,41 @@ impl MiniLsm {
         self.inner.scan(lower, upper)
     }
 
+    pub fn scan_keys(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.inner.scan_keys(lower, upper)
+    }
+
+    pub fn scan_with_stats(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<(
+        FusedIterator<InstrumentedIterator<LsmIterator>>,
+        IterStatsHandle,
+    )> {
+        self.inner.scan_with_stats(lower, upper)
+    }
+
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<FusedIterator<LsmIterator>> {
+        self.inner.scan_prefix(prefix)
+    }
+
+    pub fn bloom_stats(&self) -> BloomStats {
+        self.inner.bloom_stats()
+    }
+
+    pub fn meta_memory_usage(&self) -> usize {
+        self.inner.meta_memory_usage()
+    }
+
+    pub fn read_metrics(&self) -> ReadMetrics {
+        self.inner.read_metrics()
+    }
+
     /// Only call this in test cases due to race conditions
     pub fn force_flush(&self) -> Result<()> {
         if !self.inner.state.read().memtable.is_empty() {
@@ -293,13 +421,17 @@ impl LsmStorageInner {
             state: Arc::new(RwLock::new(Arc::new(state))),
             state_lock: Mutex::new(()),
             path: path.to_path_buf(),
-            block_cache: Arc::new(BlockCache::new(1024)),
+            block_cache: Arc::new(block_cache_with_capacity_bytes(
+                options.block_cache_capacity,
+            )),
             next_sst_id: AtomicUsize::new(1),
             compaction_controller,
             manifest: None,
             options: options.into(),
             mvcc: None,
             compaction_filters: Arc::new(Mutex::new(Vec::new())),
+            bloom_counters: BloomCounters::default(),
+            read_counters: ReadCounters::default(),
         };
 
         Ok(storage)
@@ -316,10 +448,19 @@ impl LsmStorageInner {
 
     /// Get a key from the storage. In day 7, this can be further optimized by using a bloom filter.
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        let mut probes = 0;
+        let value = self.get_probed(key, &mut probes);
+        self.read_counters.record_get(probes);
+        value
+    }
+
+    /// Get a key from the storage, counting the memtables and SSTs probed in `probes`.
+    fn get_probed(&self, key: &[u8], probes: &mut usize) -> Result<Option<Bytes>> {
         let snapshot = {
             let guard = self.state.read();
             Arc::clone(&guard)
         };
+        *probes += 1;
         if let Some(value) = snapshot.memtable.get(key) {
             if value.is_empty() {
                 return Ok(None);
@@ -329,6 +470,7 @@ impl LsmStorageInner {
         };
 
         for memtable in snapshot.imm_memtables.iter() {
+            *probes += 1;
             if let Some(value) = memtable.get(key) {
                 if value.is_empty() {
                     return Ok(None);
@@ -342,19 +484,25 @@ impl LsmStorageInner {
         for table in snapshot.l0_sstables.iter() {
             let table = snapshot.sstables[table].clone();
             if key_within(key, table.first_key().raw_ref(), table.last_key().raw_ref()) {
-                if let Some(bloom) = &table.bloom {
-                    if bloom.may_contain(farmhash::fingerprint32(key)) {
-                        iters.push(Box::new(SsTableIterator::create_and_seek_to_key(
-                            table,
-                            KeySlice::from_slice(key),
-                        )?));
-                    }
-                } else {
-                    iters.push(Box::new(SsTableIterator::create_and_seek_to_key(
-                        table,
-                        KeySlice::from_slice(key),
-                    )?));
+                let may_contain = table.key_may_exist(key);
+                if table.bloom.is_some() {
+                    self.bloom_counters.record_check(may_contain);
+                }
+                if !may_contain {
+                    continue;
+                }
+                *probes += 1;
+                let iter = SsTableIterator::create_and_seek_to_key(
+                    table.clone(),
+                    KeySlice::from_slice(key),
+                )?;
+                if table.bloom.is_some()
+                    && !(iter.is_valid() && iter.key() == KeySlice::from_slice(key))
+                {
+                    table.record_bloom_false_positive();
+                    self.bloom_counters.record_false_positive();
                 }
+                iters.push(Box::new(iter));
             }
         }
         let merge_iterator = MergeIterator::create(iters);
@@ -368,6 +516,33 @@ impl LsmStorageInner {
         Ok(None)
     }
 
+    /// Statistics on the bloom filter checks made by point lookups, with the memory used by the
+    /// bloom filters of the current SSTs.
+    pub fn bloom_stats(&self) -> BloomStats {
+        let snapshot = self.state.read().clone();
+        let memory_usage = snapshot
+            .sstables
+            .values()
+            .map(|table| table.bloom_stats().memory_usage)
+            .sum();
+        self.bloom_counters.stats(memory_usage)
+    }
+
+    /// The number of memtables and SSTs probed by the reads so far.
+    pub fn read_metrics(&self) -> ReadMetrics {
+        self.read_counters.stats()
+    }
+
+    /// The memory pinned by the metadata of the current SSTs, see `SsTable::meta_memory_usage`.
+    pub fn meta_memory_usage(&self) -> usize {
+        let snapshot = self.state.read().clone();
+        snapshot
+            .sstables
+            .values()
+            .map(|table| table.meta_memory_usage())
+            .sum()
+    }
+
     /// Write a batch of data into the storage. Implement in week 2 day 7.
     pub fn write_batch<T: AsRef<[u8]>>(&self, _batch: &[WriteBatchRecord<T>]) -> Result<()> {
         unimplemented!()
@@ -459,9 +634,19 @@ impl LsmStorageInner {
                 .expect("no imm memtables")
                 .clone();
         }
-        let mut builder = SsTableBuilder::new(self.options.block_size);
+        let mut builder = SsTableBuilder::new(self.options.block_size)
+            .with_bloom(self.options.bloom_options.clone());
         flush_memtable.flush(&mut builder)?;
         let sst_id = flush_memtable.id();
+        if builder.num_entries() == 0 {
+            // There is nothing to persist, so drop the memtable without creating an SST.
+            let mut guard = self.state.write();
+            let mut snapshot = guard.as_ref().clone();
+            let mem = snapshot.imm_memtables.pop().unwrap();
+            assert!(mem.id() == sst_id);
+            *guard = Arc::new(snapshot);
+            return Ok(());
+        }
         let sst = Arc::new(builder.build(
             sst_id,
             Some(self.block_cache.clone()),
@@ -492,6 +677,70 @@ impl LsmStorageInner {
         lower: Bound<&[u8]>,
         upper: Bound<&[u8]>,
     ) -> Result<FusedIterator<LsmIterator>> {
+        Ok(FusedIterator::new(
+            self.scan_inner(lower, upper, None, false, None)?,
+        ))
+    }
+
+    /// Create an iterator over the keys in a range, e.g., to count them. The values are not read
+    /// out of the blocks, and `value` returns an empty slice.
+    pub fn scan_keys(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        Ok(FusedIterator::new(
+            self.scan_inner(lower, upper, None, true, None)?,
+        ))
+    }
+
+    /// Create an iterator over a range of keys, counting the work done by the scan: the entries
+    /// yielded and skipped, the SST blocks read and the seeks into the SSTs. The returned handle
+    /// reads the counters at any time during or after the scan.
+    pub fn scan_with_stats(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<(
+        FusedIterator<InstrumentedIterator<LsmIterator>>,
+        IterStatsHandle,
+    )> {
+        let stats = IterStatsHandle::new();
+        let iter = self.scan_inner(lower, upper, None, false, Some(&stats))?;
+        let iter = InstrumentedIterator::new(iter, stats.clone());
+        Ok((FusedIterator::new(iter), stats))
+    }
+
+    /// Create an iterator over the keys starting with `prefix`. SSTs whose prefix bloom filter
+    /// rules out the prefix are skipped without being opened.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<FusedIterator<LsmIterator>> {
+        // The keys with the prefix end before the prefix with its last non-0xff byte incremented.
+        let upper = prefix.iter().rposition(|&b| b != u8::MAX).map(|idx| {
+            let mut upper = prefix[..=idx].to_vec();
+            upper[idx] += 1;
+            upper
+        });
+        let upper = match &upper {
+            Some(upper) => Bound::Excluded(&upper[..]),
+            None => Bound::Unbounded,
+        };
+        Ok(FusedIterator::new(self.scan_inner(
+            Bound::Included(prefix),
+            upper,
+            Some(prefix),
+            false,
+            None,
+        )?))
+    }
+
+    fn scan_inner(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        prefix: Option<&[u8]>,
+        keys_only: bool,
+        stats: Option<&IterStatsHandle>,
+    ) -> Result<LsmIterator> {
         let snapshot = {
             let guard = self.state.read();
             Arc::clone(&guard)
@@ -501,7 +750,6 @@ impl LsmStorageInner {
         for memtable in snapshot.imm_memtables.iter() {
             memtable_iters.push(Box::new(memtable.scan(lower, upper)));
         }
-        let mem_table_merge_iterator = MergeIterator::create(memtable_iters);
 
         // let mut mem_table_iter_vec = snapshot
         //     .imm_memtables
@@ -514,38 +762,77 @@ impl LsmStorageInner {
         let mut sstable_iter_vec = Vec::new();
         for table_id in snapshot.l0_sstables.iter() {
             let table = snapshot.sstables[table_id].clone();
+            if prefix.is_some_and(|prefix| !table.may_contain_prefix(prefix)) {
+                continue;
+            }
             if range_overlap(
                 lower,
                 upper,
                 table.first_key().raw_ref(),
                 table.last_key().raw_ref(),
             ) {
-                let iter = match lower {
-                    Bound::Unbounded => SsTableIterator::create_and_seek_to_first(table)?,
-                    Bound::Included(lower) => {
-                        SsTableIterator::create_and_seek_to_key(table, KeySlice::from_slice(lower))?
+                let mut iter = match lower {
+                    Bound::Unbounded => {
+                        let mut iter = SsTableIterator::create_and_seek_to_first(table)?;
+                        if let Some(stats) = stats {
+                            iter.set_stats(stats.clone());
+                        }
+                        iter
                     }
-                    Bound::Excluded(lower) => {
-                        let mut iter = SsTableIterator::create_and_seek_to_key(
-                            table.clone(),
-                            KeySlice::from_slice(lower),
+                    Bound::Included(seek_key) | Bound::Excluded(seek_key) => {
+                        if let Some(stats) = stats {
+                            stats.record_seek();
+                        }
+                        let mut iter = SsTableIterator::create_and_seek_to_key_with_stats(
+                            table,
+                            KeySlice::from_slice(seek_key),
+                            stats.cloned(),
                         )?;
-                        if iter.is_valid() && iter.key() == KeySlice::from_slice(lower) {
+                        if matches!(lower, Bound::Excluded(_))
+                            && iter.is_valid()
+                            && iter.key() == KeySlice::from_slice(seek_key)
+                        {
                             iter.next()?;
                         }
                         iter
                     }
                 };
+                iter.set_keys_only(keys_only);
                 sstable_iter_vec.push(Box::new(iter));
             };
         }
 
-        let sstable_merge_iterator = MergeIterator::create(sstable_iter_vec);
-        let two_merge_iterator =
-            TwoMergeIterator::create(mem_table_merge_iterator, sstable_merge_iterator)?;
-        Ok(FusedIterator::new(LsmIterator::new(
-            two_merge_iterator,
-            upper.map(Bytes::copy_from_slice),
-        )?))
+        self.read_counters
+            .record_scan(memtable_iters.len() + sstable_iter_vec.len());
+        let end_bound = upper.map(Bytes::copy_from_slice);
+        let mut iter = match stats {
+            None => LsmIterator::new(
+                TwoMergeIterator::create(
+                    MergeIterator::create(memtable_iters),
+                    MergeIterator::create(sstable_iter_vec),
+                )?,
+                end_bound,
+            )?,
+            // Count the entries read from each memtable and SST.
+            Some(stats) => {
+                let iter = LsmIterator::new(
+                    TwoMergeIterator::create(
+                        MergeIterator::create(InstrumentedIterator::new_sources(
+                            memtable_iters,
+                            stats,
+                        )),
+                        MergeIterator::create(InstrumentedIterator::new_sources(
+                            sstable_iter_vec,
+                            stats,
+                        )),
+                    )?,
+                    end_bound,
+                )?;
+                stats.set_read_amplification(iter.num_active_iterators());
+                iter
+            }
+        };
+        iter.set_keys_only(keys_only);
+        Ok(iter)
     }
 }
@@ -114,7 +114,7 @@ impl MemTable {
     /// Flush the mem-table to SSTable. Implement in week 1 day 6.
     pub fn flush(&self, builder: &mut SsTableBuilder) -> Result<()> {
         for entry in self.map.iter() {
-            builder.add(KeySlice::from_slice(&entry.key()[..]), &entry.value()[..]);
+            builder.add(KeySlice::from_slice(&entry.key()[..]), &entry.value()[..])?;
         }
         Ok(())
     }
@@ -191,6 +191,9 @@ impl StorageIterator for MemTableIterator {
     // }
 
     fn next(&mut self) -> Result<()> {
+        if !self.is_valid() {
+            return Ok(());
+        }
         let entry = self.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next()));
         self.with_mut(|x| *x.item = entry);
         Ok(())
@@ -1,18 +1,48 @@
+#[cfg(feature = "async")]
+mod async_iterator;
+mod block_index;
 pub(crate) mod bloom;
 mod builder;
+mod compression;
+mod dump;
 mod iterator;
-use self::bloom::Bloom;
-use crate::block::Block;
-use crate::key::{Key, KeyBytes, KeySlice};
+mod multi_builder;
+mod negative_cache;
+mod properties;
+mod verify;
+use self::block_index::BlockIndex;
+use self::bloom::BloomCounters;
+use self::negative_cache::NegativeCache;
+use crate::block::{Block, BlockIterator};
+use crate::iterators::StorageIterator;
+use crate::key::{shared_prefix_len, Key, KeyBytes, KeySlice};
 use crate::lsm_storage::BlockCache;
-use anyhow::Result;
-pub use builder::SsTableBuilder;
+use anyhow::{bail, Context, Result};
+#[cfg(feature = "async")]
+pub use async_iterator::AsyncSsTableIterator;
+pub use bloom::{Bloom, BloomKind, BloomOptions, BloomStats, FarmHasher, Hasher};
+#[cfg(test)]
+pub(crate) use builder::is_next_key;
+pub use builder::{SsTableBuildStats, SsTableBuilder};
 use bytes::Bytes;
 use bytes::{Buf, BufMut};
-pub use iterator::SsTableIterator;
+pub use compression::Compression;
+pub use dump::DumpFormat;
+pub use iterator::{ReverseSsTableIterator, SsTableEntries, SsTableIterator};
+pub use multi_builder::MultiSsTableBuilder;
+pub use properties::SsTableProperties;
 use std::fs::File;
-use std::path::Path;
+use std::io::Write;
+use std::ops::{Bound, Range};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+pub use verify::{VerifyReport, VerifyViolation};
+
+/// Version of the SST file format, stored in the last 4 bytes of the file.
+pub(crate) const SST_FORMAT_VERSION: u32 = 8;
+
+const SIZEOF_U64: u64 = std::mem::size_of::<u64>() as u64;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BlockMeta {
@@ -22,101 +52,727 @@ pub struct BlockMeta {
     pub first_key: KeyBytes,
     /// The last key of the data block.
     pub last_key: KeyBytes,
+    /// How the data block is compressed on the disk.
+    pub compression: Compression,
 }
 
 impl BlockMeta {
-    /// Encode block meta to a buffer.
-    /// You may add extra fields to the buffer,
-    /// in order to help keep track of `first_key` when decoding from the same buffer in the future.
+    /// Encode block meta to a buffer. The metas are prefixed with their number (u32) and the
+    /// total length of the encoded metas (u32), so that other sections can follow them.
+    ///
+    /// Each meta is encoded as the varint delta of its offset from the previous block's offset,
+    /// the first key as the varint length of the prefix shared with the previous block's last key
+    /// followed by the varint length and bytes of the rest, the last key in the same way against
+    /// its own first key, and the compression as a u8.
     pub fn encode_block_meta(block_meta: &[BlockMeta], buf: &mut Vec<u8>) {
-        // let mut count = 0;
+        let mut seg = Vec::new();
+        let mut prev: Option<&BlockMeta> = None;
         for meta_data in block_meta {
-            let mut seg = Vec::new();
-            seg.extend((meta_data.offset as u32).to_be_bytes());
+            let prev_offset = prev.map_or(0, |prev| prev.offset);
+            // Offsets that are not increasing wrap around, which the decoder rejects.
+            put_varint(&mut seg, meta_data.offset.wrapping_sub(prev_offset) as u64);
+            let prev_last_key = prev.map_or(&[][..], |prev| prev.last_key.raw_ref());
+            Self::encode_key(&mut seg, prev_last_key, meta_data.first_key.raw_ref());
+            Self::encode_key(
+                &mut seg,
+                meta_data.first_key.raw_ref(),
+                meta_data.last_key.raw_ref(),
+            );
+            seg.put_u8(meta_data.compression.to_u8());
+            prev = Some(meta_data);
+        }
+        buf.put_u32(block_meta.len() as u32);
+        buf.put_u32(seg.len() as u32);
+        buf.extend(seg);
+    }
 
-            let first_key_len = meta_data.first_key.len() as u16;
-            seg.extend(first_key_len.to_be_bytes());
-            seg.extend(meta_data.first_key.raw_ref());
+    /// The encoded size of a meta with the given offset and keys following `prev`, without the
+    /// header of the section.
+    pub(crate) fn encoded_len(
+        prev: Option<&BlockMeta>,
+        offset: usize,
+        first_key: &[u8],
+        last_key: &[u8],
+    ) -> usize {
+        let key_len = |base: &[u8], key: &[u8]| {
+            let shared = shared_prefix_len(base, key);
+            varint_len(shared as u64) + varint_len((key.len() - shared) as u64) + key.len() - shared
+        };
+        let prev_offset = prev.map_or(0, |prev| prev.offset);
+        let prev_last_key = prev.map_or(&[][..], |prev| prev.last_key.raw_ref());
+        varint_len(offset.wrapping_sub(prev_offset) as u64)
+            + key_len(prev_last_key, first_key)
+            + key_len(first_key, last_key)
+            + std::mem::size_of::<u8>()
+    }
 
-            let last_key_len = meta_data.last_key.len() as u16;
-            seg.extend(last_key_len.to_be_bytes());
-            seg.extend(meta_data.last_key.raw_ref());
+    fn encode_key(buf: &mut Vec<u8>, base: &[u8], key: &[u8]) {
+        let shared = shared_prefix_len(base, key);
+        put_varint(buf, shared as u64);
+        put_varint(buf, (key.len() - shared) as u64);
+        buf.extend(&key[shared..]);
+    }
 
-            // count += seg.len();
-            buf.extend(seg);
+    /// Decode block meta from a buffer, ignoring any data after the encoded metas. Every length is
+    /// checked against the buffer, and the metas must have strictly increasing offsets and
+    /// `first_key <= last_key`.
+    pub fn decode_block_meta(mut buf: &[u8]) -> Result<Vec<BlockMeta>> {
+        if buf.remaining() < 2 * std::mem::size_of::<u32>() {
+            bail!("truncated block meta: missing header");
         }
+        let count = buf.get_u32() as usize;
+        let len = buf.get_u32() as usize;
+        if buf.remaining() < len {
+            bail!(
+                "truncated block meta: length {} exceeds the remaining {} bytes",
+                len,
+                buf.remaining()
+            );
+        }
+        let mut buf = &buf[..len];
+        let mut block_meta: Vec<BlockMeta> = Vec::with_capacity(count.min(len));
+        for _ in 0..count {
+            let prev = block_meta.last();
+            let delta = get_varint(&mut buf).context("truncated block meta: missing offset")?;
+            let offset = usize::try_from(delta)
+                .ok()
+                .and_then(|delta| delta.checked_add(prev.map_or(0, |prev| prev.offset)))
+                .context("block meta offset overflows")?;
+            let prev_last_key = prev.map_or(&[][..], |prev| prev.last_key.raw_ref());
+            let first_key = Self::decode_key(&mut buf, prev_last_key)?;
+            let last_key = Self::decode_key(&mut buf, first_key.raw_ref())?;
+            if !buf.has_remaining() {
+                bail!("truncated block meta: missing compression");
+            }
+            let compression = Compression::from_u8(buf.get_u8())?;
+
+            if let Some(prev) = prev {
+                if offset <= prev.offset {
+                    bail!(
+                        "block meta offsets are not increasing: {} after {}",
+                        offset,
+                        prev.offset
+                    );
+                }
+            }
+            if first_key > last_key {
+                bail!(
+                    "block meta first key {:?} is larger than last key {:?}",
+                    first_key,
+                    last_key
+                );
+            }
+            block_meta.push(BlockMeta {
+                offset,
+                first_key,
+                last_key,
+                compression,
+            });
+        }
+        if buf.has_remaining() {
+            bail!(
+                "block meta has {} unexpected bytes after {} entries",
+                buf.remaining(),
+                count
+            );
+        }
+        Ok(block_meta)
     }
 
-    /// Decode block meta from a buffer.
-    pub fn decode_block_meta(mut buf: &[u8]) -> Vec<BlockMeta> {
-        let mut block_meta = Vec::new();
-        while buf.remaining() > 0 {
-            let offset = buf.get_u32();
+    /// Decode a key stored as the length of the prefix shared with `base`, and the rest.
+    fn decode_key(buf: &mut &[u8], base: &[u8]) -> Result<KeyBytes> {
+        let shared = get_varint(buf).context("truncated block meta: missing key prefix length")?;
+        let rest = get_varint(buf).context("truncated block meta: missing key length")?;
+        if shared > base.len() as u64 {
+            bail!(
+                "block meta key shares {} bytes with a key of {} bytes",
+                shared,
+                base.len()
+            );
+        }
+        if rest > buf.remaining() as u64 {
+            bail!(
+                "truncated block meta: key length {} exceeds the remaining {} bytes",
+                rest,
+                buf.remaining()
+            );
+        }
+        let (shared, rest) = (shared as usize, rest as usize);
+        let mut key = Vec::with_capacity(shared + rest);
+        key.extend(&base[..shared]);
+        key.extend(&buf[..rest]);
+        buf.advance(rest);
+        Ok(Key::from_bytes(Bytes::from(key)))
+    }
+}
 
-            let first_key_len = buf.get_u16();
-            let mut first_key = Vec::new();
-            for _ in 0..first_key_len {
-                first_key.push(buf.get_u8());
-            }
+/// Append `value` as a LEB128 varint: 7 bits per byte, least significant first, with the high bit
+/// set on every byte but the last.
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.put_u8(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
 
-            let last_key_len = buf.get_u16();
-            let mut last_key = Vec::new();
-            for _ in 0..last_key_len {
-                last_key.push(buf.get_u8());
-            }
+fn varint_len(value: u64) -> usize {
+    (64 - value.leading_zeros() as usize).div_ceil(7).max(1)
+}
 
-            let meta = BlockMeta {
-                offset: offset as usize,
-                first_key: Key::from_bytes(Bytes::from_iter(first_key)),
-                last_key: Key::from_bytes(Bytes::from_iter(last_key)),
-            };
-            block_meta.push(meta);
+fn get_varint(buf: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        if !buf.has_remaining() {
+            bail!("truncated varint");
+        }
+        let byte = buf.get_u8();
+        if shift == 63 && byte > 1 {
+            bail!("varint overflows u64");
+        }
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
         }
-        block_meta
     }
+    bail!("varint overflows u64")
+}
+
+/// The storage behind a `FileObject`.
+enum FileBackend {
+    /// A file on the disk.
+    Disk(Arc<File>),
+    /// An in-memory buffer holding the whole file.
+    Memory(Bytes),
+    /// A file on the disk opened with `O_DIRECT`, bypassing the page cache.
+    #[cfg(target_os = "linux")]
+    Direct(Arc<File>),
+    /// An in-memory buffer where every read is delayed, to simulate a slow disk in tests.
+    #[cfg(test)]
+    Slow(Bytes, std::time::Duration),
+}
+
+/// An access pattern hint for a range of a file, see `FileObject::advise`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Advice {
+    /// No particular access pattern.
+    Normal,
+    /// The range will be read sequentially, so the OS may read ahead more aggressively.
+    Sequential,
+    /// The range will be read soon.
+    WillNeed,
+    /// The range will not be read again soon, so its pages can be dropped from the page cache.
+    DontNeed,
 }
 
-/// A file object.
-pub struct FileObject(Option<File>, u64);
+/// A file object. The storage is `None` for the mock SSTs of `SsTable::create_meta_only`, and the
+/// path is `None` for in-memory files. The flag tells whether the file is removed from the disk
+/// when the object is dropped.
+pub struct FileObject(Option<FileBackend>, u64, Option<PathBuf>, AtomicBool);
 
 impl FileObject {
+    fn backend(&self) -> Result<&FileBackend> {
+        match &self.0 {
+            Some(backend) => Ok(backend),
+            None => bail!("the file has no storage, e.g., it belongs to a meta-only SST"),
+        }
+    }
+
+    /// Describe a range of the file for error messages.
+    fn describe_range(&self, offset: u64, len: u64) -> String {
+        match &self.2 {
+            Some(path) => format!("sst file {:?} offset {} len {}", path, offset, len),
+            None => format!("in-memory sst file offset {} len {}", offset, len),
+        }
+    }
+
     pub fn read(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
-        use std::os::unix::fs::FileExt;
         let mut data = vec![0; len as usize];
-        self.0
-            .as_ref()
-            .unwrap()
-            .read_exact_at(&mut data[..], offset)?;
+        self.read_into(offset, &mut data[..])?;
         Ok(data)
     }
 
+    /// Read exactly `buf.len()` bytes at `offset` into a caller-provided buffer, so that a scratch
+    /// buffer can be reused across reads.
+    pub fn read_into(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        #[cfg(test)]
+        READ_COUNT.with(|count| count.set(count.get() + 1));
+        let len = buf.len() as u64;
+        self.read_into_inner(offset, buf)
+            .with_context(|| self.describe_range(offset, len))
+    }
+
+    fn read_into_inner(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        match self.backend()? {
+            FileBackend::Disk(file) => read_exact_at(file, buf, offset)?,
+            #[cfg(target_os = "linux")]
+            FileBackend::Direct(file) => read_direct(file, buf, offset)?,
+            FileBackend::Memory(data) => {
+                let range = Self::memory_range(data, offset, buf.len() as u64)?;
+                buf.copy_from_slice(&data[range]);
+            }
+            #[cfg(test)]
+            FileBackend::Slow(data, latency) => {
+                std::thread::sleep(*latency);
+                let range = Self::memory_range(data, offset, buf.len() as u64)?;
+                buf.copy_from_slice(&data[range]);
+            }
+        }
+        Ok(())
+    }
+
+    fn memory_range(data: &Bytes, offset: u64, len: u64) -> Result<std::ops::Range<usize>> {
+        if offset + len > data.len() as u64 {
+            bail!(
+                "read out of range: offset {} len {} file size {}",
+                offset,
+                len,
+                data.len()
+            );
+        }
+        Ok(offset as usize..(offset + len) as usize)
+    }
+
+    /// Read `len` bytes at `offset` without blocking the async runtime. The positional read runs
+    /// on tokio's blocking thread pool, so this must be awaited inside a tokio runtime.
+    #[cfg(feature = "async")]
+    pub async fn read_async(&self, offset: u64, len: u64) -> Result<Bytes> {
+        self.read_async_inner(offset, len)
+            .await
+            .with_context(|| self.describe_range(offset, len))
+    }
+
+    #[cfg(feature = "async")]
+    async fn read_async_inner(&self, offset: u64, len: u64) -> Result<Bytes> {
+        let (file, direct) = match self.backend()? {
+            FileBackend::Disk(file) => (file.clone(), false),
+            #[cfg(target_os = "linux")]
+            FileBackend::Direct(file) => (file.clone(), true),
+            FileBackend::Memory(data) => {
+                return Ok(data.slice(Self::memory_range(data, offset, len)?));
+            }
+            #[cfg(test)]
+            FileBackend::Slow(data, _) => {
+                return Ok(data.slice(Self::memory_range(data, offset, len)?));
+            }
+        };
+        tokio::task::spawn_blocking(move || -> Result<Bytes> {
+            let mut data = vec![0; len as usize];
+            if direct {
+                #[cfg(target_os = "linux")]
+                read_direct(&file, &mut data[..], offset)?;
+            } else {
+                read_exact_at(&file, &mut data[..], offset)?;
+            }
+            Ok(Bytes::from(data))
+        })
+        .await?
+    }
+
+    /// Read several `(offset, len)` ranges at once. On Linux, ranges that are sorted and disjoint
+    /// are fetched with a single `preadv` spanning all of them, with the gaps in between landing in
+    /// a scratch buffer. Otherwise, each range is read separately.
+    pub fn read_vectored(&self, ranges: &[(u64, u64)]) -> Result<Vec<Vec<u8>>> {
+        let mut bufs = ranges
+            .iter()
+            .map(|(_, len)| vec![0; *len as usize])
+            .collect::<Vec<_>>();
+        #[cfg(target_os = "linux")]
+        {
+            let disjoint = ranges.windows(2).all(|w| w[0].0 + w[0].1 <= w[1].0);
+            if disjoint && !ranges.is_empty() && self.preadv(ranges, &mut bufs)? {
+                return Ok(bufs);
+            }
+        }
+        for ((offset, _), buf) in ranges.iter().zip(bufs.iter_mut()) {
+            self.read_into(*offset, buf)?;
+        }
+        Ok(bufs)
+    }
+
+    /// Issue one `preadv` for sorted, disjoint `ranges`. Returns false if the request could not be
+    /// served in a single call (too many ranges or a short read), so the caller should fall back.
+    #[cfg(target_os = "linux")]
+    fn preadv(&self, ranges: &[(u64, u64)], bufs: &mut [Vec<u8>]) -> Result<bool> {
+        use std::os::unix::io::AsRawFd;
+        const MAX_IOVECS: usize = 1024;
+
+        let Some(FileBackend::Disk(file)) = self.0.as_ref() else {
+            return Ok(false);
+        };
+
+        let gap_of = |idx: usize| ranges[idx].0 - ranges[idx - 1].0 - ranges[idx - 1].1;
+        let max_gap = (1..ranges.len()).map(gap_of).max().unwrap_or(0);
+        let mut gap = vec![0u8; max_gap as usize];
+        let mut iovecs = Vec::with_capacity(ranges.len() * 2);
+        for (idx, buf) in bufs.iter_mut().enumerate() {
+            if idx > 0 && gap_of(idx) > 0 {
+                iovecs.push(libc::iovec {
+                    iov_base: gap.as_mut_ptr() as *mut libc::c_void,
+                    iov_len: gap_of(idx) as usize,
+                });
+            }
+            iovecs.push(libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            });
+        }
+        if iovecs.len() > MAX_IOVECS {
+            return Ok(false);
+        }
+        let total = iovecs.iter().map(|iov| iov.iov_len).sum::<usize>();
+        let fd = file.as_raw_fd();
+        // SAFETY: every iovec points into a live buffer of at least `iov_len` bytes, and none of
+        // them are touched until the call returns.
+        let read = unsafe {
+            libc::preadv(
+                fd,
+                iovecs.as_ptr(),
+                iovecs.len() as libc::c_int,
+                ranges[0].0 as libc::off_t,
+            )
+        };
+        if read < 0 {
+            let end = ranges[ranges.len() - 1].0 + ranges[ranges.len() - 1].1;
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| self.describe_range(ranges[0].0, end - ranges[0].0));
+        }
+        Ok(read as usize == total)
+    }
+
+    /// Tell the OS how the range `offset..offset + len` will be accessed, with `posix_fadvise`. A
+    /// `len` of 0 extends the range to the end of the file. Does nothing on platforms without
+    /// `posix_fadvise` and for in-memory files.
+    pub fn advise(&self, offset: u64, len: u64, advice: Advice) -> Result<()> {
+        #[cfg(test)]
+        ADVICE_LOG.with(|log| log.borrow_mut().push((offset, len, advice)));
+        #[cfg(target_os = "linux")]
+        if let Some(FileBackend::Disk(file) | FileBackend::Direct(file)) = self.0.as_ref() {
+            use std::os::unix::io::AsRawFd;
+            let advice = match advice {
+                Advice::Normal => libc::POSIX_FADV_NORMAL,
+                Advice::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+                Advice::WillNeed => libc::POSIX_FADV_WILLNEED,
+                Advice::DontNeed => libc::POSIX_FADV_DONTNEED,
+            };
+            // SAFETY: `posix_fadvise` only reads its integer arguments.
+            let ret = unsafe {
+                libc::posix_fadvise(
+                    file.as_raw_fd(),
+                    offset as libc::off_t,
+                    len as libc::off_t,
+                    advice,
+                )
+            };
+            if ret != 0 {
+                return Err(std::io::Error::from_raw_os_error(ret).into());
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = (offset, len, advice);
+        Ok(())
+    }
+
     pub fn size(&self) -> u64 {
         self.1
     }
 
     /// Create a new file object (day 2) and write the file to the disk (day 4).
     pub fn create(path: &Path, data: Vec<u8>) -> Result<Self> {
-        std::fs::write(path, &data)?;
-        File::open(path)?.sync_all()?;
+        Self::create_with_options(path, data, true)
+    }
+
+    /// Create a new file object, optionally skipping the fsync. Callers that pass `sync = false`
+    /// are responsible for making the file durable later, e.g., with a single directory sync after
+    /// a bulk load. With `sync = true`, the file is created with `create_atomic`.
+    pub fn create_with_options(path: &Path, data: Vec<u8>, sync: bool) -> Result<Self> {
+        if sync {
+            return Self::create_atomic(path, data);
+        }
+        std::fs::write(path, &data)
+            .with_context(|| format!("failed to create sst file {:?}", path))?;
+        Self::open_created(path, data.len())
+    }
+
+    /// Create a new file object such that `path` only ever holds the complete file: the data is
+    /// written and synced to `{path}.tmp`, renamed to `path`, and then the parent directory is
+    /// synced so that the new entry survives a crash. The temporary file is removed on failure.
+    pub fn create_atomic(path: &Path, data: Vec<u8>) -> Result<Self> {
+        let tmp_path = tmp_path_of(path);
+        let write_and_rename = || -> Result<()> {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(&data)?;
+            file.sync_all()?;
+            std::fs::rename(&tmp_path, path)?;
+            Ok(())
+        };
+        if let Err(e) = write_and_rename() {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(e.context(format!("failed to create sst file {:?}", path)));
+        }
+        sync_parent_dir(path)
+            .with_context(|| format!("failed to sync the directory of sst file {:?}", path))?;
+        Self::open_created(path, data.len())
+    }
+
+    fn open_created(path: &Path, size: usize) -> Result<Self> {
+        let file = File::options()
+            .read(true)
+            .write(false)
+            .open(path)
+            .with_context(|| format!("failed to open sst file {:?}", path))?;
         Ok(FileObject(
-            Some(File::options().read(true).write(false).open(path)?),
-            data.len() as u64,
+            Some(FileBackend::Disk(Arc::new(file))),
+            size as u64,
+            Some(path.to_path_buf()),
+            AtomicBool::new(false),
         ))
     }
 
     pub fn open(path: &Path) -> Result<Self> {
-        let file = File::options().read(true).write(false).open(path)?;
-        let size = file.metadata()?.len();
-        Ok(FileObject(Some(file), size))
+        let open = || -> Result<(File, u64)> {
+            let file = File::options().read(true).write(false).open(path)?;
+            let size = file.metadata()?.len();
+            Ok((file, size))
+        };
+        let (file, size) = open().with_context(|| format!("failed to open sst file {:?}", path))?;
+        Ok(FileObject(
+            Some(FileBackend::Disk(Arc::new(file))),
+            size,
+            Some(path.to_path_buf()),
+            AtomicBool::new(false),
+        ))
+    }
+
+    /// Open a file with `O_DIRECT`, so that reads bypass the OS page cache, e.g., for benchmarks.
+    /// Reads are rounded out to `DIRECT_IO_ALIGN` internally, so the file needs no padding. Only
+    /// supported on Linux.
+    pub fn open_direct(path: &Path) -> Result<Self> {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            let open = || -> Result<(File, u64)> {
+                let file = File::options()
+                    .read(true)
+                    .custom_flags(libc::O_DIRECT)
+                    .open(path)?;
+                let size = file.metadata()?.len();
+                Ok((file, size))
+            };
+            let (file, size) =
+                open().with_context(|| format!("failed to open sst file {:?}", path))?;
+            Ok(FileObject(
+                Some(FileBackend::Direct(Arc::new(file))),
+                size,
+                Some(path.to_path_buf()),
+                AtomicBool::new(false),
+            ))
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = path;
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "O_DIRECT is only supported on Linux",
+            )
+            .into())
+        }
+    }
+
+    /// Create a file object backed by an in-memory buffer instead of a file on the disk.
+    pub fn from_bytes(data: Bytes) -> Self {
+        let size = data.len() as u64;
+        FileObject(
+            Some(FileBackend::Memory(data)),
+            size,
+            None,
+            AtomicBool::new(false),
+        )
+    }
+
+    /// Like `from_bytes`, but every read sleeps for `latency` first.
+    #[cfg(test)]
+    pub(crate) fn from_bytes_with_latency(data: Bytes, latency: std::time::Duration) -> Self {
+        let size = data.len() as u64;
+        FileObject(
+            Some(FileBackend::Slow(data, latency)),
+            size,
+            None,
+            AtomicBool::new(false),
+        )
     }
 }
 
+impl Drop for FileObject {
+    fn drop(&mut self) {
+        if !*self.3.get_mut() {
+            return;
+        }
+        if let Some(path) = &self.2 {
+            if let Err(e) = std::fs::remove_file(path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    eprintln!("failed to remove sst file {:?}: {}", path, e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    static READ_COUNT: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// The number of `FileObject::read_into` calls made by the current thread, for tests counting I/O.
+#[cfg(test)]
+pub(crate) fn read_count() -> usize {
+    READ_COUNT.with(|count| count.get())
+}
+
+#[cfg(test)]
+thread_local! {
+    static ADVICE_LOG: std::cell::RefCell<Vec<(u64, u64, Advice)>> =
+        const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Take the `FileObject::advise` calls made by the current thread so far, in order.
+#[cfg(test)]
+pub(crate) fn take_advice_log() -> Vec<(u64, u64, Advice)> {
+    ADVICE_LOG.with(|log| std::mem::take(&mut *log.borrow_mut()))
+}
+
+/// The temporary file an SST at `path` is written to before being renamed into place.
+fn tmp_path_of(path: &Path) -> PathBuf {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    PathBuf::from(tmp_path)
+}
+
+/// Sync the directory containing `path`, so that a newly created or renamed entry is durable.
+/// Directories cannot be opened as files on Windows, where this is a no-op.
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::open(parent)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Read exactly `buf.len()` bytes at `offset` of `file`, without moving the file cursor on unix.
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileExt;
+        file.read_exact_at(buf, offset)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::FileExt;
+        read_exact_with(buf, offset, |buf, offset| file.seek_read(buf, offset))
+    }
+}
+
+/// Alignment of the offset, length and buffer of reads from a file opened with `O_DIRECT`.
+#[cfg(target_os = "linux")]
+const DIRECT_IO_ALIGN: usize = 4096;
+
+/// Read exactly `buf.len()` bytes at `offset` of a file opened with `O_DIRECT`, through an aligned
+/// scratch buffer covering the requested range rounded out to `DIRECT_IO_ALIGN`.
+#[cfg(target_os = "linux")]
+fn read_direct(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    use std::io::{Error, ErrorKind};
+    use std::os::unix::fs::FileExt;
+
+    let start = offset as usize / DIRECT_IO_ALIGN * DIRECT_IO_ALIGN;
+    let skip = offset as usize - start;
+    let needed = skip + buf.len();
+    let len = needed.div_ceil(DIRECT_IO_ALIGN) * DIRECT_IO_ALIGN;
+    let mut scratch = vec![0u8; len + DIRECT_IO_ALIGN];
+    let shift = scratch.as_ptr().align_offset(DIRECT_IO_ALIGN);
+    let aligned = &mut scratch[shift..shift + len];
+    // The last read may come back short at the end of the file.
+    let mut read = 0;
+    while read < needed {
+        match file.read_at(&mut aligned[read..], (start + read) as u64) {
+            Ok(0) => {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "failed to fill whole buffer",
+                ))
+            }
+            Ok(n) => read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    buf.copy_from_slice(&aligned[skip..needed]);
+    Ok(())
+}
+
+/// Fill `buf` with positional reads starting at `offset`, retrying on short and interrupted reads.
+#[cfg(any(windows, test))]
+pub(crate) fn read_exact_with(
+    mut buf: &mut [u8],
+    mut offset: u64,
+    mut read_at: impl FnMut(&mut [u8], u64) -> std::io::Result<usize>,
+) -> std::io::Result<()> {
+    use std::io::{Error, ErrorKind};
+    while !buf.is_empty() {
+        match read_at(buf, offset) {
+            Ok(0) => {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "failed to fill whole buffer",
+                ))
+            }
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Options for reading blocks through the block cache.
+#[derive(Clone, Debug)]
+pub struct ReadOptions {
+    /// Whether blocks read from the disk are inserted into the block cache. Scans that read each
+    /// block once, e.g., compaction, should not fill the cache so as to keep hot blocks in it.
+    pub fill_cache: bool,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self { fill_cache: true }
+    }
+}
+
+/// Options for opening an SST.
+#[derive(Clone, Debug, Default)]
+pub struct SsTableOpenOptions {
+    /// Whether a bloom filter failing its checksum fails the open. Otherwise, the SST is opened
+    /// without a bloom filter and a warning is printed.
+    pub strict_bloom_checksum: bool,
+    /// The hasher the bloom filter was built with, `FarmHasher` if not set. A bloom filter built
+    /// with a hasher of another id is not used.
+    pub bloom_hasher: Option<Arc<dyn Hasher>>,
+}
+
 /// An SSTable.
 pub struct SsTable {
     /// The actual storage unit of SsTable, the format is as above.
     pub(crate) file: FileObject,
-    /// The meta blocks that hold info for data blocks.
-    pub(crate) block_meta: Vec<BlockMeta>,
+    /// The two-level index of the metas of the data blocks.
+    pub(crate) block_index: BlockIndex,
     /// The offset that indicates the start point of meta blocks in `file`.
     pub(crate) block_meta_offset: usize,
     id: usize,
@@ -124,8 +780,14 @@ pub struct SsTable {
     first_key: KeyBytes,
     last_key: KeyBytes,
     pub(crate) bloom: Option<Bloom>,
+    /// Counters of the bloom filter checks made by point lookups on this SST.
+    bloom_counters: BloomCounters,
+    /// The keys that point lookups read a block for and did not find, if enabled.
+    negative_cache: Option<NegativeCache>,
     /// The maximum timestamp stored in this SST, implemented in week 3.
     max_ts: u64,
+    /// Statistics over the entries stored in this SST.
+    properties: SsTableProperties,
 }
 
 impl SsTable {
@@ -136,45 +798,153 @@ impl SsTable {
 
     /// Open SSTable from a file.
     pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
-        let offset_size = std::mem::size_of::<u32>() as u64;
+        Self::open_with_options(id, block_cache, file, &SsTableOpenOptions::default())
+    }
 
-        let raw_bloom_offset = file.read(file.size() - 4, 4)?;
-        let bloom_offset = (&raw_bloom_offset[..]).get_u32() as u64;
-        let raw_bloom = file.read(bloom_offset, file.size() - 4 - bloom_offset)?;
-        let bloom_filter = Bloom::decode(&raw_bloom)?;
+    /// Open SSTable from a file, with options on how to handle a corrupted bloom filter.
+    pub fn open_with_options(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+        options: &SsTableOpenOptions,
+    ) -> Result<Self> {
+        let footer_size = SIZEOF_U64 + std::mem::size_of::<u32>() as u64;
+        if file.size() < footer_size {
+            bail!("SST {} is too small: {} bytes", id, file.size());
+        }
+        let raw_footer = file.read(file.size() - footer_size, footer_size)?;
+        let mut raw_footer = &raw_footer[..];
+        let properties_offset = raw_footer.get_u64();
+        let version = raw_footer.get_u32();
+        if version != SST_FORMAT_VERSION {
+            bail!("unsupported format version {} of SST {}", version, id);
+        }
+        // Each section is followed by its offset, so the section before it ends at `end`.
+        let read_section_offset = |end: u64| -> Result<u64> {
+            let Some(start) = end.checked_sub(SIZEOF_U64) else {
+                bail!("invalid section offset in SST {}", id);
+            };
+            Ok(file.read(start, SIZEOF_U64)?.as_slice().get_u64())
+        };
+        let section_len = |start: u64, end: u64| -> Result<u64> {
+            match end.checked_sub(start) {
+                Some(len) => Ok(len),
+                None => bail!("invalid section offset {} in SST {}", start, id),
+            }
+        };
 
-        let block_meta_offset = file.read(bloom_offset - offset_size, offset_size)?;
-        let block_meta_offset = block_meta_offset[..].try_into()?;
-        let block_meta_offset = u32::from_be_bytes(block_meta_offset) as u64;
+        let properties_end = file.size() - footer_size;
+        let raw_properties = file.read(
+            properties_offset,
+            section_len(properties_offset, properties_end)?,
+        )?;
+        let properties = SsTableProperties::decode(&raw_properties)?;
 
-        let buf = file.read(block_meta_offset, bloom_offset - 4 - block_meta_offset)?;
-        let block_meta = BlockMeta::decode_block_meta(&buf[..]);
-        let first_key = block_meta
-            .iter()
-            .map(|meta| &meta.first_key)
-            .min()
-            .unwrap()
-            .to_owned();
-        let last_key = block_meta
-            .iter()
-            .map(|meta| &meta.last_key)
-            .max()
-            .unwrap()
-            .to_owned();
+        let bloom_offset = read_section_offset(properties_offset)?;
+        let bloom_end = properties_offset - SIZEOF_U64;
+        let raw_bloom = file.read(bloom_offset, section_len(bloom_offset, bloom_end)?)?;
+        let Some((raw_bloom, checksum)) = raw_bloom.split_last_chunk::<4>() else {
+            bail!("truncated bloom section in SST {}", id);
+        };
+        let bloom = if crc32fast::hash(raw_bloom) == u32::from_be_bytes(*checksum) {
+            Self::decode_bloom_section(raw_bloom)
+                .with_context(|| format!("invalid bloom section in SST {}", id))?
+        } else if options.strict_bloom_checksum {
+            bail!("bloom filter checksum mismatch in SST {}", id);
+        } else {
+            // A corrupted bloom filter could reject keys that exist, so reads go to the data
+            // blocks instead.
+            eprintln!(
+                "bloom filter checksum mismatch in SST {}, opening it without the bloom filter",
+                id
+            );
+            None
+        };
+        let hasher = options
+            .bloom_hasher
+            .clone()
+            .unwrap_or_else(|| Arc::new(FarmHasher));
+        let bloom = match bloom {
+            Some((bloom, hasher_id)) if hasher_id == hasher.id() => Some(bloom.with_hasher(hasher)),
+            Some((_, hasher_id)) => {
+                // Probing the filter with other hashes would reject keys that exist.
+                eprintln!(
+                    "bloom filter of SST {} was built with hasher {} instead of {}, opening it without the bloom filter",
+                    id,
+                    hasher_id,
+                    hasher.id()
+                );
+                None
+            }
+            None => None,
+        };
+
+        let index_offset = read_section_offset(bloom_offset)?;
+        let index_end = bloom_offset - SIZEOF_U64;
+        let raw_index = file.read(index_offset, section_len(index_offset, index_end)?)?;
+        let block_meta_offset = read_section_offset(index_offset)?;
+        let block_meta_end = index_offset - SIZEOF_U64;
+        section_len(block_meta_offset, block_meta_end)?;
+        let block_index = BlockIndex::decode(
+            &raw_index,
+            block_meta_offset as usize..block_meta_end as usize,
+        )
+        .with_context(|| format!("failed to decode block index of SST {}", id))?;
+        // The partitions are in file order, so the table starts and ends where the first and the
+        // last blocks do. Comparing keys would not work once keys carry descending timestamps.
+        let (Some(first_summary), Some(last_summary)) =
+            (block_index.first_summary(), block_index.last_summary())
+        else {
+            bail!("SST {} has no blocks", id);
+        };
+        let first_key = first_summary.first_key.clone();
+        let last_key = last_summary.last_key.clone();
 
         Ok(Self {
             file,
-            block_meta,
+            block_index,
             block_meta_offset: block_meta_offset as usize,
             id,
             block_cache,
             first_key,
             last_key,
-            bloom: Some(bloom_filter),
-            max_ts: 0,
+            bloom,
+            bloom_counters: BloomCounters::default(),
+            negative_cache: None,
+            max_ts: properties.max_ts,
+            properties,
         })
     }
 
+    /// Open SSTable from an in-memory buffer holding the whole file.
+    pub fn open_from_bytes(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        data: Bytes,
+    ) -> Result<Self> {
+        Self::open(id, block_cache, FileObject::from_bytes(data))
+    }
+
+    /// Decode the bloom section, without its checksum, into the bloom filter and the id of its
+    /// hasher. It starts with a flag indicating whether the SST has a bloom filter, and whether key
+    /// prefixes of the length that follows the hasher id are added to it.
+    fn decode_bloom_section(raw_bloom: &[u8]) -> Result<Option<(Bloom, u32)>> {
+        let (flag, mut raw_bloom) = match raw_bloom.split_first() {
+            Some((&0, _)) => return Ok(None),
+            Some((&flag, raw_bloom)) if flag == 1 || flag == 2 => (flag, raw_bloom),
+            _ => bail!("unknown bloom section flag"),
+        };
+        let header_len = if flag == 2 { 2 } else { 1 } * std::mem::size_of::<u32>();
+        if raw_bloom.len() <= header_len {
+            bail!("truncated bloom section");
+        }
+        let hasher_id = raw_bloom.get_u32();
+        let prefix_len = (flag == 2).then(|| raw_bloom.get_u32() as usize);
+        let mut bloom = Bloom::decode(raw_bloom)?;
+        bloom.prefix_len = prefix_len;
+        Ok(Some((bloom, hasher_id)))
+    }
+
     /// Create a mock SST with only first key + last key metadata
     pub fn create_meta_only(
         id: usize,
@@ -183,27 +953,188 @@ impl SsTable {
         last_key: KeyBytes,
     ) -> Self {
         Self {
-            file: FileObject(None, file_size),
-            block_meta: vec![],
+            file: FileObject(None, file_size, None, AtomicBool::new(false)),
+            block_index: BlockIndex::default(),
             block_meta_offset: 0,
             id,
             block_cache: None,
             first_key,
             last_key,
             bloom: None,
+            bloom_counters: BloomCounters::default(),
+            negative_cache: None,
             max_ts: 0,
+            properties: SsTableProperties::default(),
         }
     }
 
     pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
-        let offset = self.block_meta[block_idx].offset;
-        let offset_end = self
-            .block_meta
-            .get(block_idx + 1)
-            .map_or(self.block_meta_offset, |x| x.offset);
+        let (offset, offset_end) = self.block_range(block_idx)?;
+        let block_data = self
+            .file
+            .read(offset as u64, (offset_end - offset) as u64)
+            .with_context(|| format!("failed to read block {} of SST {}", block_idx, self.id))?;
+        self.decode_block(block_idx, block_data)
+    }
+
+    /// The metas of the blocks in index partition `partition`, read from the file on first use.
+    fn index_partition(&self, partition: usize) -> Result<&[BlockMeta]> {
+        if let Some(metas) = self.block_index.loaded(partition) {
+            return Ok(metas);
+        }
+        let (offset, len) = self.block_index.location(partition);
+        let buf = self.file.read(offset as u64, len as u64).with_context(|| {
+            format!(
+                "failed to read index partition {} of SST {}",
+                partition, self.id
+            )
+        })?;
+        self.block_index
+            .load(partition, &buf, self.block_meta_offset)
+            .with_context(|| {
+                format!(
+                    "failed to decode index partition {} of SST {}",
+                    partition, self.id
+                )
+            })
+    }
+
+    /// The meta of block `block_idx`, reading its index partition if it has not been used yet.
+    pub(crate) fn block_meta(&self, block_idx: usize) -> Result<&BlockMeta> {
+        if block_idx >= self.num_of_blocks() {
+            bail!(
+                "block {} out of bounds of SST {} with {} blocks",
+                block_idx,
+                self.id,
+                self.num_of_blocks()
+            );
+        }
+        let (partition, idx) = self.block_index.locate(block_idx);
+        Ok(&self.index_partition(partition)?[idx])
+    }
+
+    /// The metas of all the blocks, reading every index partition.
+    pub(crate) fn block_metas(&self) -> Result<Vec<BlockMeta>> {
+        let mut block_meta = Vec::with_capacity(self.num_of_blocks());
+        for partition in 0..self.block_index.num_partitions() {
+            block_meta.extend_from_slice(self.index_partition(partition)?);
+        }
+        Ok(block_meta)
+    }
+
+    /// The byte range of block `block_idx` in the file.
+    fn block_range(&self, block_idx: usize) -> Result<(usize, usize)> {
+        let offset = self.block_meta(block_idx)?.offset;
+        let (partition, idx) = self.block_index.locate(block_idx);
+        // The block ends where the next one starts, which may be the first block of the next
+        // partition, or where the block metas start for the last block.
+        let offset_end = match self.index_partition(partition)?.get(idx + 1) {
+            Some(next) => next.offset,
+            None => self
+                .block_index
+                .first_block_offset(partition + 1)
+                .unwrap_or(self.block_meta_offset),
+        };
+        Ok((offset, offset_end))
+    }
+
+    /// Read the blocks in `range` with a single read spanning all of them, e.g., for scans that
+    /// visit every block.
+    pub fn read_blocks(&self, range: Range<usize>) -> Result<Vec<Arc<Block>>> {
+        if range.start > range.end || range.end > self.num_of_blocks() {
+            bail!(
+                "block range {:?} out of bounds of {} blocks",
+                range,
+                self.num_of_blocks()
+            );
+        }
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+        let start = self.block_range(range.start)?.0;
+        let end = self.block_range(range.end - 1)?.1;
+        let data = self
+            .file
+            .read(start as u64, (end - start) as u64)
+            .with_context(|| format!("failed to read blocks {:?} of SST {}", range, self.id))?;
+        range
+            .map(|idx| {
+                let (offset, offset_end) = self.block_range(idx)?;
+                let block_data = &data[offset - start..offset_end - start];
+                self.decode_block(idx, block_data.to_vec())
+            })
+            .collect()
+    }
+
+    /// Read the whole data region with a single read and decode all the blocks in parallel, one
+    /// chunk of consecutive blocks per core. Decoding, and decompressing in particular, is CPU-bound,
+    /// so this speeds up full scans of large SSTs. The blocks are returned in order, and bypass the
+    /// block cache.
+    pub fn par_scan(&self) -> Result<Vec<Arc<Block>>> {
+        let num_blocks = self.num_of_blocks();
+        if num_blocks == 0 {
+            return Ok(Vec::new());
+        }
+        // Locate every block up front, so that the index partitions are loaded on this thread.
+        let ranges = (0..num_blocks)
+            .map(|idx| self.block_range(idx))
+            .collect::<Result<Vec<_>>>()?;
+        let start = ranges[0].0;
+        let end = ranges[num_blocks - 1].1;
+        let data = self
+            .file
+            .read(start as u64, (end - start) as u64)
+            .with_context(|| format!("failed to read the data blocks of SST {}", self.id))?;
+
+        let num_threads = std::thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(num_blocks);
+        let chunk_len = num_blocks.div_ceil(num_threads);
+        std::thread::scope(|scope| {
+            let workers = ranges
+                .chunks(chunk_len)
+                .enumerate()
+                .map(|(chunk, ranges)| {
+                    let data = &data;
+                    scope.spawn(move || {
+                        ranges
+                            .iter()
+                            .enumerate()
+                            .map(|(idx, &(offset, offset_end))| {
+                                let block_data = &data[offset - start..offset_end - start];
+                                self.decode_block(chunk * chunk_len + idx, block_data.to_vec())
+                            })
+                            .collect::<Result<Vec<_>>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            let mut blocks = Vec::with_capacity(num_blocks);
+            for worker in workers {
+                blocks.extend(worker.join().expect("block decoding thread panicked")?);
+            }
+            Ok(blocks)
+        })
+    }
+
+    /// Read a block from the disk without blocking the async runtime.
+    #[cfg(feature = "async")]
+    pub async fn read_block_async(&self, block_idx: usize) -> Result<Arc<Block>> {
+        let (offset, offset_end) = self.block_range(block_idx)?;
         let block_data = self
             .file
-            .read(offset as u64, (offset_end - offset) as u64)?;
+            .read_async(offset as u64, (offset_end - offset) as u64)
+            .await
+            .with_context(|| format!("failed to read block {} of SST {}", block_idx, self.id))?;
+        self.decode_block(block_idx, block_data.to_vec())
+    }
+
+    /// Decode the data of block `block_idx` as read from the disk.
+    fn decode_block(&self, block_idx: usize, block_data: Vec<u8>) -> Result<Arc<Block>> {
+        let block_data = self
+            .block_meta(block_idx)?
+            .compression
+            .decompress(block_data)
+            .with_context(|| format!("failed to read block {} of SST {}", block_idx, self.id))?;
         Ok(Arc::new(Block::decode(&block_data[..])))
     }
 
@@ -226,33 +1157,215 @@ impl SsTable {
 
     /// Read a block from disk, with block cache. (Day 4)
     pub fn read_block_cached(&self, block_idx: usize) -> Result<Arc<Block>> {
-        // if let Some(block_cache) = self.block_cache.as_ref() {
-        //     let key = (self.id, block_idx);
-        //     let res = block_cache.try_get_with(key, || self.read_block(block_idx));
-        //     match res {
-        //         Ok(block) => Ok(block),
-        //         Err(_) => bail!("read block error"),
-        //     }
-        // } else {
-        self.read_block(block_idx)
-        // }
+        self.read_block_with_options(block_idx, &ReadOptions::default())
+    }
+
+    /// Read a block, looking it up in the block cache first. A block read from the disk is only
+    /// inserted into the cache if `options.fill_cache` is set.
+    pub fn read_block_with_options(
+        &self,
+        block_idx: usize,
+        options: &ReadOptions,
+    ) -> Result<Arc<Block>> {
+        let Some(block_cache) = self.block_cache.as_ref() else {
+            return self.read_block(block_idx);
+        };
+        let key = (self.id, block_idx);
+        if let Some(block) = block_cache.get(&key) {
+            return Ok(block);
+        }
+        let block = self.read_block(block_idx)?;
+        if options.fill_cache {
+            block_cache.insert(key, block.clone());
+        }
+        Ok(block)
+    }
+
+    /// Read a block without blocking the async runtime, looking it up in the block cache first.
+    /// The cache is only consulted and filled around the read, so concurrent misses on the same
+    /// block may each read it from the disk.
+    #[cfg(feature = "async")]
+    pub async fn read_block_cached_async(&self, block_idx: usize) -> Result<Arc<Block>> {
+        let Some(block_cache) = self.block_cache.as_ref() else {
+            return self.read_block_async(block_idx).await;
+        };
+        let key = (self.id, block_idx);
+        if let Some(block) = block_cache.get(&key) {
+            return Ok(block);
+        }
+        let block = self.read_block_async(block_idx).await?;
+        block_cache.insert(key, block.clone());
+        Ok(block)
+    }
+
+    /// Create an iterator over the whole SST.
+    pub fn iter(self: Arc<Self>) -> Result<SsTableIterator> {
+        SsTableIterator::create_and_seek_to_first(self)
+    }
+
+    /// Create an iterator over the keys of the SST within `lower` and `upper`. It seeks to `lower`,
+    /// and becomes invalid once the key passes `upper`, without reading the blocks beyond it.
+    pub fn range(
+        self: Arc<Self>,
+        lower: Bound<KeySlice>,
+        upper: Bound<KeySlice>,
+    ) -> Result<SsTableIterator> {
+        let mut iter = match lower {
+            Bound::Unbounded => SsTableIterator::create_and_seek_to_first(self)?,
+            Bound::Included(key) | Bound::Excluded(key) => {
+                SsTableIterator::create_and_seek_to_key(self, key)?
+            }
+        };
+        iter.set_end_bound(upper.map(|key| key.to_key_vec().into_key_bytes()));
+        if let Bound::Excluded(key) = lower {
+            if iter.is_valid() && iter.key() == key {
+                iter.next()?;
+            }
+        }
+        Ok(iter)
     }
 
     /// Find the block that may contain `key`.
     /// Note: You may want to make use of the `first_key` stored in `BlockMeta`.
     /// You may also assume the key-value pairs stored in each consecutive block are sorted.
-    pub fn find_block_idx(&self, key: KeySlice) -> usize {
-        for (idx, block_meta) in self.block_meta.iter().enumerate() {
-            if block_meta.last_key.as_key_slice() >= key {
-                return idx;
+    ///
+    /// Only the index partition that may hold the block is read, found by its key range in the
+    /// top-level index.
+    pub fn find_block_idx(&self, key: KeySlice) -> Result<usize> {
+        let partition = self.block_index.find_partition(key);
+        let metas = self.index_partition(partition)?;
+        let idx = metas
+            .partition_point(|meta| meta.last_key.as_key_slice() < key)
+            .min(metas.len() - 1);
+        Ok(self.block_index.partition_blocks(partition).start + idx)
+    }
+
+    /// Point lookup of `key` in this SST. The value is returned as stored, so a delete tombstone
+    /// comes back as an empty value. Keys outside `[first_key, last_key]` or rejected by the bloom
+    /// filter return `None` without reading any block.
+    pub fn get(&self, key: KeySlice) -> Result<Option<Bytes>> {
+        if key < self.first_key.as_key_slice() || key > self.last_key.as_key_slice() {
+            return Ok(None);
+        }
+        if let Some(negative_cache) = &self.negative_cache {
+            if negative_cache.contains(key.raw_ref()) {
+                return Ok(None);
             }
         }
-        self.block_meta.len() - 1
+        if !self.key_may_exist(key.key_ref()) {
+            return Ok(None);
+        }
+        let block = self.read_block_cached(self.find_block_idx(key)?)?;
+        let iter = BlockIterator::create_and_seek_to_key(block, key);
+        if iter.is_valid() && iter.key() == key {
+            return Ok(Some(Bytes::copy_from_slice(iter.value())));
+        }
+        self.record_bloom_false_positive();
+        if let Some(negative_cache) = &self.negative_cache {
+            negative_cache.insert(key.raw_ref());
+        }
+        Ok(None)
+    }
+
+    /// Remember up to `capacity` keys that `get` read a block for and did not find, so that looking
+    /// them up again skips the bloom filter and the block read. The full raw key is cached, as a
+    /// missing version of a key says nothing about its other versions. Keys rejected by the bloom
+    /// filter are cheap to look up and are not cached.
+    pub fn with_negative_cache(mut self, capacity: usize) -> Self {
+        self.negative_cache = Some(NegativeCache::new(capacity));
+        self
+    }
+
+    /// Check the bloom filter for the user key `key`, without a timestamp. Always returns true if
+    /// the SST has no bloom filter.
+    pub fn key_may_exist(&self, key: &[u8]) -> bool {
+        match &self.bloom {
+            Some(bloom) => {
+                let may_contain = bloom.may_contain_key(key);
+                self.bloom_counters.record_check(may_contain);
+                may_contain
+            }
+            None => true,
+        }
+    }
+
+    /// Account for a lookup of a key that passed `key_may_exist` but was not found, if the SST has
+    /// a bloom filter.
+    pub(crate) fn record_bloom_false_positive(&self) {
+        if self.bloom.is_some() {
+            self.bloom_counters.record_false_positive();
+        }
+    }
+
+    /// The bloom filter of the SST, if it has one.
+    pub fn bloom(&self) -> Option<&Bloom> {
+        self.bloom.as_ref()
+    }
+
+    /// Statistics on the bloom filter checks made by point lookups on this SST.
+    pub fn bloom_stats(&self) -> BloomStats {
+        self.bloom_counters
+            .stats(self.bloom.as_ref().map_or(0, Bloom::memory_usage))
+    }
+
+    /// The memory pinned by this SST while it is open: the block index with the metas of the
+    /// partitions read so far, the first and last keys of the SST, and the bloom filter. Cached
+    /// blocks are not counted.
+    pub fn meta_memory_usage(&self) -> usize {
+        self.block_index.memory_usage()
+            + self.first_key.len()
+            + self.last_key.len()
+            + self.bloom.as_ref().map_or(0, Bloom::memory_usage)
+    }
+
+    /// Check the bloom filter for keys starting with `prefix`. Returns true if the SST has no
+    /// bloom filter over key prefixes, or if `prefix` is shorter than the prefixes in the filter.
+    pub fn may_contain_prefix(&self, prefix: &[u8]) -> bool {
+        let Some((bloom, prefix_len)) = self
+            .bloom
+            .as_ref()
+            .and_then(|bloom| Some((bloom, bloom.prefix_len?)))
+        else {
+            return true;
+        };
+        match prefix.get(..prefix_len) {
+            Some(prefix) => bloom.may_contain_key(prefix),
+            None => true,
+        }
+    }
+
+    /// Rebuild the in-memory bloom filter from the keys stored in the data blocks, e.g., for an SST
+    /// built without a bloom filter. The file on the disk is left untouched. Keys are hashed with
+    /// the hasher of the current bloom filter, or `FarmHasher` if there is none, and the filter keeps
+    /// its layout.
+    pub fn recompute_bloom(&mut self, false_positive_rate: f64) -> Result<()> {
+        let hasher = self.bloom.as_ref().map_or_else(
+            || Arc::new(FarmHasher) as Arc<dyn Hasher>,
+            |bloom| bloom.hasher.clone(),
+        );
+        let mut key_hashes = Vec::new();
+        for block_idx in 0..self.num_of_blocks() {
+            let mut iter = BlockIterator::create_and_seek_to_first(self.read_block(block_idx)?);
+            while iter.is_valid() {
+                key_hashes.push(hasher.hash(iter.key().key_ref()));
+                iter.next();
+            }
+        }
+        let bits_per_key = Bloom::bloom_bits_per_key(key_hashes.len(), false_positive_rate);
+        let kind = self
+            .bloom
+            .as_ref()
+            .map_or(BloomKind::Standard, |bloom| bloom.kind);
+        self.bloom = Some(
+            Bloom::build_from_key_hashes_with_kind(&key_hashes, bits_per_key, kind)
+                .with_hasher(hasher),
+        );
+        Ok(())
     }
 
     /// Get number of data blocks.
     pub fn num_of_blocks(&self) -> usize {
-        self.block_meta.len()
+        self.block_index.len()
     }
 
     pub fn first_key(&self) -> &KeyBytes {
@@ -274,4 +1387,19 @@ impl SsTable {
     pub fn max_ts(&self) -> u64 {
         self.max_ts
     }
+
+    pub fn properties(&self) -> &SsTableProperties {
+        &self.properties
+    }
+
+    /// Remove the file of this SST once the last `Arc<SsTable>` is dropped, e.g., after it is
+    /// compacted away. Iterators created before still hold a reference and can keep reading it.
+    /// The storage does not call this yet, as it has no compaction that removes SSTs.
+    pub fn mark_for_deletion(&self) {
+        self.file.3.store(true, Ordering::Release);
+    }
+
+    pub fn is_marked_for_deletion(&self) -> bool {
+        self.file.3.load(Ordering::Acquire)
+    }
 }
@@ -0,0 +1,101 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use super::SsTable;
+use crate::{block::BlockIterator, key::KeySlice};
+
+/// An iterator over the contents of an SSTable that reads its blocks without blocking the async
+/// runtime. It mirrors `SsTableIterator`, but as moving to another block may read from the disk,
+/// the seeking methods and `next` return futures and it does not implement `StorageIterator`.
+pub struct AsyncSsTableIterator {
+    table: Arc<SsTable>,
+    blk_iter: BlockIterator,
+    blk_idx: usize,
+}
+
+impl AsyncSsTableIterator {
+    /// Create a new iterator and seek to the first key-value pair in the first data block.
+    pub async fn create_and_seek_to_first(table: Arc<SsTable>) -> Result<Self> {
+        let block = table.read_block_cached_async(0).await?;
+        Ok(Self {
+            table,
+            blk_iter: BlockIterator::create_and_seek_to_first(block),
+            blk_idx: 0,
+        })
+    }
+
+    /// Create a new iterator and seek to the first key-value pair which >= `key`.
+    pub async fn create_and_seek_to_key(table: Arc<SsTable>, key: KeySlice<'_>) -> Result<Self> {
+        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&table, key).await?;
+        Ok(Self {
+            table,
+            blk_iter,
+            blk_idx,
+        })
+    }
+
+    /// Seek to the first key-value pair in the first data block.
+    pub async fn seek_to_first(&mut self) -> Result<()> {
+        let block = self.table.read_block_cached_async(0).await?;
+        self.blk_idx = 0;
+        self.blk_iter = BlockIterator::create_and_seek_to_first(block);
+        Ok(())
+    }
+
+    async fn seek_to_key_inner(
+        table: &Arc<SsTable>,
+        key: KeySlice<'_>,
+    ) -> Result<(usize, BlockIterator)> {
+        let mut blk_idx = table.find_block_idx(key)?;
+        let block = table.read_block_cached_async(blk_idx).await?;
+        let mut blk_iter = BlockIterator::create_and_seek_to_key(block, key);
+        if !blk_iter.is_valid() {
+            blk_idx += 1;
+            if blk_idx < table.num_of_blocks() {
+                let block = table.read_block_cached_async(blk_idx).await?;
+                blk_iter = BlockIterator::create_and_seek_to_first(block);
+            }
+        }
+        Ok((blk_idx, blk_iter))
+    }
+
+    /// Seek to the first key-value pair which >= `key`.
+    pub async fn seek_to_key(&mut self, key: KeySlice<'_>) -> Result<()> {
+        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&self.table, key).await?;
+        self.blk_idx = blk_idx;
+        self.blk_iter = blk_iter;
+        Ok(())
+    }
+
+    /// Return the key of the current entry.
+    pub fn key(&self) -> KeySlice<'_> {
+        self.blk_iter.key()
+    }
+
+    /// Return the value of the current entry.
+    pub fn value(&self) -> &[u8] {
+        self.blk_iter.value()
+    }
+
+    /// Return whether the iterator points at an entry.
+    pub fn is_valid(&self) -> bool {
+        self.blk_iter.is_valid()
+    }
+
+    /// Move to the next entry, reading the next block once the current one is exhausted.
+    pub async fn next(&mut self) -> Result<()> {
+        if !self.is_valid() {
+            return Ok(());
+        }
+        self.blk_iter.next();
+        if !self.blk_iter.is_valid() {
+            self.blk_idx += 1;
+            if self.blk_idx < self.table.num_of_blocks() {
+                let block = self.table.read_block_cached_async(self.blk_idx).await?;
+                self.blk_iter = BlockIterator::create_and_seek_to_first(block);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,306 @@
+use std::ops::Range;
+use std::sync::OnceLock;
+
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut};
+
+use super::{BlockMeta, Compression};
+use crate::key::KeySlice;
+
+/// The number of block metas in each partition of the index written by `SsTableBuilder`.
+pub(crate) const BLOCKS_PER_PARTITION: usize = 128;
+
+const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+const SIZEOF_U64: usize = std::mem::size_of::<u64>();
+
+/// A partition of the block metas, summarized by the offset of its first block and the key range
+/// of its blocks.
+struct IndexPartition {
+    summary: BlockMeta,
+    /// The location of the encoded metas in the file.
+    offset: usize,
+    len: usize,
+    /// The decoded metas, set when the partition is first used.
+    metas: OnceLock<Vec<BlockMeta>>,
+}
+
+/// The two-level index of the data blocks of an SST. The block metas are split into partitions of
+/// `blocks_per_partition` consecutive blocks, each encoded like a block meta section, and the
+/// partitions are followed by a top-level index summarizing each of them:
+///
+/// ```text
+/// | partition | ... | partition | partitions offset (u64) | top-level index | index offset (u64) |
+/// ```
+///
+/// The top-level index holds the number of blocks (u32), the number of blocks per partition
+/// (u32), the encoded length of each partition (u32), and the summaries of the partitions encoded
+/// as a block meta section. Opening an SST only decodes the top-level index, and a partition is
+/// read when a lookup first lands on it, so the cost of opening an SST does not grow with the
+/// number of its blocks.
+#[derive(Default)]
+pub(crate) struct BlockIndex {
+    num_blocks: usize,
+    blocks_per_partition: usize,
+    partitions: Vec<IndexPartition>,
+}
+
+impl BlockIndex {
+    /// The summary of a partition from its first and last blocks: the offset and first key of the
+    /// first block, and the last key of the last block.
+    fn summarize(first: &BlockMeta, last: &BlockMeta) -> BlockMeta {
+        BlockMeta {
+            offset: first.offset,
+            first_key: first.first_key.clone(),
+            last_key: last.last_key.clone(),
+            compression: Compression::None,
+        }
+    }
+
+    /// Encode the index over `block_meta` to `buf`, which starts at file offset `base`, and return
+    /// it with every partition already decoded.
+    pub(crate) fn encode(block_meta: Vec<BlockMeta>, buf: &mut Vec<u8>, base: usize) -> Self {
+        let partitions_offset = base + buf.len();
+        let mut partitions = Vec::new();
+        let mut block_meta = block_meta.into_iter().peekable();
+        let mut num_blocks = 0;
+        while block_meta.peek().is_some() {
+            let metas = block_meta
+                .by_ref()
+                .take(BLOCKS_PER_PARTITION)
+                .collect::<Vec<_>>();
+            num_blocks += metas.len();
+            let start = buf.len();
+            BlockMeta::encode_block_meta(&metas, buf);
+            partitions.push(IndexPartition {
+                summary: Self::summarize(&metas[0], &metas[metas.len() - 1]),
+                offset: base + start,
+                len: buf.len() - start,
+                metas: OnceLock::from(metas),
+            });
+        }
+        buf.put_u64(partitions_offset as u64);
+
+        let index_offset = base + buf.len();
+        buf.put_u32(num_blocks as u32);
+        buf.put_u32(BLOCKS_PER_PARTITION as u32);
+        for partition in &partitions {
+            buf.put_u32(partition.len as u32);
+        }
+        let summaries = partitions
+            .iter()
+            .map(|partition| partition.summary.clone())
+            .collect::<Vec<_>>();
+        BlockMeta::encode_block_meta(&summaries, buf);
+        buf.put_u64(index_offset as u64);
+        Self {
+            num_blocks,
+            blocks_per_partition: BLOCKS_PER_PARTITION,
+            partitions,
+        }
+    }
+
+    /// The size of the index over `block_meta` as written by `encode`, including the offsets that
+    /// follow the partitions and the top-level index.
+    pub(crate) fn encoded_len(block_meta: &[&BlockMeta]) -> usize {
+        let mut len = 0;
+        let mut prev_summary = None;
+        for metas in block_meta.chunks(BLOCKS_PER_PARTITION) {
+            // The header of the partition, and its length in the top-level index.
+            len += 3 * SIZEOF_U32;
+            let mut prev = None;
+            for meta in metas {
+                len += BlockMeta::encoded_len(
+                    prev,
+                    meta.offset,
+                    meta.first_key.raw_ref(),
+                    meta.last_key.raw_ref(),
+                );
+                prev = Some(meta);
+            }
+            let summary = Self::summarize(metas[0], metas[metas.len() - 1]);
+            len += BlockMeta::encoded_len(
+                prev_summary.as_ref(),
+                summary.offset,
+                summary.first_key.raw_ref(),
+                summary.last_key.raw_ref(),
+            );
+            prev_summary = Some(summary);
+        }
+        len + SIZEOF_U64 + 4 * SIZEOF_U32 + SIZEOF_U64
+    }
+
+    /// Decode the top-level index from `buf`, for partitions stored in `partitions`.
+    pub(crate) fn decode(mut buf: &[u8], partitions: Range<usize>) -> Result<Self> {
+        if buf.remaining() < 2 * SIZEOF_U32 {
+            bail!("truncated block index: missing header");
+        }
+        let num_blocks = buf.get_u32() as usize;
+        let blocks_per_partition = buf.get_u32() as usize;
+        if num_blocks == 0 {
+            bail!("block index has no blocks");
+        }
+        if blocks_per_partition == 0 {
+            bail!("block index has empty partitions");
+        }
+        let num_partitions = num_blocks.div_ceil(blocks_per_partition);
+        if buf.remaining() < num_partitions * SIZEOF_U32 {
+            bail!(
+                "truncated block index: missing the lengths of {} partitions",
+                num_partitions
+            );
+        }
+        let mut offset = partitions.start;
+        let mut locations = Vec::with_capacity(num_partitions);
+        for _ in 0..num_partitions {
+            let len = buf.get_u32() as usize;
+            locations.push((offset, len));
+            offset += len;
+        }
+        if offset != partitions.end {
+            bail!(
+                "block index partitions end at {}, but the section ends at {}",
+                offset,
+                partitions.end
+            );
+        }
+        let summaries = BlockMeta::decode_block_meta(buf)?;
+        if summaries.len() != num_partitions {
+            bail!(
+                "block index has {} partition summaries for {} partitions",
+                summaries.len(),
+                num_partitions
+            );
+        }
+        let partitions = summaries
+            .into_iter()
+            .zip(locations)
+            .map(|(summary, (offset, len))| IndexPartition {
+                summary,
+                offset,
+                len,
+                metas: OnceLock::new(),
+            })
+            .collect();
+        Ok(Self {
+            num_blocks,
+            blocks_per_partition,
+            partitions,
+        })
+    }
+
+    /// The number of data blocks.
+    pub(crate) fn len(&self) -> usize {
+        self.num_blocks
+    }
+
+    /// The summary of the first partition, with the first key of the SST.
+    pub(crate) fn first_summary(&self) -> Option<&BlockMeta> {
+        self.partitions.first().map(|partition| &partition.summary)
+    }
+
+    /// The summary of the last partition, with the last key of the SST.
+    pub(crate) fn last_summary(&self) -> Option<&BlockMeta> {
+        self.partitions.last().map(|partition| &partition.summary)
+    }
+
+    /// The number of partitions.
+    pub(crate) fn num_partitions(&self) -> usize {
+        self.partitions.len()
+    }
+
+    /// The partition holding block `block_idx`, and the index of the block within it.
+    pub(crate) fn locate(&self, block_idx: usize) -> (usize, usize) {
+        (
+            block_idx / self.blocks_per_partition,
+            block_idx % self.blocks_per_partition,
+        )
+    }
+
+    /// The blocks in `partition`.
+    pub(crate) fn partition_blocks(&self, partition: usize) -> Range<usize> {
+        let start = partition * self.blocks_per_partition;
+        start..(start + self.blocks_per_partition).min(self.num_blocks)
+    }
+
+    /// The first partition whose last key is not smaller than `key`, or the last partition if all
+    /// of them are.
+    pub(crate) fn find_partition(&self, key: KeySlice) -> usize {
+        self.partitions
+            .partition_point(|partition| partition.summary.last_key.as_key_slice() < key)
+            .min(self.partitions.len() - 1)
+    }
+
+    /// The offset of the first block in `partition`, known without reading the partition.
+    pub(crate) fn first_block_offset(&self, partition: usize) -> Option<usize> {
+        self.partitions
+            .get(partition)
+            .map(|partition| partition.summary.offset)
+    }
+
+    /// The location of `partition` in the file, as an offset and a length.
+    pub(crate) fn location(&self, partition: usize) -> (usize, usize) {
+        let partition = &self.partitions[partition];
+        (partition.offset, partition.len)
+    }
+
+    /// The metas of `partition` if it has been decoded.
+    pub(crate) fn loaded(&self, partition: usize) -> Option<&[BlockMeta]> {
+        self.partitions[partition].metas.get().map(Vec::as_slice)
+    }
+
+    /// Decode `partition` from `buf` and keep its metas. They are checked against the summary of
+    /// the partition, and must end before the next partition, or `data_end` for the last one.
+    pub(crate) fn load(
+        &self,
+        partition: usize,
+        buf: &[u8],
+        data_end: usize,
+    ) -> Result<&[BlockMeta]> {
+        let metas = BlockMeta::decode_block_meta(buf)?;
+        let expected = self.partition_blocks(partition).len();
+        if metas.len() != expected {
+            bail!(
+                "index partition has {} blocks instead of {}",
+                metas.len(),
+                expected
+            );
+        }
+        let summary = &self.partitions[partition].summary;
+        let (first, last) = (&metas[0], &metas[metas.len() - 1]);
+        if first.offset != summary.offset
+            || first.first_key != summary.first_key
+            || last.last_key != summary.last_key
+        {
+            bail!("index partition does not match its summary in the top-level index");
+        }
+        let end = self.first_block_offset(partition + 1).unwrap_or(data_end);
+        if last.offset >= end {
+            bail!(
+                "index partition has a block at offset {} after its end {}",
+                last.offset,
+                end
+            );
+        }
+        // Another thread may have loaded the partition in the meantime, with the same metas.
+        let _ = self.partitions[partition].metas.set(metas);
+        Ok(self.loaded(partition).unwrap())
+    }
+
+    /// The memory pinned by the index: the summaries of the partitions, and the metas of the
+    /// partitions decoded so far, each with its keys.
+    pub(crate) fn memory_usage(&self) -> usize {
+        let meta_size = |meta: &BlockMeta| {
+            std::mem::size_of::<BlockMeta>() + meta.first_key.len() + meta.last_key.len()
+        };
+        self.partitions
+            .iter()
+            .map(|partition| {
+                meta_size(&partition.summary)
+                    + partition
+                        .metas
+                        .get()
+                        .map_or(0, |metas| metas.iter().map(meta_size).sum())
+            })
+            .sum()
+    }
+}
@@ -1,13 +1,24 @@
-use std::path::Path;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use bytes::{BufMut, Bytes};
 
-use super::{bloom::Bloom, BlockMeta, FileObject, SsTable};
+use super::{
+    block_index::BlockIndex,
+    bloom::{Bloom, BloomCounters, BloomOptions, FarmHasher, Hasher},
+    compression::{CompressedBlock, CompressionPool},
+    sync_parent_dir, tmp_path_of, BlockMeta, Compression, FileObject, SsTable, SsTableProperties,
+    SST_FORMAT_VERSION,
+};
 use crate::{
     block::BlockBuilder,
-    key::{KeyBytes, KeySlice},
+    iterators::StorageIterator,
+    key::{compare_with_ts, KeyBytes, KeySlice, TS_ENABLED},
     lsm_storage::BlockCache,
 };
 
@@ -20,6 +31,66 @@ pub struct SsTableBuilder {
     pub(crate) meta: Vec<BlockMeta>,
     block_size: usize,
     key_hashes: Vec<u32>,
+    /// Hashes of the distinct key prefixes, if the bloom filter is configured with a prefix length.
+    prefix_hashes: Vec<u32>,
+    properties: SsTableProperties,
+    bloom_options: BloomOptions,
+    /// The hash function of the bloom filter.
+    hasher: Arc<dyn Hasher>,
+    compression: Compression,
+    streaming: Option<StreamingWriter>,
+    /// The workers compressing the data blocks, if compression runs in parallel.
+    compression_pool: Option<CompressionPool>,
+    /// Blocks compressed out of order, waiting for the blocks before them.
+    compressed: BTreeMap<usize, CompressedBlock>,
+    /// The uncompressed size of the blocks submitted to the pool but not yet in `data`.
+    in_flight_size: usize,
+    /// The number of blocks moved into `data` so far. Metas after these have no offset yet.
+    num_appended: usize,
+    /// The interval of the sparse index in each data block, if they get one.
+    sparse_index_interval: Option<usize>,
+}
+
+/// Statistics about a freshly built SST, e.g., for compaction metrics.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SsTableBuildStats {
+    /// Number of data blocks.
+    pub num_blocks: usize,
+    /// Number of key-value pairs.
+    pub num_entries: usize,
+    /// Total size of the data blocks in bytes.
+    pub data_size: usize,
+    /// Size of the encoded bloom filter in bytes, or 0 if the SST has none.
+    pub bloom_size: usize,
+    /// The largest timestamp of all keys.
+    pub max_ts: u64,
+}
+
+/// Whether the raw key `key` may be added to an SST right after `prev`: either it is larger, or,
+/// with timestamps, it is an older version of the same user key. With timestamps, the keys are
+/// compared with `compare_with_ts`, as the big-endian timestamp suffix sorts the wrong way as bytes.
+pub(crate) fn is_next_key(prev: &[u8], key: &[u8], ts_enabled: bool) -> bool {
+    let ordering = if ts_enabled {
+        compare_with_ts(key, prev)
+    } else {
+        key.cmp(prev)
+    };
+    ordering == Ordering::Greater
+}
+
+/// Capacity of the write buffer used by a streaming builder.
+const STREAMING_BUFFER_SIZE: usize = 1 << 20;
+
+/// Writes the finished data blocks of a streaming builder to a temporary file, which is renamed to
+/// the output path once the SST is complete.
+struct StreamingWriter {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    tmp_path: PathBuf,
+    /// Number of bytes handed to `writer` so far.
+    written: usize,
+    /// The first error hit when writing a block, reported by `build_streaming`.
+    error: Option<std::io::Error>,
 }
 
 impl SsTableBuilder {
@@ -34,34 +105,146 @@ impl SsTableBuilder {
             meta: Vec::new(),
             block_size,
             key_hashes: Vec::new(),
+            prefix_hashes: Vec::new(),
+            properties: SsTableProperties::default(),
+            bloom_options: BloomOptions::default(),
+            hasher: Arc::new(FarmHasher),
+            compression: Compression::default(),
+            streaming: None,
+            compression_pool: None,
+            compressed: BTreeMap::new(),
+            in_flight_size: 0,
+            num_appended: 0,
+            sparse_index_interval: None,
+        }
+    }
+
+    /// Create a builder that writes each data block out as soon as it is finished, instead of
+    /// keeping the whole SST in memory. Finish it with `build_streaming`. Like
+    /// `FileObject::create_atomic`, the blocks go to `{path}.tmp`, so `path` only ever holds the
+    /// complete file.
+    pub fn new_streaming(block_size: usize, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let tmp_path = tmp_path_of(path);
+        let file = File::create(&tmp_path)
+            .with_context(|| format!("failed to create sst file {:?}", tmp_path))?;
+        let mut builder = Self::new(block_size);
+        builder.streaming = Some(StreamingWriter {
+            writer: BufWriter::with_capacity(STREAMING_BUFFER_SIZE, file),
+            path: path.to_path_buf(),
+            tmp_path,
+            written: 0,
+            error: None,
+        });
+        Ok(builder)
+    }
+
+    /// Store a sparse index of every `interval`-th entry in each data block, which speeds up seeks
+    /// within large blocks. See `BlockBuilder::with_sparse_index`.
+    pub fn with_block_sparse_index(mut self, interval: usize) -> Self {
+        self.sparse_index_interval = Some(interval);
+        self.builder = self.new_block_builder();
+        self
+    }
+
+    fn new_block_builder(&self) -> BlockBuilder {
+        let builder = BlockBuilder::new(self.block_size);
+        match self.sparse_index_interval {
+            Some(interval) => builder.with_sparse_index(interval),
+            None => builder,
         }
     }
 
-    /// Adds a key-value pair to SSTable.
+    /// Configure the bloom filter of the SST, or disable it with `enabled: false`.
+    pub fn with_bloom(mut self, bloom_options: BloomOptions) -> Self {
+        self.bloom_options = bloom_options;
+        self
+    }
+
+    /// Set the target false positive rate of the bloom filter, which must be in (0, 1).
+    pub fn with_bloom_fp_rate(mut self, rate: f64) -> Self {
+        assert!(
+            rate > 0.0 && rate < 1.0,
+            "bloom false positive rate must be in (0, 1), got {}",
+            rate
+        );
+        self.bloom_options.false_positive_rate = rate;
+        self
+    }
+
+    /// Compress each data block with `compression`. Blocks that do not get smaller are stored
+    /// uncompressed.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Compress the data blocks on `num_workers` threads while the builder keeps accepting
+    /// entries. The blocks are still written in order, so the SST is the same as when compressing
+    /// them one by one, which is what happens with 0 workers, the default.
+    pub fn with_compression_workers(mut self, num_workers: usize) -> Self {
+        self.compression_pool = (num_workers > 0).then(|| CompressionPool::new(num_workers));
+        self
+    }
+
+    /// Hash the keys added to the bloom filter with `hasher` instead of `FarmHasher`. Only the id of
+    /// the hasher is recorded in the SST, so the hasher must be passed again in
+    /// `SsTableOpenOptions` when reopening, or the SST is opened without its bloom filter.
+    pub fn with_hasher(mut self, hasher: Arc<dyn Hasher>) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Skip building the bloom filter, e.g., for SSTs that are only ever scanned.
+    pub fn disable_bloom(mut self) -> Self {
+        self.bloom_options.enabled = false;
+        self
+    }
+
+    /// Adds a key-value pair to SSTable. Keys must be added in strictly ascending order, except
+    /// that versions of the same key may follow each other in descending timestamp order once
+    /// timestamps are enabled.
     ///
     /// Note: You should split a new block when the current block is full.(`std::mem::replace` may
     /// be helpful here)
-    pub fn add(&mut self, key: KeySlice, value: &[u8]) {
+    pub fn add(&mut self, key: KeySlice, value: &[u8]) -> Result<()> {
+        if self.num_entries() > 0 && !is_next_key(&self.last_key, key.raw_ref(), TS_ENABLED) {
+            bail!(
+                "keys must be added to SsTableBuilder in ascending order: {:?} added after {:?}",
+                Bytes::copy_from_slice(key.raw_ref()),
+                Bytes::copy_from_slice(&self.last_key),
+            );
+        }
         let not_full = self.builder.add(key, value);
         if !not_full {
-            let block_meta = BlockMeta {
-                offset: self.data.len(),
-                first_key: KeyBytes::from_bytes(Bytes::from(self.builder.first_key())),
-                last_key: KeyBytes::from_bytes(Bytes::from(self.builder.last_key())),
-            };
-            self.meta.push(block_meta);
-            let block = self.builder.build();
-            self.data.extend(block.encode());
-            let _ = std::mem::replace(&mut self.builder, BlockBuilder::new(self.block_size));
+            self.finish_block();
             let _ = self.builder.add(key, value);
         }
-        self.key_hashes.push(farmhash::fingerprint32(key.raw_ref()));
-        if self.first_key.is_empty() || self.first_key > self.builder.first_key() {
-            self.first_key = self.builder.first_key();
+        if self.num_entries() == 0 {
+            self.first_key = key.raw_ref().to_vec();
         }
-        if self.last_key.is_empty() || self.last_key < self.builder.last_key() {
-            self.last_key = self.builder.last_key();
+        // Only the user key is hashed, so that lookups at any read timestamp can use the filter.
+        self.key_hashes.push(self.hasher.hash(key.key_ref()));
+        if let Some(prefix) = self
+            .bloom_options
+            .prefix_len
+            .and_then(|prefix_len| key.key_ref().get(..prefix_len))
+        {
+            // Keys are sorted, so keys sharing a prefix are adjacent and it is hashed only once.
+            let hash = self.hasher.hash(prefix);
+            if self.prefix_hashes.last() != Some(&hash) {
+                self.prefix_hashes.push(hash);
+            }
         }
+        self.properties.add(key, value);
+        self.last_key.clear();
+        self.last_key.extend(key.raw_ref());
+        Ok(())
+    }
+
+    /// The number of key-value pairs added so far.
+    pub fn num_entries(&self) -> usize {
+        self.key_hashes.len()
     }
 
     /// Get the estimated size of the SSTable.
@@ -69,51 +252,388 @@ impl SsTableBuilder {
     /// Since the data blocks contain much more data than meta blocks, just return the size of data
     /// blocks here.
     pub fn estimated_size(&self) -> usize {
-        self.data.len()
+        self.data_offset() + self.in_flight_size
+    }
+
+    /// Estimate the size of the SST file if it were built now, including the block index, bloom
+    /// filter and properties sections, so that callers can cap the size of the files they write.
+    /// Compressed blocks that are still being built are counted at their uncompressed size.
+    pub fn estimated_file_size(&self) -> usize {
+        const U32: usize = std::mem::size_of::<u32>();
+        const U64: usize = std::mem::size_of::<u64>();
+        let mut data_size = self.estimated_size();
+        let mut pending = None;
+        if !self.builder.is_empty() {
+            data_size += self.builder.estimated_size();
+            pending = Some(BlockMeta {
+                offset: self.data_offset(),
+                first_key: KeyBytes::from_bytes(Bytes::from(self.builder.first_key())),
+                last_key: KeyBytes::from_bytes(Bytes::from(self.builder.last_key())),
+                compression: self.compression,
+            });
+        }
+        let block_meta = self.meta.iter().chain(&pending).collect::<Vec<_>>();
+        let meta_size = BlockIndex::encoded_len(&block_meta);
+        // The bloom section is a flag byte, followed by the hasher id and the prefix length if the
+        // filter has prefixes, the version byte, filter bits and `k` if present, and a checksum.
+        let bloom_size = 1
+            + U32
+            + self.bloom_bits_per_key().map_or(0, |bits_per_key| {
+                let num_hashes = self.key_hashes.len() + self.prefix_hashes.len();
+                let prefix_len_size = if self.bloom_options.prefix_len.is_some() {
+                    U32
+                } else {
+                    0
+                };
+                U32 + prefix_len_size
+                    + 1
+                    + Bloom::filter_len(self.bloom_options.kind, num_hashes, bits_per_key)
+                    + 1
+            });
+        data_size + meta_size + bloom_size + U64 + SsTableProperties::ENCODED_SIZE + U64 + U32
+    }
+
+    /// The bits per key of the bloom filter to build, or `None` if the SST gets no bloom filter.
+    fn bloom_bits_per_key(&self) -> Option<usize> {
+        if !self.bloom_options.enabled || self.key_hashes.len() < self.bloom_options.min_num_keys {
+            return None;
+        }
+        Some(self.bloom_options.bits_per_key.unwrap_or_else(|| {
+            Bloom::bloom_bits_per_key(
+                self.key_hashes.len(),
+                self.bloom_options.false_positive_rate,
+            )
+        }))
+    }
+
+    /// The file offset at which the next data block will be written.
+    fn data_offset(&self) -> usize {
+        self.streaming.as_ref().map_or(0, |s| s.written) + self.data.len()
+    }
+
+    /// Finish the current block even if it is not full, so that the next key starts a new block.
+    /// Does nothing if the current block is empty. The block is moved into `data`, and written out
+    /// if the builder is streaming. With compression workers, the block is handed to them instead,
+    /// and moved into `data` once it and the blocks before it are compressed.
+    pub fn finish_block(&mut self) {
+        if self.builder.is_empty() {
+            return;
+        }
+        let first_key = KeyBytes::from_bytes(Bytes::from(self.builder.first_key()));
+        let last_key = KeyBytes::from_bytes(Bytes::from(self.builder.last_key()));
+        let encoded = self.builder.build().encode();
+        self.builder = self.new_block_builder();
+        // The offset and the compression are set when the block is appended.
+        self.meta.push(BlockMeta {
+            offset: 0,
+            first_key,
+            last_key,
+            compression: Compression::None,
+        });
+        match &self.compression_pool {
+            Some(pool) => {
+                self.in_flight_size += encoded.len();
+                // If the workers exited, the block is lost, and waiting for it fails the build.
+                if pool
+                    .submit(self.meta.len() - 1, self.compression, encoded)
+                    .is_ok()
+                {
+                    // Never fails without waiting.
+                    let _ = self.append_compressed(false);
+                }
+            }
+            None => {
+                let (compression, block_data) = self.compression.compress_block(&encoded);
+                self.append_block(compression, block_data);
+            }
+        }
+    }
+
+    /// Move the next block into `data`, and write it out if the builder is streaming.
+    fn append_block(&mut self, compression: Compression, block_data: Vec<u8>) {
+        let offset = self.data_offset();
+        let meta = &mut self.meta[self.num_appended];
+        meta.offset = offset;
+        meta.compression = compression;
+        self.num_appended += 1;
+        self.data.extend(block_data);
+
+        if let Some(streaming) = &mut self.streaming {
+            if streaming.error.is_none() {
+                if let Err(e) = streaming.writer.write_all(&self.data) {
+                    streaming.error = Some(e);
+                }
+            }
+            streaming.written += self.data.len();
+            self.data.clear();
+        }
+    }
+
+    /// Append the blocks compressed by the workers in order. If `wait` is set, wait until every
+    /// submitted block is appended; otherwise, stop at the first block that is not ready.
+    fn append_compressed(&mut self, wait: bool) -> Result<()> {
+        loop {
+            while let Some(block) = self.compressed.remove(&self.num_appended) {
+                self.in_flight_size -= block.encoded_len;
+                self.append_block(block.compression, block.data);
+            }
+            let Some(pool) = &self.compression_pool else {
+                return Ok(());
+            };
+            if self.num_appended == self.meta.len() {
+                return Ok(());
+            }
+            let block = if wait {
+                pool.recv()?
+            } else {
+                match pool.try_recv() {
+                    Some(block) => block,
+                    None => return Ok(()),
+                }
+            };
+            self.compressed.insert(block.block_idx, block);
+        }
+    }
+
+    /// Encode the sections following the data blocks: block index, bloom filter and properties.
+    /// `base` is the file offset at which `buf` starts. Returns the bloom filter and the block
+    /// index, which takes the block metas.
+    fn encode_tail(&mut self, buf: &mut Vec<u8>, base: usize) -> (Option<Bloom>, BlockIndex) {
+        let block_index = BlockIndex::encode(std::mem::take(&mut self.meta), buf, base);
+
+        let bloom = self.bloom_bits_per_key().map(|bits_per_key| {
+            let hashes = [&self.key_hashes[..], &self.prefix_hashes[..]].concat();
+            let mut bloom = Bloom::build_from_key_hashes_with_kind(
+                &hashes,
+                bits_per_key,
+                self.bloom_options.kind,
+            )
+            .with_hasher(self.hasher.clone());
+            bloom.prefix_len = self.bloom_options.prefix_len;
+            bloom
+        });
+        let bloom_offset = base + buf.len();
+        let bloom_start = buf.len();
+        match &bloom {
+            Some(
+                bloom @ Bloom {
+                    prefix_len: Some(prefix_len),
+                    ..
+                },
+            ) => {
+                buf.put_u8(2);
+                buf.put_u32(bloom.hasher().id());
+                buf.put_u32(*prefix_len as u32);
+                bloom.encode(buf);
+            }
+            Some(bloom) => {
+                buf.put_u8(1);
+                buf.put_u32(bloom.hasher().id());
+                bloom.encode(buf);
+            }
+            None => buf.put_u8(0),
+        }
+        let checksum = crc32fast::hash(&buf[bloom_start..]);
+        buf.put_u32(checksum);
+        buf.put_u64(bloom_offset as u64);
+        let properties_offset = base + buf.len();
+        self.properties.encode(buf);
+        buf.put_u64(properties_offset as u64);
+        buf.put_u32(SST_FORMAT_VERSION);
+        (bloom, block_index)
+    }
+
+    fn into_sst(
+        self,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+        block_meta_offset: usize,
+        block_index: BlockIndex,
+        bloom: Option<Bloom>,
+    ) -> SsTable {
+        SsTable {
+            file,
+            block_index,
+            block_meta_offset,
+            id,
+            block_cache,
+            first_key: KeyBytes::from_bytes(Bytes::copy_from_slice(&self.first_key)),
+            last_key: KeyBytes::from_bytes(Bytes::copy_from_slice(&self.last_key)),
+            bloom,
+            bloom_counters: BloomCounters::default(),
+            negative_cache: None,
+            max_ts: self.properties.max_ts,
+            properties: self.properties,
+        }
     }
 
     /// Builds the SSTable and writes it to the given path. Use the `FileObject` structure to manipulate the disk objects.
+    ///
+    /// Fails if no key-value pair was added, as an SST cannot be empty.
     pub fn build(
         mut self,
         id: usize,
         block_cache: Option<Arc<BlockCache>>,
         path: impl AsRef<Path>,
     ) -> Result<SsTable> {
-        let block_meta = BlockMeta {
-            offset: self.data.len(),
-            first_key: KeyBytes::from_bytes(Bytes::from(self.builder.first_key())),
-            last_key: KeyBytes::from_bytes(Bytes::from(self.builder.last_key())),
+        if self.streaming.is_some() {
+            bail!("a streaming builder must be finished with `build_streaming`");
+        }
+        if self.num_entries() == 0 {
+            bail!("cannot build an empty SST");
+        }
+        let (data, block_meta_offset, block_index, bloom) = self.encode()?;
+        let file_object = FileObject::create(path.as_ref(), data)?;
+        Ok(self.into_sst(
+            id,
+            block_cache,
+            file_object,
+            block_meta_offset,
+            block_index,
+            bloom,
+        ))
+    }
+
+    /// Like `build`, but keeps the SST in memory instead of writing it to a file, e.g., for tests
+    /// and ephemeral tables. Reads slice the in-memory buffer.
+    pub fn build_in_memory(mut self, id: usize) -> Result<SsTable> {
+        if self.streaming.is_some() {
+            bail!("a streaming builder must be finished with `build_streaming`");
+        }
+        if self.num_entries() == 0 {
+            bail!("cannot build an empty SST");
+        }
+        let (data, block_meta_offset, block_index, bloom) = self.encode()?;
+        let file_object = FileObject::from_bytes(data.into());
+        Ok(self.into_sst(id, None, file_object, block_meta_offset, block_index, bloom))
+    }
+
+    /// Encode the whole SST into a buffer. Returns the buffer, the offset of the block meta, the
+    /// block index and the bloom filter.
+    fn encode(&mut self) -> Result<(Vec<u8>, usize, BlockIndex, Option<Bloom>)> {
+        self.finish_block();
+        self.append_compressed(true)?;
+        let block_meta_offset = self.data.len();
+        let mut data = std::mem::take(&mut self.data);
+        let (bloom, block_index) = self.encode_tail(&mut data, 0);
+        Ok((data, block_meta_offset, block_index, bloom))
+    }
+
+    /// Like `build`, but also returns statistics about the SST.
+    pub fn build_with_stats(
+        self,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        path: impl AsRef<Path>,
+    ) -> Result<(SsTable, SsTableBuildStats)> {
+        let sst = self.build(id, block_cache, path)?;
+        let stats = SsTableBuildStats {
+            num_blocks: sst.num_of_blocks(),
+            num_entries: sst.properties().num_entries as usize,
+            data_size: sst.block_meta_offset,
+            bloom_size: sst.bloom.as_ref().map_or(0, |bloom| bloom.filter.len() + 1),
+            max_ts: sst.max_ts(),
         };
-        self.meta.push(block_meta);
-        let block = self.builder.build();
-        self.data.extend(block.encode());
-        let _ = std::mem::replace(&mut self.builder, BlockBuilder::new(self.block_size));
-
-        let extra = self.data.len();
-        let mut data = self.data;
-        BlockMeta::encode_block_meta(&self.meta, &mut data);
-        data.extend((extra as u32).to_be_bytes());
-
-        let bloom = Bloom::build_from_key_hashes(
-            &self.key_hashes,
-            Bloom::bloom_bits_per_key(self.key_hashes.len(), 0.01),
-        );
-        let bloom_offset = data.len();
-        bloom.encode(&mut data);
-        data.put_u32(bloom_offset as u32);
+        Ok((sst, stats))
+    }
 
-        let file_object = FileObject::create(path.as_ref(), data)?;
-        Ok(SsTable {
-            file: file_object,
-            block_meta: self.meta,
-            block_meta_offset: extra,
+    /// Finishes a builder created by `new_streaming`: writes the remaining sections after the data
+    /// blocks that have already been streamed out, syncs the file, renames it to its final path,
+    /// and syncs the directory. The temporary file is removed on failure.
+    pub fn build_streaming(
+        mut self,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+    ) -> Result<SsTable> {
+        self.finish_block();
+        self.append_compressed(true)?;
+        let Some(mut streaming) = self.streaming.take() else {
+            bail!("the builder was not created by `new_streaming`");
+        };
+        if let Some(e) = streaming.error.take() {
+            drop(streaming.writer);
+            let _ = std::fs::remove_file(&streaming.tmp_path);
+            return Err(e.into());
+        }
+        if self.num_entries() == 0 {
+            drop(streaming.writer);
+            let _ = std::fs::remove_file(&streaming.tmp_path);
+            bail!("cannot build an empty SST");
+        }
+        let block_meta_offset = streaming.written;
+        let mut tail = Vec::new();
+        let (bloom, block_index) = self.encode_tail(&mut tail, block_meta_offset);
+        let StreamingWriter {
+            mut writer,
+            path,
+            tmp_path,
+            ..
+        } = streaming;
+        let write_and_rename = || -> Result<()> {
+            writer.write_all(&tail)?;
+            let file = writer.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+            std::fs::rename(&tmp_path, &path)?;
+            Ok(())
+        };
+        if let Err(e) = write_and_rename() {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(e.context(format!("failed to create sst file {:?}", path)));
+        }
+        sync_parent_dir(&path)
+            .with_context(|| format!("failed to sync the directory of sst file {:?}", path))?;
+        let file_object = FileObject::open(&path)?;
+        Ok(self.into_sst(
             id,
             block_cache,
-            first_key: KeyBytes::from_bytes(Bytes::copy_from_slice(&self.first_key)),
-            last_key: KeyBytes::from_bytes(Bytes::copy_from_slice(&self.last_key)),
-            bloom: Some(bloom),
-            max_ts: 0,
-        })
+            file_object,
+            block_meta_offset,
+            block_index,
+            bloom,
+        ))
+    }
+
+    /// Add the key-value pairs of `iter` until it is exhausted or, if `limit` is set, until the
+    /// estimated size of the SST reaches `limit` bytes. Like `MultiSsTableBuilder`, the budget is
+    /// checked before each key that is not another version of the last added one, so the SST ends
+    /// at a block boundary and `iter` is left at the first pair not added. Delete tombstones are
+    /// added like any other value, as they must keep shadowing older SSTs. Returns the number of
+    /// pairs added.
+    pub fn add_from_iter<I>(&mut self, iter: &mut I, limit: Option<usize>) -> Result<usize>
+    where
+        I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
+    {
+        let mut num_added = 0;
+        while iter.is_valid() {
+            if let Some(limit) = limit {
+                let key = iter.key();
+                let last_key = KeySlice::from_slice(&self.last_key);
+                if self.estimated_size() >= limit && key.key_ref() != last_key.key_ref() {
+                    break;
+                }
+            }
+            self.add(iter.key(), iter.value())?;
+            num_added += 1;
+            iter.next()?;
+        }
+        Ok(num_added)
+    }
+
+    /// Add every remaining key-value pair of `iter` and build the SSTable, e.g., to write out the
+    /// merged output of a compaction.
+    pub fn build_from_iter<I>(
+        mut self,
+        mut iter: I,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        path: impl AsRef<Path>,
+    ) -> Result<SsTable>
+    where
+        I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
+    {
+        self.add_from_iter(&mut iter, None)?;
+        self.build(id, block_cache, path)
     }
 
     #[cfg(test)]
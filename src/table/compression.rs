@@ -0,0 +1,148 @@
+use std::thread::JoinHandle;
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+
+/// The compression applied to the data blocks of an SST.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Blocks are stored as they are.
+    #[default]
+    None,
+    /// Blocks are compressed with LZ4, prefixed with their uncompressed length.
+    Lz4,
+}
+
+impl Compression {
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+        }
+    }
+
+    pub(crate) fn from_u8(tag: u8) -> Result<Self> {
+        match tag {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Lz4),
+            _ => bail!("unknown block compression {}", tag),
+        }
+    }
+
+    pub(crate) fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::None => data.to_vec(),
+            Compression::Lz4 => lz4_flex::compress_prepend_size(data),
+        }
+    }
+
+    /// Compress an encoded block, keeping it uncompressed if compression does not make it smaller.
+    /// Returns the compression actually applied with the data to write.
+    pub(crate) fn compress_block(self, encoded: &[u8]) -> (Compression, Vec<u8>) {
+        let block_data = self.compress(encoded);
+        if block_data.len() >= encoded.len() {
+            (Compression::None, encoded.to_vec())
+        } else {
+            (self, block_data)
+        }
+    }
+
+    pub(crate) fn decompress(self, data: Vec<u8>) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data),
+            Compression::Lz4 => {
+                lz4_flex::decompress_size_prepended(&data).context("failed to decompress LZ4 block")
+            }
+        }
+    }
+}
+
+/// A data block compressed by a `CompressionPool`.
+pub(crate) struct CompressedBlock {
+    /// The index of the block in the SST.
+    pub(crate) block_idx: usize,
+    /// The size of the block before compression.
+    pub(crate) encoded_len: usize,
+    pub(crate) compression: Compression,
+    pub(crate) data: Vec<u8>,
+}
+
+/// Worker threads compressing the data blocks of an SST builder. Blocks are submitted in order
+/// and may come back out of order.
+pub(crate) struct CompressionPool {
+    jobs: Option<crossbeam_channel::Sender<(usize, Compression, Bytes)>>,
+    results: crossbeam_channel::Receiver<CompressedBlock>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl CompressionPool {
+    pub(crate) fn new(num_workers: usize) -> Self {
+        // Bound the queued blocks so that a slow pool holds back the builder instead of buffering
+        // the whole SST.
+        let (jobs, job_rx) =
+            crossbeam_channel::bounded::<(usize, Compression, Bytes)>(num_workers * 2);
+        let (result_tx, results) = crossbeam_channel::unbounded();
+        let workers = (0..num_workers)
+            .map(|_| {
+                let job_rx = job_rx.clone();
+                let result_tx = result_tx.clone();
+                std::thread::spawn(move || {
+                    for (block_idx, compression, encoded) in job_rx {
+                        let (compression, data) = compression.compress_block(&encoded);
+                        let block = CompressedBlock {
+                            block_idx,
+                            encoded_len: encoded.len(),
+                            compression,
+                            data,
+                        };
+                        if result_tx.send(block).is_err() {
+                            return;
+                        }
+                    }
+                })
+            })
+            .collect();
+        Self {
+            jobs: Some(jobs),
+            results,
+            workers,
+        }
+    }
+
+    /// Queue a block for compression, waiting if all workers are busy and the queue is full. Fails
+    /// if every worker exited.
+    pub(crate) fn submit(
+        &self,
+        block_idx: usize,
+        compression: Compression,
+        encoded: Bytes,
+    ) -> Result<()> {
+        self.jobs
+            .as_ref()
+            .unwrap()
+            .send((block_idx, compression, encoded))
+            .map_err(|_| anyhow!("block compression workers exited"))
+    }
+
+    /// Wait for the next compressed block.
+    pub(crate) fn recv(&self) -> Result<CompressedBlock> {
+        self.results
+            .recv()
+            .context("block compression workers exited")
+    }
+
+    /// Get the next compressed block if one is ready.
+    pub(crate) fn try_recv(&self) -> Option<CompressedBlock> {
+        self.results.try_recv().ok()
+    }
+}
+
+impl Drop for CompressionPool {
+    fn drop(&mut self) {
+        // Closing the queue stops the workers once they finish their current block.
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::io::Write;
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use super::{SsTable, SST_FORMAT_VERSION};
+use crate::block::BlockIterator;
+
+/// The output format of `SsTable::dump`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// Plain text for reading in a terminal.
+    Human,
+    /// One JSON object per line, tagged with a `"type"` field, e.g., to be piped into `jq`.
+    Jsonl,
+}
+
+/// Escape a key or value so that it can be printed, keeping printable ASCII as it is.
+fn escape(bytes: &[u8]) -> String {
+    bytes.escape_ascii().to_string()
+}
+
+fn write_json(out: &mut dyn Write, value: Value) -> Result<()> {
+    serde_json::to_writer(&mut *out, &value)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+impl SsTable {
+    /// Print the footer fields, the properties, the bloom filter stats and the meta of every block
+    /// of the SST, for debugging.
+    pub fn dump(&self, out: &mut dyn Write, format: DumpFormat) -> Result<()> {
+        self.dump_inner(out, format, false)
+    }
+
+    /// Like `dump`, but also print every key-value pair with its size, block by block.
+    pub fn dump_with_entries(&self, out: &mut dyn Write, format: DumpFormat) -> Result<()> {
+        self.dump_inner(out, format, true)
+    }
+
+    fn dump_inner(&self, out: &mut dyn Write, format: DumpFormat, entries: bool) -> Result<()> {
+        let properties = self.properties();
+        match format {
+            DumpFormat::Human => {
+                writeln!(
+                    out,
+                    "SST {}: {} bytes, format version {}, {} blocks, block meta at offset {}",
+                    self.sst_id(),
+                    self.table_size(),
+                    SST_FORMAT_VERSION,
+                    self.num_of_blocks(),
+                    self.block_meta_offset
+                )?;
+                writeln!(
+                    out,
+                    "key range: {} .. {}",
+                    escape(self.first_key().raw_ref()),
+                    escape(self.last_key().raw_ref())
+                )?;
+                writeln!(
+                    out,
+                    "properties: {} entries, {} tombstones, {} key bytes, {} value bytes, ts {}..={}",
+                    properties.num_entries,
+                    properties.num_tombstones,
+                    properties.raw_key_size,
+                    properties.raw_value_size,
+                    properties.min_ts,
+                    properties.max_ts
+                )?;
+                match &self.bloom {
+                    Some(bloom) => writeln!(
+                        out,
+                        "bloom: {} bytes, k = {}, prefix length {:?}, {:?} layout",
+                        bloom.filter.len(),
+                        bloom.k,
+                        bloom.prefix_len,
+                        bloom.kind
+                    )?,
+                    None => writeln!(out, "bloom: none")?,
+                }
+            }
+            DumpFormat::Jsonl => {
+                write_json(
+                    out,
+                    json!({
+                        "type": "footer",
+                        "sst_id": self.sst_id(),
+                        "file_size": self.table_size(),
+                        "format_version": SST_FORMAT_VERSION,
+                        "num_blocks": self.num_of_blocks(),
+                        "block_meta_offset": self.block_meta_offset,
+                        "first_key": escape(self.first_key().raw_ref()),
+                        "last_key": escape(self.last_key().raw_ref()),
+                    }),
+                )?;
+                write_json(
+                    out,
+                    json!({
+                        "type": "properties",
+                        "num_entries": properties.num_entries,
+                        "num_tombstones": properties.num_tombstones,
+                        "raw_key_size": properties.raw_key_size,
+                        "raw_value_size": properties.raw_value_size,
+                        "min_ts": properties.min_ts,
+                        "max_ts": properties.max_ts,
+                    }),
+                )?;
+                let bloom = match &self.bloom {
+                    Some(bloom) => json!({
+                        "type": "bloom",
+                        "size": bloom.filter.len(),
+                        "k": bloom.k,
+                        "prefix_len": bloom.prefix_len,
+                        "kind": format!("{:?}", bloom.kind),
+                    }),
+                    None => json!({
+                        "type": "bloom",
+                        "size": null,
+                        "k": null,
+                        "prefix_len": null,
+                        "kind": null,
+                    }),
+                };
+                write_json(out, bloom)?;
+            }
+        }
+
+        for (block_idx, meta) in self.block_metas()?.iter().enumerate() {
+            match format {
+                DumpFormat::Human => writeln!(
+                    out,
+                    "block {}: offset {}, first key {}, last key {}, compression {:?}",
+                    block_idx,
+                    meta.offset,
+                    escape(meta.first_key.raw_ref()),
+                    escape(meta.last_key.raw_ref()),
+                    meta.compression
+                )?,
+                DumpFormat::Jsonl => write_json(
+                    out,
+                    json!({
+                        "type": "block",
+                        "block_idx": block_idx,
+                        "offset": meta.offset,
+                        "first_key": escape(meta.first_key.raw_ref()),
+                        "last_key": escape(meta.last_key.raw_ref()),
+                        "compression": format!("{:?}", meta.compression),
+                    }),
+                )?,
+            }
+            if !entries {
+                continue;
+            }
+            let mut iter = BlockIterator::create_and_seek_to_first(self.read_block(block_idx)?);
+            while iter.is_valid() {
+                let (key, value) = (iter.key(), iter.value());
+                match format {
+                    DumpFormat::Human => writeln!(
+                        out,
+                        "  {} ({} bytes) => {} ({} bytes)",
+                        escape(key.raw_ref()),
+                        key.raw_ref().len(),
+                        escape(value),
+                        value.len()
+                    )?,
+                    DumpFormat::Jsonl => write_json(
+                        out,
+                        json!({
+                            "type": "entry",
+                            "block_idx": block_idx,
+                            "key": escape(key.raw_ref()),
+                            "ts": key.ts(),
+                            "key_size": key.raw_ref().len(),
+                            "value": escape(value),
+                            "value_size": value.len(),
+                        }),
+                    )?,
+                }
+                iter.next();
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,31 +1,250 @@
+use std::collections::VecDeque;
+use std::ops::Bound;
 use std::sync::Arc;
+use std::thread::JoinHandle;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use bytes::Bytes;
 
-use super::SsTable;
-use crate::{block::BlockIterator, iterators::StorageIterator, key::KeySlice};
+use super::{Advice, ReadOptions, SsTable};
+use crate::{
+    block::{Block, BlockIterator},
+    iterators::{instrumented_iterator::IterStatsHandle, StorageIterator},
+    key::{KeyBytes, KeySlice},
+};
 
 /// An iterator over the contents of an SSTable.
 pub struct SsTableIterator {
     table: Arc<SsTable>,
     blk_iter: BlockIterator,
     blk_idx: usize,
+    /// The iterator becomes invalid once the key passes this bound.
+    end_bound: Bound<KeyBytes>,
+    /// Number of blocks fetched per read by a sequential scan, or 0 to read blocks one at a time
+    /// through the block cache.
+    readahead: usize,
+    /// Blocks read ahead of the current one, starting at block `prefetched_idx`.
+    prefetched: VecDeque<Arc<Block>>,
+    prefetched_idx: usize,
+    /// Whether a sequential scan hints the OS page cache, and the file offset up to which the
+    /// consumed blocks have been dropped from it.
+    advise: bool,
+    dropped_until: usize,
+    /// How blocks are read through the block cache when not reading ahead.
+    read_options: ReadOptions,
+    /// Whether the next block is read on a background thread when `next` enters a block, and the
+    /// pending background read with the index of its block.
+    prefetch: bool,
+    pending: Option<(usize, JoinHandle<Result<Arc<Block>>>)>,
+    /// Whether `value` returns an empty slice, for scans that only need the keys.
+    keys_only: bool,
+    /// The counters of the scan the iterator is part of, if it is instrumented.
+    stats: Option<IterStatsHandle>,
 }
 
 impl SsTableIterator {
+    fn new(table: Arc<SsTable>, blk_idx: usize, blk_iter: BlockIterator) -> Self {
+        Self {
+            table,
+            blk_iter,
+            blk_idx,
+            end_bound: Bound::Unbounded,
+            readahead: 0,
+            prefetched: VecDeque::new(),
+            prefetched_idx: 0,
+            advise: false,
+            dropped_until: 0,
+            read_options: ReadOptions::default(),
+            prefetch: false,
+            pending: None,
+            keys_only: false,
+            stats: None,
+        }
+    }
+
     /// Create a new iterator and seek to the first key-value pair in the first data block.
     pub fn create_and_seek_to_first(table: Arc<SsTable>) -> Result<Self> {
         let block = table.read_block_cached(0)?;
-        Ok(Self {
+        Ok(Self::new(
             table,
-            blk_iter: BlockIterator::create_and_seek_to_first(block),
-            blk_idx: 0,
-        })
+            0,
+            BlockIterator::create_and_seek_to_first(block),
+        ))
+    }
+
+    /// Create a new iterator and seek to the first key-value pair in the first data block. If
+    /// `prefetch` is set, whenever the iterator enters a block, the following block is read into
+    /// the block cache on a background thread, so that a sequential scan does not wait for the
+    /// disk at every block boundary.
+    pub fn create_and_seek_to_first_with_prefetch(
+        table: Arc<SsTable>,
+        prefetch: bool,
+    ) -> Result<Self> {
+        let mut iter = Self::create_and_seek_to_first(table)?;
+        iter.prefetch = prefetch;
+        iter.prefetch_block(1);
+        Ok(iter)
+    }
+
+    /// Create a new iterator for a compaction scan from the first key-value pair. Blocks already
+    /// in the block cache are used, but blocks read from the disk are not inserted into it, so that
+    /// the scan does not evict the hot blocks.
+    pub fn create_for_compaction(table: Arc<SsTable>) -> Result<Self> {
+        let read_options = ReadOptions { fill_cache: false };
+        let block = table.read_block_with_options(0, &read_options)?;
+        let mut iter = Self::new(table, 0, BlockIterator::create_and_seek_to_first(block));
+        iter.read_options = read_options;
+        Ok(iter)
+    }
+
+    /// Create a new iterator and seek to the last key-value pair in the last data block, e.g., to
+    /// scan the SST in descending order with `prev`. The iterator is invalid if the SST has no
+    /// data blocks, e.g., a meta-only one.
+    pub fn create_and_seek_to_last(table: Arc<SsTable>) -> Result<Self> {
+        let Some(blk_idx) = table.num_of_blocks().checked_sub(1) else {
+            return Ok(Self::new(table, 0, BlockIterator::create_empty()));
+        };
+        let block = table.read_block_cached(blk_idx)?;
+        Ok(Self::new(
+            table,
+            blk_idx,
+            BlockIterator::create_and_seek_to_last(block),
+        ))
+    }
+
+    /// Seek to the last key-value pair in the last data block. The iterator becomes invalid if the
+    /// SST has no data blocks.
+    pub fn seek_to_last(&mut self) -> Result<()> {
+        let Some(blk_idx) = self.table.num_of_blocks().checked_sub(1) else {
+            self.blk_idx = 0;
+            self.blk_iter = BlockIterator::create_empty();
+            return Ok(());
+        };
+        let block = self.table.read_block_cached(blk_idx)?;
+        self.record_block(&block);
+        self.blk_idx = blk_idx;
+        self.blk_iter = BlockIterator::create_and_seek_to_last(block);
+        Ok(())
+    }
+
+    /// Move to the previous key-value pair, loading the last entry of the previous block when
+    /// moving before the first entry of the current one. The iterator becomes invalid when moving
+    /// before the first key of the SST, and stays so.
+    pub fn prev(&mut self) -> Result<()> {
+        if !self.is_valid() {
+            return Ok(());
+        }
+        self.blk_iter.prev();
+        if !self.blk_iter.is_valid() && self.blk_idx > 0 {
+            self.blk_idx -= 1;
+            self.blk_iter = BlockIterator::create_and_seek_to_last(self.read_block(self.blk_idx)?);
+        }
+        Ok(())
+    }
+
+    /// Create a new iterator for a sequential scan from the first key-value pair, e.g., for
+    /// compaction. Blocks are read `readahead` at a time with a single read each, bypassing the
+    /// block cache.
+    pub fn create_sequential(table: Arc<SsTable>, readahead: usize) -> Result<Self> {
+        Self::create_sequential_inner(table, readahead, false)
+    }
+
+    /// Like `create_sequential`, but also hints the OS page cache: the data blocks are advised as
+    /// sequential when the scan starts, and the blocks already consumed are advised as not needed,
+    /// so that a compaction does not evict the hot working set of the page cache.
+    pub fn create_sequential_with_advice(table: Arc<SsTable>, readahead: usize) -> Result<Self> {
+        Self::create_sequential_inner(table, readahead, true)
+    }
+
+    fn create_sequential_inner(
+        table: Arc<SsTable>,
+        readahead: usize,
+        advise: bool,
+    ) -> Result<Self> {
+        if advise {
+            table
+                .file
+                .advise(0, table.block_meta_offset as u64, Advice::Sequential)?;
+        }
+        let readahead = readahead.max(1);
+        let end = readahead.min(table.num_of_blocks());
+        let mut prefetched = VecDeque::from(table.read_blocks(0..end)?);
+        let Some(block) = prefetched.pop_front() else {
+            bail!("SST {} has no blocks", table.sst_id());
+        };
+        let mut iter = Self::new(table, 0, BlockIterator::create_and_seek_to_first(block));
+        iter.readahead = readahead;
+        iter.prefetched = prefetched;
+        iter.prefetched_idx = 1;
+        iter.advise = advise;
+        Ok(iter)
+    }
+
+    /// Start reading block `blk_idx` on a background thread, if prefetching is enabled and the
+    /// block is within the end bound.
+    fn prefetch_block(&mut self, blk_idx: usize) {
+        if !self.prefetch || blk_idx >= self.table.num_of_blocks() {
+            return;
+        }
+        // A block meta that fails to load is reported by the read of the block instead.
+        let within_end_bound = self
+            .table
+            .block_meta(blk_idx)
+            .is_ok_and(|meta| self.within_end_bound(meta.first_key.as_key_slice()));
+        if !within_end_bound {
+            return;
+        }
+        let table = self.table.clone();
+        let read_options = self.read_options.clone();
+        let handle =
+            std::thread::spawn(move || table.read_block_with_options(blk_idx, &read_options));
+        self.pending = Some((blk_idx, handle));
+    }
+
+    /// Read block `blk_idx`, from the read-ahead window for a sequential scan, or from the pending
+    /// background read if it is prefetching this block.
+    fn read_block(&mut self, blk_idx: usize) -> Result<Arc<Block>> {
+        let block = self.read_block_uncounted(blk_idx)?;
+        self.record_block(&block);
+        Ok(block)
+    }
+
+    fn read_block_uncounted(&mut self, blk_idx: usize) -> Result<Arc<Block>> {
+        if self.readahead == 0 {
+            if let Some((pending_idx, handle)) = self.pending.take() {
+                if pending_idx == blk_idx {
+                    let Ok(block) = handle.join() else {
+                        bail!("prefetching block {} panicked", blk_idx);
+                    };
+                    return block;
+                }
+            }
+            return self
+                .table
+                .read_block_with_options(blk_idx, &self.read_options);
+        }
+        if self.prefetched.is_empty() || self.prefetched_idx != blk_idx {
+            let offset = self.table.block_meta(blk_idx)?.offset;
+            if self.advise && offset > self.dropped_until {
+                self.table.file.advise(
+                    self.dropped_until as u64,
+                    (offset - self.dropped_until) as u64,
+                    Advice::DontNeed,
+                )?;
+                self.dropped_until = offset;
+            }
+            let end = (blk_idx + self.readahead).min(self.table.num_of_blocks());
+            self.prefetched = self.table.read_blocks(blk_idx..end)?.into();
+            self.prefetched_idx = blk_idx;
+        }
+        self.prefetched_idx += 1;
+        Ok(self.prefetched.pop_front().unwrap())
     }
 
     /// Seek to the first key-value pair in the first data block.
     pub fn seek_to_first(&mut self) -> Result<()> {
         let block = self.table.read_block_cached(0)?;
+        self.record_block(&block);
         self.blk_idx = 0;
         self.blk_iter = BlockIterator::create_and_seek_to_first(block);
         Ok(())
@@ -33,11 +252,67 @@ impl SsTableIterator {
 
     /// Create a new iterator and seek to the first key-value pair which >= `key`.
     pub fn create_and_seek_to_key(table: Arc<SsTable>, key: KeySlice) -> Result<Self> {
-        let mut table_iterator = SsTableIterator::create_and_seek_to_first(table)?;
-        table_iterator.seek_to_key(key)?;
+        Self::create_and_seek_to_key_with_stats(table, key, None)
+    }
+
+    /// Like `create_and_seek_to_key`, but counts every block the iterator loads into `stats` from
+    /// the start, including any read by the seek itself. See `set_stats`.
+    pub fn create_and_seek_to_key_with_stats(
+        table: Arc<SsTable>,
+        key: KeySlice,
+        stats: Option<IterStatsHandle>,
+    ) -> Result<Self> {
+        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&table, key, stats.as_ref())?;
+        let mut iter = Self::new(table, blk_idx, blk_iter);
+        iter.stats = stats;
+        Ok(iter)
+    }
+
+    /// Create a new iterator that seeks to the first key-value pair which >= `key` and becomes
+    /// invalid once the key passes `end_bound`.
+    pub fn create_and_seek_to_key_bounded(
+        table: Arc<SsTable>,
+        key: KeySlice,
+        end_bound: Bound<KeyBytes>,
+    ) -> Result<Self> {
+        let mut table_iterator = SsTableIterator::create_and_seek_to_key(table, key)?;
+        table_iterator.set_end_bound(end_bound);
         Ok(table_iterator)
     }
 
+    /// Set the upper bound of the iterator. Blocks starting beyond the bound are never read.
+    pub fn set_end_bound(&mut self, end_bound: Bound<KeyBytes>) {
+        self.end_bound = end_bound;
+    }
+
+    /// Set whether the iterator only yields keys. In keys-only mode, `value` returns an empty slice
+    /// and `is_tombstone` still tells the deletes apart.
+    pub fn set_keys_only(&mut self, keys_only: bool) {
+        self.keys_only = keys_only;
+    }
+
+    /// Count the blocks the iterator loads and its seeks into `stats`, for an instrumented scan.
+    /// The block the iterator is positioned in when this is called is counted as loaded, but not
+    /// any other block read to get there; use `create_and_seek_to_key_with_stats` for seeks.
+    pub fn set_stats(&mut self, stats: IterStatsHandle) {
+        stats.record_block(self.blk_iter.block());
+        self.stats = Some(stats);
+    }
+
+    fn record_block(&self, block: &Block) {
+        if let Some(stats) = &self.stats {
+            stats.record_block(block);
+        }
+    }
+
+    fn within_end_bound(&self, key: KeySlice) -> bool {
+        match &self.end_bound {
+            Bound::Unbounded => true,
+            Bound::Included(end) => key <= end.as_key_slice(),
+            Bound::Excluded(end) => key < end.as_key_slice(),
+        }
+    }
+
     /// Seek to the first key-value pair which >= `key`.
     /// Note: You probably want to review the handout for detailed explanation when implementing
     /// this function.
@@ -61,28 +336,144 @@ impl SsTableIterator {
     //     Ok(())
     // }
 
-    fn seek_to_key_inner(table: &Arc<SsTable>, key: KeySlice) -> Result<(usize, BlockIterator)> {
-        let mut blk_idx = table.find_block_idx(key);
-        let mut blk_iter =
-            BlockIterator::create_and_seek_to_key(table.read_block_cached(blk_idx)?, key);
+    fn seek_to_key_inner(
+        table: &Arc<SsTable>,
+        key: KeySlice,
+        stats: Option<&IterStatsHandle>,
+    ) -> Result<(usize, BlockIterator)> {
+        let read_block = |blk_idx: usize| -> Result<Arc<Block>> {
+            let block = table.read_block_cached(blk_idx)?;
+            if let Some(stats) = stats {
+                stats.record_block(&block);
+            }
+            Ok(block)
+        };
+        if table.num_of_blocks() == 0 {
+            return Ok((0, BlockIterator::create_empty()));
+        }
+        let mut blk_idx = table.find_block_idx(key)?;
+        let mut blk_iter = BlockIterator::create_and_seek_to_key(read_block(blk_idx)?, key);
         if !blk_iter.is_valid() {
             blk_idx += 1;
             if blk_idx < table.num_of_blocks() {
-                blk_iter =
-                    BlockIterator::create_and_seek_to_first(table.read_block_cached(blk_idx)?);
+                blk_iter = BlockIterator::create_and_seek_to_first(read_block(blk_idx)?);
             }
         }
         Ok((blk_idx, blk_iter))
     }
-    /// Seek to the first key-value pair which >= `key`.
+    /// Seek to the first key-value pair which >= `key`. If the key lies within the currently
+    /// loaded block, the block is reused without looking it up again.
     pub fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
-        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&self.table, key)?;
+        if let Some(stats) = &self.stats {
+            stats.record_seek();
+        }
+        if self.blk_iter.is_valid() {
+            let meta = self.table.block_meta(self.blk_idx)?;
+            if meta.first_key.as_key_slice() <= key && key <= meta.last_key.as_key_slice() {
+                self.blk_iter.seek_to_key(key);
+                return Ok(());
+            }
+        }
+        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&self.table, key, self.stats.as_ref())?;
         self.blk_iter = blk_iter;
         self.blk_idx = blk_idx;
         Ok(())
     }
 }
 
+impl SsTableIterator {
+    /// Return the index of the data block the iterator is positioned in.
+    pub fn current_block_idx(&self) -> usize {
+        self.blk_idx
+    }
+
+    /// Turn the iterator into a std iterator over owned key-value pairs.
+    pub fn into_entries(self) -> SsTableEntries {
+        SsTableEntries {
+            iter: self,
+            has_errored: false,
+        }
+    }
+}
+
+/// A std iterator over the owned key-value pairs of an SSTable, starting from the current position
+/// of an `SsTableIterator`. Iteration stops after the first error.
+pub struct SsTableEntries {
+    iter: SsTableIterator,
+    has_errored: bool,
+}
+
+impl Iterator for SsTableEntries {
+    type Item = Result<(KeyBytes, Bytes)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.has_errored || !self.iter.is_valid() {
+            return None;
+        }
+        let key = KeyBytes::from_bytes(Bytes::copy_from_slice(self.iter.key().raw_ref()));
+        let value = Bytes::copy_from_slice(self.iter.value());
+        if let Err(e) = self.iter.next() {
+            self.has_errored = true;
+            return Some(Err(e));
+        }
+        Some(Ok((key, value)))
+    }
+}
+
+/// An iterator over the contents of an SSTable in descending order, whose `next` moves to the
+/// previous key-value pair.
+pub struct ReverseSsTableIterator(SsTableIterator);
+
+impl ReverseSsTableIterator {
+    /// Create a new iterator and seek to the last key-value pair.
+    pub fn create_and_seek_to_last(table: Arc<SsTable>) -> Result<Self> {
+        Ok(Self(SsTableIterator::create_and_seek_to_last(table)?))
+    }
+
+    /// Create a new iterator and seek to the last key-value pair which <= `key`.
+    pub fn create_and_seek_to_key(table: Arc<SsTable>, key: KeySlice) -> Result<Self> {
+        let mut iter = SsTableIterator::create_and_seek_to_key(table, key)?;
+        if !iter.is_valid() {
+            iter.seek_to_last()?;
+        } else if iter.key() > key {
+            iter.prev()?;
+        }
+        Ok(Self(iter))
+    }
+}
+
+impl StorageIterator for ReverseSsTableIterator {
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn key(&self) -> KeySlice<'_> {
+        self.0.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.0.value()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.0.is_tombstone()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.0.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.0.prev()
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.0.num_active_iterators()
+    }
+
+    fn remaining_upper_bound(&self) -> Option<usize> {
+        self.0.remaining_upper_bound()
+    }
+}
+
 impl StorageIterator for SsTableIterator {
     type KeyType<'a> = KeySlice<'a>;
 
@@ -91,14 +482,22 @@ impl StorageIterator for SsTableIterator {
         self.blk_iter.key()
     }
 
-    /// Return the `value` that's held by the underlying block iterator.
+    /// Return the `value` that's held by the underlying block iterator, or an empty slice in
+    /// keys-only mode.
     fn value(&self) -> &[u8] {
+        if self.keys_only {
+            return &[];
+        }
         self.blk_iter.value()
     }
 
+    fn is_tombstone(&self) -> bool {
+        self.blk_iter.is_tombstone()
+    }
+
     /// Return whether the current block iterator is valid or not.
     fn is_valid(&self) -> bool {
-        self.blk_iter.is_valid()
+        self.blk_iter.is_valid() && self.within_end_bound(self.blk_iter.key())
     }
 
     /// Move to the next `key` in the block.
@@ -113,15 +512,60 @@ impl StorageIterator for SsTableIterator {
     //     Ok(())
     // }
     fn next(&mut self) -> Result<()> {
+        if !self.is_valid() {
+            return Ok(());
+        }
         self.blk_iter.next();
         if !self.blk_iter.is_valid() {
             self.blk_idx += 1;
-            if self.blk_idx < self.table.num_of_blocks() {
-                self.blk_iter = BlockIterator::create_and_seek_to_first(
-                    self.table.read_block_cached(self.blk_idx)?,
-                );
+            if self.blk_idx < self.table.num_of_blocks()
+                && self.within_end_bound(
+                    self.table
+                        .block_meta(self.blk_idx)?
+                        .first_key
+                        .as_key_slice(),
+                )
+            {
+                self.blk_iter =
+                    BlockIterator::create_and_seek_to_first(self.read_block(self.blk_idx)?);
+                self.prefetch_block(self.blk_idx + 1);
+            }
+        }
+        Ok(())
+    }
+
+    /// Skip the entries within a block by their index, so that only the landing entry is decoded.
+    /// Every block on the way is still read to learn its number of entries.
+    fn advance_by(&mut self, mut n: usize) -> Result<()> {
+        while n > 0 && self.is_valid() {
+            let remaining = self.blk_iter.remaining();
+            if n <= remaining {
+                self.blk_iter.advance_by(n);
+                break;
             }
+            // Move to the last entry of the block, and step into the next block.
+            self.blk_iter.advance_by(remaining);
+            n -= remaining + 1;
+            self.next()?;
         }
         Ok(())
     }
+
+    /// An SST iterator counts as one active iterator until it is exhausted.
+    fn num_active_iterators(&self) -> usize {
+        usize::from(self.is_valid())
+    }
+
+    fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
+        SsTableIterator::seek_to_key(self, key)
+    }
+
+    /// The number of entries in the SST, as the position within it is not tracked.
+    fn remaining_upper_bound(&self) -> Option<usize> {
+        if self.is_valid() {
+            Some(self.table.properties().num_entries as usize)
+        } else {
+            Some(0)
+        }
+    }
 }
@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use super::{SsTable, SsTableBuilder};
+use crate::{key::KeySlice, lsm_storage::BlockCache};
+
+/// Builds a sorted run of SSTs from key-value pairs, starting a new SST once the current one
+/// reaches the target size, e.g., for the output of a compaction.
+pub struct MultiSsTableBuilder {
+    block_size: usize,
+    target_sst_size: usize,
+    builder: Option<SsTableBuilder>,
+    /// The last added key, so that versions of the same key never span two SSTs.
+    last_key: Vec<u8>,
+    id_alloc: Box<dyn FnMut() -> usize + Send>,
+    path_of_sst: Box<dyn Fn(usize) -> PathBuf + Send>,
+    block_cache: Option<Arc<BlockCache>>,
+    ssts: Vec<Arc<SsTable>>,
+}
+
+impl MultiSsTableBuilder {
+    /// Create a builder whose SSTs get ids from `id_alloc` and are written to `path_of_sst(id)`.
+    pub fn new(
+        block_size: usize,
+        target_sst_size: usize,
+        id_alloc: impl FnMut() -> usize + Send + 'static,
+        path_of_sst: impl Fn(usize) -> PathBuf + Send + 'static,
+    ) -> Self {
+        Self {
+            block_size,
+            target_sst_size,
+            builder: None,
+            last_key: Vec::new(),
+            id_alloc: Box::new(id_alloc),
+            path_of_sst: Box::new(path_of_sst),
+            block_cache: None,
+            ssts: Vec::new(),
+        }
+    }
+
+    /// Open the built SSTs with `block_cache`.
+    pub fn with_block_cache(mut self, block_cache: Arc<BlockCache>) -> Self {
+        self.block_cache = Some(block_cache);
+        self
+    }
+
+    /// Adds a key-value pair. The current SST is built first if it has reached the target size and
+    /// `key` is not another version of the last added key, so SSTs always end at a block boundary.
+    pub fn add(&mut self, key: KeySlice, value: &[u8]) -> Result<()> {
+        if let Some(builder) = &self.builder {
+            if builder.estimated_size() >= self.target_sst_size
+                && key.key_ref() != KeySlice::from_slice(&self.last_key).key_ref()
+            {
+                self.finish_sst()?;
+            }
+        }
+        let block_size = self.block_size;
+        self.builder
+            .get_or_insert_with(|| SsTableBuilder::new(block_size))
+            .add(key, value)?;
+        self.last_key.clear();
+        self.last_key.extend(key.raw_ref());
+        Ok(())
+    }
+
+    fn finish_sst(&mut self) -> Result<()> {
+        if let Some(builder) = self.builder.take() {
+            let id = (self.id_alloc)();
+            let sst = builder.build(id, self.block_cache.clone(), (self.path_of_sst)(id))?;
+            self.ssts.push(Arc::new(sst));
+        }
+        Ok(())
+    }
+
+    /// Build the last SST and return all SSTs in key order.
+    pub fn finish(mut self) -> Result<Vec<Arc<SsTable>>> {
+        self.finish_sst()?;
+        Ok(self.ssts)
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::{BTreeMap, HashMap};
+
+use parking_lot::Mutex;
+
+/// A small LRU set of the raw keys, timestamp included, an SST is known not to contain.
+pub(crate) struct NegativeCache {
+    capacity: usize,
+    inner: Mutex<NegativeCacheInner>,
+}
+
+#[derive(Default)]
+struct NegativeCacheInner {
+    /// The cached keys, each with the tick of its last use.
+    keys: HashMap<Vec<u8>, u64>,
+    /// The cached keys by the tick of their last use, so the least recently used one comes first.
+    by_tick: BTreeMap<u64, Vec<u8>>,
+    tick: u64,
+}
+
+impl NegativeCacheInner {
+    /// Take a fresh tick, larger than every tick in use.
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// Mark `key` as recently used if it is cached, and return whether it is.
+    fn touch(&mut self, key: &[u8]) -> bool {
+        let tick = self.next_tick();
+        let Some(last_used) = self.keys.get_mut(key) else {
+            return false;
+        };
+        let last_used = std::mem::replace(last_used, tick);
+        let key = self.by_tick.remove(&last_used).unwrap();
+        self.by_tick.insert(tick, key);
+        true
+    }
+}
+
+impl NegativeCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(NegativeCacheInner::default()),
+        }
+    }
+
+    /// Whether `key` is known to be absent, marking it as recently used if so.
+    pub(crate) fn contains(&self, key: &[u8]) -> bool {
+        self.inner.lock().touch(key)
+    }
+
+    /// Remember that `key` is absent, evicting the least recently used key if the cache is full.
+    pub(crate) fn insert(&self, key: &[u8]) {
+        if self.capacity == 0 {
+            return;
+        }
+        // Look up and insert under the same lock, so that a concurrent lookup cannot take the
+        // tick of the new key.
+        let mut inner = self.inner.lock();
+        if inner.touch(key) {
+            return;
+        }
+        if inner.keys.len() >= self.capacity {
+            if let Some((_, evicted)) = inner.by_tick.pop_first() {
+                inner.keys.remove(&evicted);
+            }
+        }
+        let tick = inner.next_tick();
+        inner.keys.insert(key.to_vec(), tick);
+        inner.by_tick.insert(tick, key.to_vec());
+    }
+}
@@ -0,0 +1,69 @@
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut};
+
+use crate::key::KeySlice;
+
+/// Statistics over all entries of an SST. They are collected by `SsTableBuilder` and stored right
+/// before the footer, so that compaction can make decisions without scanning the data blocks.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SsTableProperties {
+    /// Number of key-value pairs.
+    pub num_entries: u64,
+    /// Number of delete tombstones, i.e., entries with an empty value.
+    pub num_tombstones: u64,
+    /// Total size of all keys in bytes.
+    pub raw_key_size: u64,
+    /// Total size of all values in bytes.
+    pub raw_value_size: u64,
+    /// The smallest timestamp of all keys.
+    pub min_ts: u64,
+    /// The largest timestamp of all keys.
+    pub max_ts: u64,
+}
+
+impl SsTableProperties {
+    pub(crate) const ENCODED_SIZE: usize = 6 * std::mem::size_of::<u64>();
+
+    /// Account for a key-value pair added to the SST.
+    pub(crate) fn add(&mut self, key: KeySlice, value: &[u8]) {
+        if self.num_entries == 0 || key.ts() < self.min_ts {
+            self.min_ts = key.ts();
+        }
+        self.max_ts = self.max_ts.max(key.ts());
+        self.num_entries += 1;
+        if value.is_empty() {
+            self.num_tombstones += 1;
+        }
+        self.raw_key_size += key.len() as u64;
+        self.raw_value_size += value.len() as u64;
+    }
+
+    /// Encode the properties to a buffer.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u64(self.num_entries);
+        buf.put_u64(self.num_tombstones);
+        buf.put_u64(self.raw_key_size);
+        buf.put_u64(self.raw_value_size);
+        buf.put_u64(self.min_ts);
+        buf.put_u64(self.max_ts);
+    }
+
+    /// Decode the properties from a buffer.
+    pub fn decode(mut buf: &[u8]) -> Result<Self> {
+        if buf.len() != Self::ENCODED_SIZE {
+            bail!(
+                "invalid properties section: expected {} bytes, got {}",
+                Self::ENCODED_SIZE,
+                buf.len()
+            );
+        }
+        Ok(Self {
+            num_entries: buf.get_u64(),
+            num_tombstones: buf.get_u64(),
+            raw_key_size: buf.get_u64(),
+            raw_value_size: buf.get_u64(),
+            min_ts: buf.get_u64(),
+            max_ts: buf.get_u64(),
+        })
+    }
+}
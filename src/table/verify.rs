@@ -0,0 +1,255 @@
+use anyhow::{bail, Result};
+
+use super::SsTable;
+use crate::block::{BLOCK_SPARSE_INDEX_FLAG, BLOCK_TS_FLAG, SIZEOF_U16, SIZEOF_U64};
+use crate::key::KeySlice;
+
+/// A problem found by `SsTable::verify`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyViolation {
+    /// Index of the data block where the problem was found.
+    pub block_idx: usize,
+    /// Offset in the file of the offending block or entry.
+    pub offset: usize,
+    pub message: String,
+}
+
+/// The result of scanning an SST with `SsTable::verify`.
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// Number of entries in each data block.
+    pub block_entries: Vec<usize>,
+    pub violations: Vec<VerifyViolation>,
+}
+
+impl VerifyReport {
+    /// Whether no violation was found.
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Turn the first violation, if any, into an error naming the offending block.
+    pub fn into_result(self) -> Result<()> {
+        if let Some(violation) = self.violations.first() {
+            bail!(
+                "block {} at offset {} is corrupted: {}",
+                violation.block_idx,
+                violation.offset,
+                violation.message
+            );
+        }
+        Ok(())
+    }
+
+    fn violation(&mut self, block_idx: usize, offset: usize, message: String) {
+        self.violations.push(VerifyViolation {
+            block_idx,
+            offset,
+            message,
+        });
+    }
+}
+
+/// Decode the `(offset, key)` of every entry in an encoded block, checking every length against the
+/// block boundaries instead of trusting them like `Block::decode` does.
+fn decode_keys(data: &[u8]) -> std::result::Result<Vec<(usize, &[u8])>, String> {
+    let read_u16 = |pos: usize| u16::from_be_bytes([data[pos], data[pos + 1]]) as usize;
+    if data.len() < SIZEOF_U16 {
+        return Err(format!("block of {} bytes is too short", data.len()));
+    }
+    let num_offsets = read_u16(data.len() - SIZEOF_U16);
+    let ts_len = if num_offsets & BLOCK_TS_FLAG as usize != 0 {
+        SIZEOF_U64
+    } else {
+        0
+    };
+    let has_sparse_index = num_offsets & BLOCK_SPARSE_INDEX_FLAG as usize != 0;
+    let num_offsets = num_offsets & !((BLOCK_TS_FLAG | BLOCK_SPARSE_INDEX_FLAG) as usize);
+    let offsets_start = data
+        .len()
+        .checked_sub(SIZEOF_U16 * (num_offsets + 1))
+        .ok_or_else(|| {
+            format!(
+                "block of {} bytes cannot hold {} offsets",
+                data.len(),
+                num_offsets
+            )
+        })?;
+    // The entries end before the sparse index and its length, if the block has one.
+    let data_end = if has_sparse_index {
+        let sparse_len = match offsets_start.checked_sub(SIZEOF_U16) {
+            Some(pos) => read_u16(pos),
+            None => return Err("block is too short for its sparse index".to_string()),
+        };
+        offsets_start
+            .checked_sub(SIZEOF_U16 * (sparse_len + 1))
+            .ok_or_else(|| {
+                format!(
+                    "block of {} bytes cannot hold a sparse index of {} entries",
+                    data.len(),
+                    sparse_len
+                )
+            })?
+    } else {
+        offsets_start
+    };
+    let mut keys = Vec::with_capacity(num_offsets.saturating_sub(1));
+    for idx in 0..num_offsets.saturating_sub(1) {
+        let offset = read_u16(offsets_start + idx * SIZEOF_U16);
+        if offset + SIZEOF_U16 > data_end {
+            return Err(format!(
+                "entry {} at offset {} is out of bounds",
+                idx, offset
+            ));
+        }
+        let key_len = read_u16(offset);
+        let key_end = offset + SIZEOF_U16 + key_len;
+        let value_len_pos = key_end + ts_len;
+        if value_len_pos + SIZEOF_U16 > data_end {
+            return Err(format!(
+                "key of entry {} at offset {} is out of bounds",
+                idx, offset
+            ));
+        }
+        let value_len = read_u16(value_len_pos);
+        if value_len_pos + SIZEOF_U16 + value_len > data_end {
+            return Err(format!(
+                "value of entry {} at offset {} is out of bounds",
+                idx, offset
+            ));
+        }
+        // The key of a version 2 entry ends with the timestamp that follows the user key.
+        keys.push((offset, &data[offset + SIZEOF_U16..value_len_pos]));
+    }
+    Ok(keys)
+}
+
+impl SsTable {
+    /// Scan every data block of the SST and check that keys are sorted, fall within the key range
+    /// recorded in the block meta and the table, and are accepted by the bloom filter. I/O errors
+    /// are returned as errors, while problems with the content are collected in the report.
+    pub fn verify(&self) -> Result<VerifyReport> {
+        let mut report = VerifyReport::default();
+        let mut prev_key: Option<Vec<u8>> = None;
+        let mut first_key: Option<Vec<u8>> = None;
+        let block_meta = self.block_metas()?;
+        for (block_idx, meta) in block_meta.iter().enumerate() {
+            let offset_end = block_meta
+                .get(block_idx + 1)
+                .map_or(self.block_meta_offset, |x| x.offset);
+            if offset_end <= meta.offset {
+                report.violation(block_idx, meta.offset, "block is empty".to_string());
+                report.block_entries.push(0);
+                continue;
+            }
+            let data = self
+                .file
+                .read(meta.offset as u64, (offset_end - meta.offset) as u64)?;
+            let data = match meta.compression.decompress(data) {
+                Ok(data) => data,
+                Err(e) => {
+                    report.violation(block_idx, meta.offset, format!("{:#}", e));
+                    report.block_entries.push(0);
+                    continue;
+                }
+            };
+            let keys = match decode_keys(&data) {
+                Ok(keys) => keys,
+                Err(message) => {
+                    report.violation(block_idx, meta.offset, message);
+                    report.block_entries.push(0);
+                    continue;
+                }
+            };
+            report.block_entries.push(keys.len());
+            match (keys.first(), keys.last()) {
+                (Some((_, first)), Some((_, last))) => {
+                    if *first != meta.first_key.raw_ref() {
+                        report.violation(
+                            block_idx,
+                            meta.offset,
+                            format!(
+                                "first key {:?} does not match block meta {:?}",
+                                first,
+                                meta.first_key.raw_ref()
+                            ),
+                        );
+                    }
+                    if *last != meta.last_key.raw_ref() {
+                        report.violation(
+                            block_idx,
+                            meta.offset,
+                            format!(
+                                "last key {:?} does not match block meta {:?}",
+                                last,
+                                meta.last_key.raw_ref()
+                            ),
+                        );
+                    }
+                }
+                _ => report.violation(block_idx, meta.offset, "block is empty".to_string()),
+            }
+            for (entry_offset, key) in keys {
+                let offset = meta.offset + entry_offset;
+                // Keys are compared like `Key`s, so that the versions of a key sort by descending
+                // timestamp once timestamps are enabled, and the bloom filter holds user keys.
+                let key_slice = KeySlice::from_slice(key);
+                if key_slice < meta.first_key.as_key_slice()
+                    || key_slice > meta.last_key.as_key_slice()
+                {
+                    report.violation(
+                        block_idx,
+                        offset,
+                        format!("key {:?} is outside of the block key range", key),
+                    );
+                }
+                if prev_key
+                    .as_deref()
+                    .is_some_and(|prev| KeySlice::from_slice(prev) >= key_slice)
+                {
+                    report.violation(block_idx, offset, format!("key {:?} is out of order", key));
+                }
+                if let Some(bloom) = &self.bloom {
+                    if !bloom.may_contain_key(key_slice.key_ref()) {
+                        report.violation(
+                            block_idx,
+                            offset,
+                            format!("key {:?} is rejected by the bloom filter", key),
+                        );
+                    }
+                }
+                if first_key.is_none() {
+                    first_key = Some(key.to_vec());
+                }
+                prev_key = Some(key.to_vec());
+            }
+        }
+        if first_key.as_deref() != Some(self.first_key.raw_ref()) {
+            report.violation(
+                0,
+                0,
+                format!(
+                    "table first key {:?} does not match the data",
+                    self.first_key.raw_ref()
+                ),
+            );
+        }
+        if prev_key.as_deref() != Some(self.last_key.raw_ref()) {
+            report.violation(
+                block_meta.len().saturating_sub(1),
+                block_meta.last().map_or(0, |meta| meta.offset),
+                format!(
+                    "table last key {:?} does not match the data",
+                    self.last_key.raw_ref()
+                ),
+            );
+        }
+        Ok(report)
+    }
+
+    /// Like `verify`, but fails with an error describing the first bad block, for `fsck`-style
+    /// tools that only need to know whether a file is healthy.
+    pub fn check(&self) -> Result<()> {
+        self.verify()?.into_result()
+    }
+}
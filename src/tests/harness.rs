@@ -45,6 +45,13 @@ impl MockIterator {
 impl StorageIterator for MockIterator {
     type KeyType<'a> = KeySlice<'a>;
 
+    fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
+        self.index = self
+            .data
+            .partition_point(|(k, _)| k.as_ref() < key.for_testing_key_ref());
+        Ok(())
+    }
+
     fn next(&mut self) -> Result<()> {
         if self.index < self.data.len() {
             self.index += 1;
@@ -188,7 +195,9 @@ pub fn generate_sst(
 ) -> SsTable {
     let mut builder = SsTableBuilder::new(128);
     for (key, value) in data {
-        builder.add(KeySlice::for_testing_from_slice_no_ts(&key[..]), &value[..]);
+        builder
+            .add(KeySlice::for_testing_from_slice_no_ts(&key[..]), &value[..])
+            .unwrap();
     }
     builder.build(id, block_cache, path.as_ref()).unwrap()
 }
@@ -202,10 +211,12 @@ pub fn generate_sst_with_ts(
 ) -> SsTable {
     let mut builder = SsTableBuilder::new(128);
     for ((key, ts), value) in data {
-        builder.add(
-            KeySlice::for_testing_from_slice_with_ts(&key[..], ts),
-            &value[..],
-        );
+        builder
+            .add(
+                KeySlice::for_testing_from_slice_with_ts(&key[..], ts),
+                &value[..],
+            )
+            .unwrap();
     }
     builder.build(id, block_cache, path.as_ref()).unwrap()
 }
@@ -4,14 +4,27 @@ use bytes::Bytes;
 use tempfile::tempdir;
 
 use crate::{
-    iterators::{merge_iterator::MergeIterator, StorageIterator},
-    lsm_iterator::FusedIterator,
+    block::{BlockBuilder, BlockIterator},
+    iterators::{
+        bounded_iterator::BoundedIterator,
+        boxed_iterator::BoxedStorageIterator,
+        concat_iterator::SstConcatIterator,
+        loser_tree_iterator::LoserTreeIterator,
+        merge_iterator::MergeIterator,
+        mvcc_iterator::{AllVersionsIterator, LatestVersionIterator, MvccIterator},
+        prefix_iterator::PrefixIterator,
+        two_merge_iterator::TwoMergeIterator,
+        StorageIterator,
+    },
+    key::{KeyBytes, KeySlice},
+    lsm_iterator::{FusedIterator, LsmIterator},
     lsm_storage::{LsmStorageInner, LsmStorageOptions},
     mem_table::MemTable,
+    table::{ReverseSsTableIterator, SsTableIterator},
     tests::harness::check_lsm_iter_result_by_key,
 };
 
-use super::harness::{check_iter_result_by_key, expect_iter_error, MockIterator};
+use super::harness::{check_iter_result_by_key, expect_iter_error, generate_sst, MockIterator};
 
 #[test]
 fn test_task1_memtable_iter() {
@@ -211,6 +224,77 @@ fn test_task2_merge_empty() {
     );
 }
 
+#[test]
+fn test_merge_exhausted_children() {
+    let empty = || Box::new(MockIterator::new(vec![]));
+    let exhausted = || {
+        let mut iter = MockIterator::new(vec![(Bytes::from("a"), Bytes::from("1.1"))]);
+        iter.next().unwrap();
+        Box::new(iter)
+    };
+    for children in [vec![], vec![empty(), empty()], vec![exhausted(), empty()]] {
+        let mut iter = MergeIterator::<MockIterator>::create(children);
+        assert!(!iter.is_valid());
+        assert_eq!(iter.num_active_iterators(), 0);
+        for _ in 0..3 {
+            iter.next().unwrap();
+            assert!(!iter.is_valid());
+        }
+        if cfg!(debug_assertions) {
+            let iter = std::panic::AssertUnwindSafe(&iter);
+            assert!(std::panic::catch_unwind(|| {
+                iter.key();
+            })
+            .is_err());
+            assert!(std::panic::catch_unwind(|| {
+                iter.value();
+            })
+            .is_err());
+        }
+    }
+}
+
+#[test]
+fn test_reverse_merge_matches_forward() {
+    let sources = [
+        vec![("a", "1.1"), ("c", "3.1"), ("d", "4.1"), ("f", "6.1")],
+        vec![("a", "1.2"), ("b", "2.2"), ("d", "4.2"), ("e", "5.2")],
+        vec![("b", "2.3"), ("c", "3.3"), ("d", "4.3"), ("g", "7.3")],
+    ];
+    let mock = |data: &Vec<(&'static str, &'static str)>, reverse: bool| {
+        let mut data = data
+            .iter()
+            .map(|(key, value)| (Bytes::from(*key), Bytes::from(*value)))
+            .collect::<Vec<_>>();
+        if reverse {
+            data.reverse();
+        }
+        Box::new(MockIterator::new(data))
+    };
+    let collect = |mut iter: MergeIterator<MockIterator>| {
+        let mut entries = Vec::new();
+        while iter.is_valid() {
+            entries.push((
+                Bytes::copy_from_slice(iter.key().for_testing_key_ref()),
+                Bytes::copy_from_slice(iter.value()),
+            ));
+            iter.next().unwrap();
+        }
+        entries
+    };
+    let forward = collect(MergeIterator::create(
+        sources.iter().map(|data| mock(data, false)).collect(),
+    ));
+    let mut reverse = collect(MergeIterator::create_reverse(
+        sources.iter().map(|data| mock(data, true)).collect(),
+    ));
+    reverse.reverse();
+    assert_eq!(forward, reverse);
+    assert_eq!(forward.len(), 7);
+    // the lowest index wins ties in both directions
+    assert_eq!(forward[3], (Bytes::from("d"), Bytes::from("4.1")));
+}
+
 #[test]
 fn test_task2_merge_error() {
     let mut iter = MergeIterator::<MockIterator>::create(vec![]);
@@ -238,6 +322,33 @@ fn test_task2_merge_error() {
     expect_iter_error(iter);
 }
 
+#[test]
+fn test_merge_error_poisons() {
+    let data = vec![
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("b"), Bytes::from("2.1")),
+        (Bytes::from("c"), Bytes::from("3.1")),
+        (Bytes::from("d"), Bytes::from("4.1")),
+    ];
+    // The second iterator fails on its third `next`, while being skipped past the duplicate "c".
+    let mut iter = MergeIterator::create(vec![
+        Box::new(MockIterator::new(data.clone())),
+        Box::new(MockIterator::new_with_error(data, 3)),
+    ]);
+    iter.next().unwrap();
+    iter.next().unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), b"c");
+    let err = iter.next().err().unwrap();
+    assert!(err.to_string().contains("iterator 1"), "{}", err);
+    assert!(format!("{:#}", err).contains("fake error"), "{:#}", err);
+    for _ in 0..3 {
+        assert!(!iter.is_valid());
+        let err = iter.next().err().unwrap();
+        assert!(err.to_string().contains("iterator 1"), "{}", err);
+        assert!(err.to_string().contains("fake error"), "{}", err);
+    }
+}
+
 #[test]
 fn test_task3_fused_iterator() {
     let iter = MockIterator::new(vec![]);
@@ -263,6 +374,36 @@ fn test_task3_fused_iterator() {
     assert!(fused_iter.next().is_err());
 }
 
+#[test]
+fn test_fused_iterator_repeats_error() {
+    let iter = MockIterator::new_with_error(
+        vec![
+            (Bytes::from("a"), Bytes::from("1.1")),
+            (Bytes::from("b"), Bytes::from("2.1")),
+        ],
+        1,
+    );
+    let merge = MergeIterator::create(vec![Box::new(iter)]);
+    let mut fused_iter = FusedIterator::new(merge);
+    let err = fused_iter.next().err().unwrap();
+    assert!(format!("{:#}", err).contains("fake error"), "{:#}", err);
+    for _ in 0..3 {
+        let err = fused_iter.next().err().unwrap();
+        assert!(format!("{:#}", err).contains("fake error"), "{:#}", err);
+        assert!(!fused_iter.is_valid());
+    }
+
+    let merge = MergeIterator::create(vec![Box::new(MockIterator::new(vec![(
+        Bytes::from("a"),
+        Bytes::from("1.1"),
+    )]))]);
+    let mut fused_iter = FusedIterator::new(merge);
+    for _ in 0..3 {
+        fused_iter.next().unwrap();
+        assert!(!fused_iter.is_valid());
+    }
+}
+
 #[test]
 fn test_task4_integration() {
     let dir = tempdir().unwrap();
@@ -315,3 +456,834 @@ fn test_task4_integration() {
         assert!(!iter.is_valid());
     }
 }
+
+#[test]
+fn test_merge_bounded() {
+    let i1 = MockIterator::new(vec![
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("c"), Bytes::from("3.1")),
+        (Bytes::from("e"), Bytes::from("5.1")),
+    ]);
+    let i2 = MockIterator::new(vec![
+        (Bytes::from("b"), Bytes::from("2.2")),
+        (Bytes::from("c"), Bytes::from("3.2")),
+        (Bytes::from("d"), Bytes::from("4.2")),
+    ]);
+    let i3 = MockIterator::new(vec![
+        (Bytes::from("d"), Bytes::from("4.3")),
+        (Bytes::from("f"), Bytes::from("6.3")),
+    ]);
+    let i4 = MockIterator::new(vec![(Bytes::from("a"), Bytes::from("1.4"))]);
+    let key = |k: &'static str| KeyBytes::from_bytes(Bytes::from(k));
+    let create = |lower: Bound<KeyBytes>, upper: Bound<KeyBytes>| {
+        MergeIterator::create_bounded(
+            vec![
+                Box::new(i1.clone()),
+                Box::new(i2.clone()),
+                Box::new(i3.clone()),
+                Box::new(i4.clone()),
+            ],
+            lower,
+            upper,
+        )
+        .unwrap()
+    };
+
+    let mut iter = create(Bound::Included(key("b")), Bound::Excluded(key("e")));
+    check_iter_result_by_key(
+        &mut iter,
+        vec![
+            (Bytes::from("b"), Bytes::from("2.2")),
+            (Bytes::from("c"), Bytes::from("3.1")),
+            (Bytes::from("d"), Bytes::from("4.2")),
+        ],
+    );
+
+    let mut iter = create(Bound::Excluded(key("c")), Bound::Included(key("e")));
+    check_iter_result_by_key(
+        &mut iter,
+        vec![
+            (Bytes::from("d"), Bytes::from("4.2")),
+            (Bytes::from("e"), Bytes::from("5.1")),
+        ],
+    );
+
+    let mut iter = create(Bound::Unbounded, Bound::Included(key("a")));
+    check_iter_result_by_key(&mut iter, vec![(Bytes::from("a"), Bytes::from("1.1"))]);
+
+    let mut iter = create(Bound::Included(key("e")), Bound::Unbounded);
+    check_iter_result_by_key(
+        &mut iter,
+        vec![
+            (Bytes::from("e"), Bytes::from("5.1")),
+            (Bytes::from("f"), Bytes::from("6.3")),
+        ],
+    );
+
+    let iter = create(Bound::Excluded(key("f")), Bound::Unbounded);
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_merge_skip_errors() {
+    let i1 = MockIterator::new(vec![
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("c"), Bytes::from("3.1")),
+    ]);
+    // Fails when moving past "b", both as the current iterator and inside the heap.
+    let i2 = MockIterator::new_with_error(
+        vec![
+            (Bytes::from("b"), Bytes::from("2.2")),
+            (Bytes::from("d"), Bytes::from("4.2")),
+        ],
+        1,
+    );
+    let i3 = MockIterator::new_with_error(
+        vec![
+            (Bytes::from("a"), Bytes::from("1.3")),
+            (Bytes::from("e"), Bytes::from("5.3")),
+        ],
+        1,
+    );
+    let i4 = MockIterator::new(vec![(Bytes::from("f"), Bytes::from("6.4"))]);
+    let mut iter = MergeIterator::create_skip_errors(vec![
+        Box::new(i1),
+        Box::new(i2),
+        Box::new(i3),
+        Box::new(i4),
+    ]);
+    assert!(iter.last_error().is_none());
+    check_iter_result_by_key(
+        &mut iter,
+        vec![
+            (Bytes::from("a"), Bytes::from("1.1")),
+            (Bytes::from("b"), Bytes::from("2.2")),
+            (Bytes::from("c"), Bytes::from("3.1")),
+            (Bytes::from("f"), Bytes::from("6.4")),
+        ],
+    );
+    assert_eq!(iter.last_error().unwrap().to_string(), "fake error!");
+}
+
+#[test]
+fn test_mvcc_iterator() {
+    let data = vec![
+        (Bytes::from("a"), Bytes::from("1.2")),
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("b"), Bytes::from("")),
+        (Bytes::from("b"), Bytes::from("2.1")),
+        (Bytes::from("c"), Bytes::from("3.1")),
+        (Bytes::from("d"), Bytes::from("4.3")),
+        (Bytes::from("d"), Bytes::from("4.2")),
+        (Bytes::from("d"), Bytes::from("4.1")),
+    ];
+    // every version has ts 0 until timestamps are encoded in keys, so each snapshot sees the
+    // newest version of every key, including the tombstone of `b`
+    for read_ts in [0, 1, u64::MAX] {
+        let mut iter = MvccIterator::new(MockIterator::new(data.clone()), read_ts).unwrap();
+        check_iter_result_by_key(
+            &mut iter,
+            vec![
+                (Bytes::from("a"), Bytes::from("1.2")),
+                (Bytes::from("b"), Bytes::from("")),
+                (Bytes::from("c"), Bytes::from("3.1")),
+                (Bytes::from("d"), Bytes::from("4.3")),
+            ],
+        );
+    }
+
+    let iter = MvccIterator::new(MockIterator::new(vec![]), 0).unwrap();
+    assert!(!iter.is_valid());
+
+    let mut iter = MvccIterator::new(MockIterator::new_with_error(data, 2), 0).unwrap();
+    assert!(iter.is_valid());
+    assert!(iter.next().is_err());
+}
+
+#[test]
+fn test_all_and_latest_versions() {
+    let mut versions = Vec::new();
+    for key in ["a", "b", "c"] {
+        for version in (0..300).rev() {
+            versions.push((
+                Bytes::from(key),
+                Bytes::from(format!("{}.{}", key, version)),
+            ));
+        }
+    }
+
+    let mut iter = AllVersionsIterator::new(MockIterator::new(versions.clone()));
+    check_iter_result_by_key(&mut iter, versions.clone());
+
+    // Until timestamps are encoded in keys, every version has ts 0 and is visible at any read_ts.
+    for read_ts in [0, 5, u64::MAX] {
+        let mut iter =
+            LatestVersionIterator::new(MockIterator::new(versions.clone()), read_ts).unwrap();
+        check_iter_result_by_key(
+            &mut iter,
+            vec![
+                (Bytes::from("a"), Bytes::from("a.299")),
+                (Bytes::from("b"), Bytes::from("b.299")),
+                (Bytes::from("c"), Bytes::from("c.299")),
+            ],
+        );
+    }
+}
+
+/// Check that `peek_key` matches `key` at every position and is `None` at the end. Returns the
+/// number of entries.
+fn check_peek_key(mut iter: impl StorageIterator) -> usize {
+    let mut count = 0;
+    while iter.is_valid() {
+        assert!(iter.peek_key() == Some(iter.key()));
+        iter.next().unwrap();
+        count += 1;
+    }
+    assert!(iter.peek_key().is_none());
+    count
+}
+
+#[test]
+fn test_peek_key() {
+    let dir = tempdir().unwrap();
+    let data = |keys: &[&'static str]| {
+        keys.iter()
+            .map(|key| (Bytes::from(*key), Bytes::from("v")))
+            .collect::<Vec<_>>()
+    };
+    let mock = || MockIterator::new(data(&["a", "b", "c"]));
+    assert_eq!(check_peek_key(mock()), 3);
+    assert_eq!(check_peek_key(MockIterator::new(vec![])), 0);
+
+    let memtable = MemTable::create(0);
+    memtable.for_testing_put_slice(b"a", b"v").unwrap();
+    memtable.for_testing_put_slice(b"b", b"v").unwrap();
+    assert_eq!(
+        check_peek_key(memtable.for_testing_scan_slice(Bound::Unbounded, Bound::Unbounded)),
+        2
+    );
+
+    let merge = || MergeIterator::create(vec![Box::new(mock()), Box::new(mock())]);
+    assert_eq!(check_peek_key(merge()), 3);
+    assert_eq!(
+        check_peek_key(TwoMergeIterator::create(merge(), mock()).unwrap()),
+        3
+    );
+    assert_eq!(check_peek_key(MvccIterator::new(mock(), 0).unwrap()), 3);
+
+    let sst1 = Arc::new(generate_sst(
+        1,
+        dir.path().join("1.sst"),
+        data(&["a", "b"]),
+        None,
+    ));
+    let sst2 = Arc::new(generate_sst(
+        2,
+        dir.path().join("2.sst"),
+        data(&["c"]),
+        None,
+    ));
+    assert_eq!(
+        check_peek_key(SsTableIterator::create_and_seek_to_first(sst1.clone()).unwrap()),
+        2
+    );
+    assert_eq!(
+        check_peek_key(ReverseSsTableIterator::create_and_seek_to_last(sst1.clone()).unwrap()),
+        2
+    );
+    assert_eq!(
+        check_peek_key(SstConcatIterator::create_and_seek_to_first(vec![sst1, sst2]).unwrap()),
+        3
+    );
+
+    let storage = LsmStorageInner::open(
+        dir.path().join("lsm"),
+        LsmStorageOptions::default_for_week1_test(),
+    )
+    .unwrap();
+    storage.put(b"a", b"v").unwrap();
+    storage.put(b"b", b"v").unwrap();
+    storage.delete(b"a").unwrap();
+    assert_eq!(
+        check_peek_key(storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+        1
+    );
+    let iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    assert_eq!(iter.peek_key(), Some(&b"b"[..]));
+}
+
+/// Exhaust the iterator and check that calling `next` again is a no-op.
+fn check_next_past_end(mut iter: impl StorageIterator) {
+    while iter.is_valid() {
+        iter.next().unwrap();
+    }
+    for _ in 0..3 {
+        iter.next().unwrap();
+        assert!(!iter.is_valid());
+    }
+}
+
+#[test]
+fn test_next_past_end() {
+    let dir = tempdir().unwrap();
+    let data = |keys: &[&'static str]| {
+        keys.iter()
+            .map(|key| (Bytes::from(*key), Bytes::from("v")))
+            .collect::<Vec<_>>()
+    };
+    let mock = || MockIterator::new(data(&["a", "b", "c"]));
+    let merge = || MergeIterator::create(vec![Box::new(mock()), Box::new(mock())]);
+    check_next_past_end(merge());
+    check_next_past_end(MergeIterator::<MockIterator>::create(vec![]));
+    check_next_past_end(
+        MergeIterator::create_bounded(
+            vec![Box::new(mock())],
+            Bound::Unbounded,
+            Bound::Excluded(KeyBytes::from_bytes(Bytes::from("b"))),
+        )
+        .unwrap(),
+    );
+    check_next_past_end(TwoMergeIterator::create(merge(), mock()).unwrap());
+    check_next_past_end(MvccIterator::new(mock(), 0).unwrap());
+
+    let memtable = MemTable::create(0);
+    memtable.for_testing_put_slice(b"a", b"v").unwrap();
+    memtable.for_testing_put_slice(b"c", b"").unwrap();
+    check_next_past_end(memtable.for_testing_scan_slice(Bound::Unbounded, Bound::Unbounded));
+    // the end bound cuts the iterator short while the inner iterators are still valid
+    let inner = TwoMergeIterator::create(
+        MergeIterator::create(vec![Box::new(
+            memtable.for_testing_scan_slice(Bound::Unbounded, Bound::Unbounded),
+        )]),
+        MergeIterator::<SsTableIterator>::create(vec![]),
+    )
+    .unwrap();
+    check_next_past_end(LsmIterator::new(inner, Bound::Excluded(Bytes::from("a"))).unwrap());
+
+    let sst1 = Arc::new(generate_sst(
+        1,
+        dir.path().join("1.sst"),
+        data(&["a", "b"]),
+        None,
+    ));
+    let sst2 = Arc::new(generate_sst(
+        2,
+        dir.path().join("2.sst"),
+        data(&["c"]),
+        None,
+    ));
+    check_next_past_end(SsTableIterator::create_and_seek_to_first(sst1.clone()).unwrap());
+    check_next_past_end(
+        SsTableIterator::create_and_seek_to_key_bounded(
+            sst1.clone(),
+            KeySlice::for_testing_from_slice_no_ts(b"a"),
+            Bound::Excluded(KeyBytes::from_bytes(Bytes::from("b"))),
+        )
+        .unwrap(),
+    );
+    check_next_past_end(ReverseSsTableIterator::create_and_seek_to_last(sst1.clone()).unwrap());
+    check_next_past_end(SstConcatIterator::create_and_seek_to_first(vec![sst1, sst2]).unwrap());
+    check_next_past_end(SstConcatIterator::create_and_seek_to_first(vec![]).unwrap());
+
+    // a block iterator seeking past the last key must not come back to life
+    let mut builder = BlockBuilder::new(4096);
+    assert!(builder.add(KeySlice::for_testing_from_slice_no_ts(b"a"), b"v"));
+    assert!(builder.add(KeySlice::for_testing_from_slice_no_ts(b"b"), b"v"));
+    let mut iter = BlockIterator::create_and_seek_to_first(Arc::new(builder.build()));
+    iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(b"c"));
+    for _ in 0..3 {
+        assert!(!iter.is_valid());
+        iter.next();
+        iter.prev();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_merge_seek_to_key() {
+    let dir = tempdir().unwrap();
+    let data = |keys: &[&'static str], tag: &str| {
+        keys.iter()
+            .map(|key| (Bytes::from(*key), Bytes::from(format!("{}{}", key, tag))))
+            .collect::<Vec<_>>()
+    };
+    let key = |key: &'static str| KeySlice::for_testing_from_slice_no_ts(key.as_bytes());
+    let mut iter = MergeIterator::create(vec![
+        Box::new(MockIterator::new(data(&["a", "c", "e"], "1"))),
+        Box::new(MockIterator::new(data(&["b", "c", "f"], "2"))),
+        Box::new(MockIterator::new(data(&["a", "d"], "3"))),
+    ]);
+    iter.seek_to_key(key("c")).unwrap();
+    check_iter_result_by_key(
+        &mut iter,
+        vec![
+            (Bytes::from("c"), Bytes::from("c1")),
+            (Bytes::from("d"), Bytes::from("d3")),
+            (Bytes::from("e"), Bytes::from("e1")),
+            (Bytes::from("f"), Bytes::from("f2")),
+        ],
+    );
+    // Every iterator is exhausted now, and seeking backward brings them back.
+    assert_eq!(iter.num_active_iterators(), 0);
+    iter.seek_to_key(key("b")).unwrap();
+    assert_eq!(iter.num_active_iterators(), 3);
+    assert_eq!(iter.key().for_testing_key_ref(), b"b");
+    assert_eq!(iter.value(), b"b2");
+    // Seeking past the end of some iterators leaves only the others in the merge.
+    iter.seek_to_key(key("ee")).unwrap();
+    assert_eq!(iter.num_active_iterators(), 1);
+    assert_eq!(iter.value(), b"f2");
+    iter.seek_to_key(key("z")).unwrap();
+    assert!(!iter.is_valid());
+    iter.seek_to_key(key("")).unwrap();
+    assert_eq!(iter.value(), b"a1");
+
+    // A bounded merge never seeks below its lower bound.
+    let bound = |key: &'static str| KeyBytes::for_testing_from_bytes_no_ts(Bytes::from(key));
+    let mut iter = MergeIterator::create_bounded(
+        vec![
+            Box::new(MockIterator::new(data(&["a", "c", "e"], "1"))),
+            Box::new(MockIterator::new(data(&["b", "c", "f"], "2"))),
+        ],
+        Bound::Excluded(bound("c")),
+        Bound::Unbounded,
+    )
+    .unwrap();
+    assert_eq!(iter.value(), b"e1");
+    for target in ["", "b", "c"] {
+        iter.seek_to_key(key(target)).unwrap();
+        assert_eq!(iter.value(), b"e1", "seek to {:?}", target);
+    }
+    iter.seek_to_key(key("f")).unwrap();
+    assert_eq!(iter.value(), b"f2");
+
+    // SSTs seek in place, and a merge of iterators that cannot seek fails.
+    let sst = Arc::new(generate_sst(
+        1,
+        dir.path().join("1.sst"),
+        data(&["a", "b", "c"], ""),
+        None,
+    ));
+    let mut iter = MergeIterator::create(vec![
+        Box::new(SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap()),
+        Box::new(SsTableIterator::create_and_seek_to_key(sst.clone(), key("c")).unwrap()),
+    ]);
+    iter.seek_to_key(key("b")).unwrap();
+    assert_eq!(iter.num_active_iterators(), 2);
+    assert_eq!(iter.key().for_testing_key_ref(), b"b");
+    let mut concat = SstConcatIterator::create_and_seek_to_first(vec![sst]).unwrap();
+    concat.seek_to_key(key("bb")).unwrap();
+    assert_eq!(concat.key().for_testing_key_ref(), b"c");
+
+    let memtable = MemTable::create(0);
+    memtable.for_testing_put_slice(b"a", b"v").unwrap();
+    let mut iter = MergeIterator::create(vec![Box::new(
+        memtable.for_testing_scan_slice(Bound::Unbounded, Bound::Unbounded),
+    )]);
+    assert!(iter.seek_to_key(key("a")).is_err());
+    assert!(!iter.is_valid());
+}
+
+fn random_runs(num_runs: usize, run_len: usize, key_space: u32) -> Vec<Vec<(Bytes, Bytes)>> {
+    use rand::Rng;
+
+    let mut rng = rand::thread_rng();
+    (0..num_runs)
+        .map(|run| {
+            let mut keys = (0..run_len)
+                .map(|_| rng.gen_range(0..key_space))
+                .collect::<Vec<_>>();
+            keys.sort_unstable();
+            keys.dedup();
+            keys.into_iter()
+                .map(|key| {
+                    (
+                        Bytes::from(format!("key_{:08}", key)),
+                        Bytes::from(format!("value_{}_{}", key, run)),
+                    )
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn collect_entries(
+    mut iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
+) -> Vec<(Bytes, Bytes)> {
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((
+            Bytes::copy_from_slice(iter.key().for_testing_key_ref()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next().unwrap();
+    }
+    entries
+}
+
+#[test]
+fn test_loser_tree_matches_merge() {
+    for num_runs in [0, 1, 2, 3, 7, 40] {
+        let runs = random_runs(num_runs, 200, 1000);
+        let mocks = || {
+            runs.iter()
+                .map(|run| Box::new(MockIterator::new(run.clone())))
+                .collect::<Vec<_>>()
+        };
+        let expected = collect_entries(MergeIterator::create(mocks()));
+        let iter = LoserTreeIterator::create(mocks());
+        assert_eq!(
+            iter.num_active_iterators(),
+            num_runs.min(runs.iter().filter(|run| !run.is_empty()).count())
+        );
+        assert_eq!(collect_entries(iter), expected, "{} runs", num_runs);
+    }
+
+    // Ties go to the smaller index.
+    let data = |tag: &str| {
+        ["a", "b", "c"]
+            .iter()
+            .map(|key| (Bytes::from(*key), Bytes::from(format!("{}{}", key, tag))))
+            .collect::<Vec<_>>()
+    };
+    let mut iter = LoserTreeIterator::create(vec![
+        Box::new(MockIterator::new(data("1")[1..].to_vec())),
+        Box::new(MockIterator::new(vec![])),
+        Box::new(MockIterator::new(data("3"))),
+        Box::new(MockIterator::new(data("4"))),
+    ]);
+    check_iter_result_by_key(
+        &mut iter,
+        vec![
+            (Bytes::from("a"), Bytes::from("a3")),
+            (Bytes::from("b"), Bytes::from("b1")),
+            (Bytes::from("c"), Bytes::from("c1")),
+        ],
+    );
+}
+
+#[test]
+fn test_loser_tree_error_poisons() {
+    let data = vec![
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("b"), Bytes::from("2.1")),
+        (Bytes::from("c"), Bytes::from("3.1")),
+        (Bytes::from("d"), Bytes::from("4.1")),
+    ];
+    let mut iter = LoserTreeIterator::create(vec![
+        Box::new(MockIterator::new(data.clone())),
+        Box::new(MockIterator::new_with_error(data, 3)),
+    ]);
+    iter.next().unwrap();
+    iter.next().unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), b"c");
+    let err = iter.next().err().unwrap();
+    assert!(err.to_string().contains("iterator 1"), "{}", err);
+    assert!(format!("{:#}", err).contains("fake error"), "{:#}", err);
+    for _ in 0..3 {
+        assert!(!iter.is_valid());
+        let err = iter.next().err().unwrap();
+        assert!(err.to_string().contains("fake error"), "{}", err);
+    }
+}
+
+#[test]
+#[ignore = "benchmark, run with `cargo test --release -- --ignored bench_loser_tree`"]
+fn bench_loser_tree() {
+    use std::time::Instant;
+
+    for num_runs in [4, 16, 64] {
+        let runs = random_runs(num_runs, 100_000, 4_000_000);
+        let mocks = || {
+            runs.iter()
+                .map(|run| Box::new(MockIterator::new(run.clone())))
+                .collect::<Vec<_>>()
+        };
+        let start = Instant::now();
+        let heap_entries = collect_entries(MergeIterator::create(mocks())).len();
+        let heap = start.elapsed();
+        let start = Instant::now();
+        let tree_entries = collect_entries(LoserTreeIterator::create(mocks())).len();
+        let tree = start.elapsed();
+        assert_eq!(heap_entries, tree_entries);
+        println!(
+            "{} runs: heap {:.1} ns per entry, loser tree {:.1} ns per entry",
+            num_runs,
+            heap.as_nanos() as f64 / heap_entries as f64,
+            tree.as_nanos() as f64 / tree_entries as f64
+        );
+    }
+}
+
+#[test]
+fn test_bounded_iterator() {
+    use std::ops::RangeBounds;
+
+    let data = ["b", "d", "f"]
+        .iter()
+        .map(|key| (Bytes::from(*key), Bytes::from(format!("{}1", key))))
+        .collect::<Vec<_>>();
+    // Bounds before, on and between the keys, and on the first and the last key.
+    let bounds = |keys: &[&'static str]| {
+        let mut bounds = vec![Bound::Unbounded];
+        for key in keys {
+            bounds.push(Bound::Included(Bytes::from(*key)));
+            bounds.push(Bound::Excluded(Bytes::from(*key)));
+        }
+        bounds
+    };
+    for lower in bounds(&["a", "b", "c", "d"]) {
+        for upper in bounds(&["d", "e", "f", "g"]) {
+            let expected = data
+                .iter()
+                .filter(|(key, _)| (lower.clone(), upper.clone()).contains(key))
+                .cloned()
+                .collect::<Vec<_>>();
+            let mut iter = BoundedIterator::new(
+                MockIterator::new(data.clone()),
+                lower.clone(),
+                upper.clone(),
+            )
+            .unwrap();
+            check_iter_result_by_key(&mut iter, expected);
+            iter.next().unwrap();
+            assert!(!iter.is_valid(), "{:?}..{:?}", lower, upper);
+        }
+    }
+
+    // A nested scan only narrows the upper bound.
+    let mut iter = BoundedIterator::new(
+        MockIterator::new(data.clone()),
+        Bound::Unbounded,
+        Bound::Included(Bytes::from("e")),
+    )
+    .unwrap();
+    iter.tighten_upper(Bound::Unbounded);
+    iter.tighten_upper(Bound::Included(Bytes::from("f")));
+    assert_eq!(iter.upper(), Bound::Included(&b"e"[..]));
+    iter.tighten_upper(Bound::Excluded(Bytes::from("e")));
+    assert_eq!(iter.upper(), Bound::Excluded(&b"e"[..]));
+    iter.tighten_upper(Bound::Included(Bytes::from("e")));
+    assert_eq!(iter.upper(), Bound::Excluded(&b"e"[..]));
+    iter.tighten_upper(Bound::Included(Bytes::from("c")));
+    check_iter_result_by_key(&mut iter, vec![data[0].clone()]);
+    assert_eq!(iter.lower(), Bound::Unbounded);
+
+    // Raw keys with the big-endian timestamp suffix: the bounds apply to the user keys, so all
+    // versions of a key are either in or out of the range.
+    let versions = ["b", "d", "f"]
+        .iter()
+        .flat_map(|key| {
+            [7u64, 3].map(|ts| {
+                let raw_key = [key.as_bytes(), &ts.to_be_bytes()[..]].concat();
+                (Bytes::from(raw_key), Bytes::from(format!("{}{}", key, ts)))
+            })
+        })
+        .collect::<Vec<_>>();
+    let user_key =
+        |raw_key: &Bytes| Bytes::copy_from_slice(KeySlice::from_slice(raw_key).key_ref());
+    for (lower, upper) in [
+        (
+            Bound::Excluded(Bytes::from("b")),
+            Bound::Included(Bytes::from("d")),
+        ),
+        (
+            Bound::Included(Bytes::from("d")),
+            Bound::Excluded(Bytes::from("f")),
+        ),
+    ] {
+        let expected = versions
+            .iter()
+            .filter(|(raw_key, _)| (lower.clone(), upper.clone()).contains(&user_key(raw_key)))
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut iter = BoundedIterator::new(
+            MockIterator::new(versions.clone()),
+            lower.clone(),
+            upper.clone(),
+        )
+        .unwrap();
+        check_iter_result_by_key(&mut iter, expected);
+        assert!(!iter.is_valid(), "{:?}..{:?}", lower, upper);
+    }
+}
+
+#[test]
+fn test_boxed_iterator() {
+    // Iterators of different types in one merge.
+    let dir = tempdir().unwrap();
+    let data = |keys: &[&'static str], tag: &str| {
+        keys.iter()
+            .map(|key| (Bytes::from(*key), Bytes::from(format!("{}{}", key, tag))))
+            .collect::<Vec<_>>()
+    };
+    let memtable = MemTable::create(0);
+    for (key, value) in data(&["b", "e"], "1") {
+        memtable.for_testing_put_slice(&key, &value).unwrap();
+    }
+    let sst = Arc::new(generate_sst(
+        1,
+        dir.path().join("1.sst"),
+        data(&["a", "b", "c"], "2"),
+        None,
+    ));
+    let mut iter = MergeIterator::create(vec![
+        Box::new(BoxedStorageIterator::new(
+            memtable.for_testing_scan_slice(Bound::Unbounded, Bound::Unbounded),
+        )),
+        Box::new(BoxedStorageIterator::new(
+            SsTableIterator::create_and_seek_to_first(sst).unwrap(),
+        )),
+        Box::new(BoxedStorageIterator::new(MergeIterator::create(vec![
+            Box::new(MockIterator::new(data(&["c", "d"], "3"))),
+            Box::new(MockIterator::new(data(&["f"], "4"))),
+        ]))),
+    ]);
+    assert_eq!(iter.num_active_iterators(), 4);
+    check_iter_result_by_key(
+        &mut iter,
+        vec![
+            (Bytes::from("a"), Bytes::from("a2")),
+            (Bytes::from("b"), Bytes::from("b1")),
+            (Bytes::from("c"), Bytes::from("c2")),
+            (Bytes::from("d"), Bytes::from("d3")),
+            (Bytes::from("e"), Bytes::from("e1")),
+            (Bytes::from("f"), Bytes::from("f4")),
+        ],
+    );
+
+    // Boxed children merge exactly like the unboxed ones, and seek and fail the same way.
+    let runs = random_runs(8, 200, 1000);
+    let mocks = || runs.iter().map(|run| MockIterator::new(run.clone()));
+    let expected = collect_entries(MergeIterator::create(mocks().map(Box::new).collect()));
+    let boxed = || {
+        MergeIterator::create(
+            mocks()
+                .map(|iter| Box::new(BoxedStorageIterator::new(iter)))
+                .collect(),
+        )
+    };
+    assert_eq!(collect_entries(boxed()), expected);
+    let mut iter = boxed();
+    iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(&expected[100].0))
+        .unwrap();
+    assert_eq!(collect_entries(iter), expected[100..]);
+    let iter = BoxedStorageIterator::new(MockIterator::new_with_error(data(&["a", "b"], ""), 1));
+    expect_iter_error(iter);
+}
+
+#[test]
+#[ignore = "benchmark, run with `cargo test --release -- --ignored bench_boxed_merge`"]
+fn bench_boxed_merge() {
+    use std::time::Instant;
+
+    let runs = random_runs(8, 200_000, 4_000_000);
+    let mocks = || runs.iter().map(|run| MockIterator::new(run.clone()));
+    for _ in 0..3 {
+        let iter = MergeIterator::create(mocks().map(Box::new).collect());
+        let start = Instant::now();
+        let entries = collect_entries(iter).len();
+        let direct = start.elapsed();
+        let iter = MergeIterator::create(
+            mocks()
+                .map(|iter| Box::new(BoxedStorageIterator::new(iter)))
+                .collect(),
+        );
+        let start = Instant::now();
+        assert_eq!(collect_entries(iter).len(), entries);
+        let boxed = start.elapsed();
+        println!(
+            "{} entries: direct {:.1} ns per entry, boxed {:.1} ns per entry",
+            entries,
+            direct.as_nanos() as f64 / entries as f64,
+            boxed.as_nanos() as f64 / entries as f64
+        );
+    }
+}
+
+#[test]
+fn test_prefix_iterator() {
+    let keys: [&[u8]; 8] = [
+        b"a",
+        b"a\xff",
+        b"a\xff\x00",
+        b"a\xff\xff",
+        b"b",
+        b"b\x00",
+        b"\xff",
+        b"\xff\xff",
+    ];
+    let data = keys
+        .iter()
+        .map(|key| (Bytes::copy_from_slice(key), Bytes::from("v")))
+        .collect::<Vec<_>>();
+    for prefix in [
+        &b""[..],
+        b"a",
+        b"a\xff",
+        b"a\xff\xff",
+        b"b",
+        b"\xff",
+        b"\xff\xff\xff",
+        b"c",
+    ] {
+        let expected = data
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut iter = PrefixIterator::create_and_seek(
+            MockIterator::new(data.clone()),
+            Bytes::copy_from_slice(prefix),
+        )
+        .unwrap();
+        check_iter_result_by_key(&mut iter, expected);
+        assert!(!iter.is_valid());
+    }
+}
+
+#[test]
+fn test_merge_next_no_dedup() {
+    // A small key space, so that most keys occur in several runs.
+    for num_runs in [1, 2, 5, 20] {
+        let runs = random_runs(num_runs, 100, 120);
+        let mocks = || {
+            runs.iter()
+                .map(|run| Box::new(MockIterator::new(run.clone())))
+                .collect::<Vec<_>>()
+        };
+        let expected = collect_entries(MergeIterator::create(mocks()));
+
+        // Without deduplication, every entry is yielded, and the first of each key is the one
+        // the merge keeps.
+        let mut iter = MergeIterator::create(mocks());
+        let mut all = Vec::new();
+        while iter.is_valid() {
+            all.push((
+                Bytes::copy_from_slice(iter.key().for_testing_key_ref()),
+                Bytes::copy_from_slice(iter.value()),
+            ));
+            iter.next_no_dedup().unwrap();
+        }
+        assert_eq!(all.len(), runs.iter().map(Vec::len).sum::<usize>());
+        all.dedup_by(|entry, prev| entry.0 == prev.0);
+        assert_eq!(all, expected, "{} runs", num_runs);
+
+        let lower = KeyBytes::for_testing_from_bytes_no_ts(Bytes::from("key_00000030"));
+        let upper = KeyBytes::for_testing_from_bytes_no_ts(Bytes::from("key_00000090"));
+        let iter = MergeIterator::create_bounded(
+            mocks(),
+            Bound::Excluded(lower.clone()),
+            Bound::Included(upper.clone()),
+        )
+        .unwrap();
+        let bounded = expected
+            .iter()
+            .filter(|(key, _)| {
+                &key[..] > lower.for_testing_key_ref() && &key[..] <= upper.for_testing_key_ref()
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(collect_entries(iter), bounded, "{} runs", num_runs);
+    }
+}
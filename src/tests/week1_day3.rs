@@ -4,7 +4,7 @@ use bytes::Bytes;
 
 use crate::{
     block::{Block, BlockBuilder, BlockIterator},
-    key::{KeySlice, KeyVec},
+    key::{compare_with_ts, split_ts, KeySlice, KeyVec},
 };
 
 #[test]
@@ -145,3 +145,180 @@ fn test_block_seek_key() {
         iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(b"k"));
     }
 }
+
+#[test]
+fn test_compare_with_ts() {
+    let with_ts = |key: &str, ts: u64| {
+        let mut raw = key.as_bytes().to_vec();
+        raw.extend(ts.to_be_bytes());
+        raw
+    };
+    assert_eq!(split_ts(&with_ts("key", 7)), (&b"key"[..], 7));
+    assert_eq!(split_ts(b"key"), (&b"key"[..], 0));
+
+    // Versions of the same user key sort newest first, and a user key sorts before the keys it is a
+    // prefix of, whatever the timestamp bytes are.
+    let expected = vec![
+        with_ts("a", 3),
+        with_ts("a", 2),
+        with_ts("a", 1),
+        with_ts("ab", u64::MAX),
+        with_ts("ab", 0),
+        with_ts("b", 2),
+        with_ts("b", 1),
+    ];
+    let mut keys = expected.clone();
+    keys.reverse();
+    keys.sort_by(|a, b| compare_with_ts(a, b));
+    assert_eq!(keys, expected);
+    keys.sort();
+    assert_ne!(keys, expected);
+}
+
+#[test]
+fn test_block_timestamps() {
+    let mut builder = BlockBuilder::new_with_ts(10000);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add_with_ts(key_of(idx).raw_ref(), idx as u64 * 7, &value_of(idx)));
+    }
+    let block = builder.build();
+    let encoded = block.encode();
+    let decoded = Block::decode(&encoded);
+    assert!(decoded.has_ts);
+    assert_eq!(block.offsets, decoded.offsets);
+    assert_eq!(block.data, decoded.data);
+    // Each entry stores 8 bytes more than in a block without timestamps.
+    assert_eq!(
+        encoded.len(),
+        generate_block().encode().len() + num_of_keys() * 8
+    );
+
+    // The key of an entry is the user key with the timestamp as a suffix, which `add` takes as it is.
+    let raw_key_of = |idx: usize| [key_of(idx).raw_ref(), &(idx as u64 * 7).to_be_bytes()].concat();
+    let mut builder = BlockBuilder::new_with_ts(10000);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(KeySlice::from_slice(&raw_key_of(idx)), &value_of(idx)));
+    }
+    assert_eq!(builder.first_key(), raw_key_of(0));
+    assert_eq!(builder.last_key(), raw_key_of(num_of_keys() - 1));
+    assert_eq!(builder.build().encode(), encoded);
+
+    let mut iter = BlockIterator::create_and_seek_to_first(Arc::new(decoded));
+    for idx in 0..num_of_keys() {
+        assert_eq!(iter.key().raw_ref(), raw_key_of(idx));
+        assert_eq!(
+            split_ts(iter.key().raw_ref()),
+            (key_of(idx).raw_ref(), idx as u64 * 7)
+        );
+        assert_eq!(iter.ts(), idx as u64 * 7);
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+    iter.seek_to_key(KeySlice::from_slice(&raw_key_of(42)));
+    assert_eq!(iter.ts(), 42 * 7);
+    iter.prev();
+    assert_eq!(iter.ts(), 41 * 7);
+    iter.seek_to_last();
+    assert_eq!(iter.ts(), (num_of_keys() as u64 - 1) * 7);
+
+    // Blocks without timestamps store the raw keys as they are, and take their timestamps from
+    // the keys.
+    let mut builder = BlockBuilder::new(10000);
+    assert!(builder.add_with_ts(b"key", 5, b"value"));
+    let mut iter = BlockIterator::create_and_seek_to_first(Arc::new(builder.build()));
+    assert_eq!(iter.key().raw_ref(), b"key\0\0\0\0\0\0\0\x05");
+    assert_eq!(iter.ts(), iter.key().ts());
+    let decoded = Block::decode(&generate_block().encode());
+    assert!(!decoded.has_ts);
+    iter = BlockIterator::create_and_seek_to_first(Arc::new(decoded));
+    while iter.is_valid() {
+        assert_eq!(iter.ts(), iter.key().ts());
+        iter.next();
+    }
+}
+
+#[test]
+fn test_key_prefix() {
+    let key = KeySlice::for_testing_from_slice_no_ts(b"user_0042");
+    assert_eq!(key.prefix(5).for_testing_key_ref(), b"user_");
+    assert_eq!(key.prefix(0).for_testing_key_ref(), b"");
+    assert_eq!(key.prefix(100), key);
+    // The prefix borrows from the key.
+    assert_eq!(
+        key.prefix(5).for_testing_key_ref().as_ptr(),
+        key.for_testing_key_ref().as_ptr()
+    );
+
+    let keys = [
+        "user_0040",
+        "user_0042",
+        "user_0042",
+        "user_0100",
+        "user_01",
+        "user_1",
+    ];
+    let mut sorted = keys.to_vec();
+    sorted.sort();
+    let key = |idx: usize| KeySlice::for_testing_from_slice_no_ts(sorted[idx].as_bytes());
+    let shared = (1..sorted.len())
+        .map(|idx| key(idx - 1).shared_prefix_len(key(idx)))
+        .collect::<Vec<_>>();
+    assert_eq!(shared, vec![8, 9, 6, 7, 5]);
+    // In a sorted batch, the prefix common to all keys is the one of the first and the last key.
+    let common = key(0).shared_prefix_len(key(sorted.len() - 1));
+    assert_eq!(common, *shared.iter().min().unwrap());
+    assert_eq!(key(0).prefix(common).for_testing_key_ref(), b"user_");
+    for (idx, raw) in sorted.iter().enumerate() {
+        assert_eq!(key(idx).shared_prefix_len(key(idx)), raw.len());
+    }
+}
+
+#[test]
+fn test_block_sparse_index() {
+    let build = |builder: BlockBuilder| {
+        let mut builder = builder;
+        for idx in 0..num_of_keys() {
+            assert!(builder.add(key_of(idx).as_key_slice(), &value_of(idx)));
+        }
+        Arc::new(Block::decode(&builder.build().encode()))
+    };
+    let plain = build(BlockBuilder::new(10000));
+    let sparse = build(BlockBuilder::new(10000).with_sparse_index(8));
+    assert!(plain.sparse_index.is_empty());
+    assert_eq!(
+        sparse.sparse_index,
+        (0..num_of_keys() as u16).step_by(8).collect::<Vec<_>>()
+    );
+    assert_eq!(sparse.data, plain.data);
+    assert_eq!(sparse.offsets, plain.offsets);
+
+    // Seek to every key, the gaps around them, and past both ends of the block.
+    let mut probes = vec![b"a".to_vec(), b"z".to_vec()];
+    for idx in 0..num_of_keys() * 5 + 1 {
+        probes.push(format!("key_{:03}", idx).into_bytes());
+    }
+    let mut plain_iter = BlockIterator::create_and_seek_to_first(plain);
+    let mut sparse_iter = BlockIterator::create_and_seek_to_first(sparse);
+    for probe in probes {
+        let key = KeySlice::for_testing_from_slice_no_ts(&probe);
+        plain_iter.seek_to_key(key);
+        sparse_iter.seek_to_key(key);
+        assert_eq!(sparse_iter.is_valid(), plain_iter.is_valid());
+        if plain_iter.is_valid() {
+            assert_eq!(sparse_iter.key(), plain_iter.key());
+            assert_eq!(sparse_iter.value(), plain_iter.value());
+            sparse_iter.next();
+            plain_iter.next();
+            assert_eq!(sparse_iter.is_valid(), plain_iter.is_valid());
+        }
+    }
+
+    // The sparse index counts towards the size of the block.
+    let mut builder = BlockBuilder::new(256).with_sparse_index(1);
+    let mut num_entries = 0;
+    while builder.add(key_of(num_entries).as_key_slice(), &value_of(num_entries)) {
+        num_entries += 1;
+    }
+    assert!(builder.build().encode().len() <= 256);
+}
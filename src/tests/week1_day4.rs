@@ -1,16 +1,25 @@
+use std::ops::Bound;
 use std::sync::Arc;
 
 use bytes::Bytes;
+use moka::sync::ConcurrentCacheExt;
 use tempfile::{tempdir, TempDir};
 
-use crate::iterators::StorageIterator;
-use crate::key::{KeySlice, KeyVec};
-use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+use crate::iterators::{
+    concat_iterator::SstConcatIterator, merge_iterator::MergeIterator, StorageIterator,
+};
+use crate::key::{KeySlice, KeyVec, TS_ENABLED};
+use crate::table::{
+    BlockMeta, Compression, DumpFormat, FileObject, MultiSsTableBuilder, ReverseSsTableIterator,
+    SsTable, SsTableBuildStats, SsTableBuilder, SsTableIterator, SsTableProperties,
+};
 
 #[test]
 fn test_sst_build_single_key() {
     let mut builder = SsTableBuilder::new(16);
-    builder.add(KeySlice::for_testing_from_slice_no_ts(b"233"), b"233333");
+    builder
+        .add(KeySlice::for_testing_from_slice_no_ts(b"233"), b"233333")
+        .unwrap();
     let dir = tempdir().unwrap();
     builder.build_for_test(dir.path().join("1.sst")).unwrap();
 }
@@ -18,12 +27,24 @@ fn test_sst_build_single_key() {
 #[test]
 fn test_sst_build_two_blocks() {
     let mut builder = SsTableBuilder::new(16);
-    builder.add(KeySlice::for_testing_from_slice_no_ts(b"11"), b"11");
-    builder.add(KeySlice::for_testing_from_slice_no_ts(b"22"), b"22");
-    builder.add(KeySlice::for_testing_from_slice_no_ts(b"33"), b"11");
-    builder.add(KeySlice::for_testing_from_slice_no_ts(b"44"), b"22");
-    builder.add(KeySlice::for_testing_from_slice_no_ts(b"55"), b"11");
-    builder.add(KeySlice::for_testing_from_slice_no_ts(b"66"), b"22");
+    builder
+        .add(KeySlice::for_testing_from_slice_no_ts(b"11"), b"11")
+        .unwrap();
+    builder
+        .add(KeySlice::for_testing_from_slice_no_ts(b"22"), b"22")
+        .unwrap();
+    builder
+        .add(KeySlice::for_testing_from_slice_no_ts(b"33"), b"11")
+        .unwrap();
+    builder
+        .add(KeySlice::for_testing_from_slice_no_ts(b"44"), b"22")
+        .unwrap();
+    builder
+        .add(KeySlice::for_testing_from_slice_no_ts(b"55"), b"11")
+        .unwrap();
+    builder
+        .add(KeySlice::for_testing_from_slice_no_ts(b"66"), b"22")
+        .unwrap();
     assert!(builder.meta.len() >= 2);
     let dir = tempdir().unwrap();
     builder.build_for_test(dir.path().join("1.sst")).unwrap();
@@ -46,7 +67,7 @@ fn generate_sst() -> (TempDir, SsTable) {
     for idx in 0..num_of_keys() {
         let key = key_of(idx);
         let value = value_of(idx);
-        builder.add(key.as_key_slice(), &value[..]);
+        builder.add(key.as_key_slice(), &value[..]).unwrap();
     }
     let dir = tempdir().unwrap();
     let path = dir.path().join("1.sst");
@@ -66,9 +87,9 @@ fn test_sst_build_all() {
 #[test]
 fn test_sst_decode() {
     let (_dir, sst) = generate_sst();
-    let meta = sst.block_meta.clone();
+    let meta = sst.block_metas().unwrap();
     let new_sst = SsTable::open_for_test(sst.file).unwrap();
-    assert_eq!(new_sst.block_meta, meta);
+    assert_eq!(new_sst.block_metas().unwrap(), meta);
     assert_eq!(
         new_sst.first_key().for_testing_key_ref(),
         key_of(0).for_testing_key_ref()
@@ -86,6 +107,10 @@ fn as_bytes(x: &[u8]) -> Bytes {
 #[test]
 fn test_sst_iterator() {
     let (_dir, sst) = generate_sst();
+    check_sst_iterator(sst);
+}
+
+fn check_sst_iterator(sst: SsTable) {
     let sst = Arc::new(sst);
     let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
     for _ in 0..5 {
@@ -115,6 +140,10 @@ fn test_sst_iterator() {
 #[test]
 fn test_sst_seek_key() {
     let (_dir, sst) = generate_sst();
+    check_sst_seek_key(sst);
+}
+
+fn check_sst_seek_key(sst: SsTable) {
     let sst = Arc::new(sst);
     let mut iter = SsTableIterator::create_and_seek_to_key(sst, key_of(0).as_key_slice()).unwrap();
     for offset in 1..=5 {
@@ -144,3 +173,1990 @@ fn test_sst_seek_key() {
             .unwrap();
     }
 }
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn test_sst_read_block_async() {
+    let (_dir, sst) = generate_sst();
+    for idx in 0..sst.num_of_blocks() {
+        let expected = sst.read_block(idx).unwrap();
+        let actual = sst.read_block_async(idx).await.unwrap();
+        assert_eq!(actual.data, expected.data);
+        assert_eq!(actual.offsets, expected.offsets);
+    }
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn test_sst_async_iterator() {
+    use crate::table::AsyncSsTableIterator;
+
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    let mut expected = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    let mut iter = AsyncSsTableIterator::create_and_seek_to_first(sst.clone())
+        .await
+        .unwrap();
+    while expected.is_valid() {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), expected.key());
+        assert_eq!(iter.value(), expected.value());
+        iter.next().await.unwrap();
+        expected.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    iter.next().await.unwrap();
+    assert!(!iter.is_valid());
+
+    for i in 0..num_of_keys() {
+        let key = format!("key_{:03}", i * 5 + 1).into_bytes();
+        let key = KeySlice::for_testing_from_slice_no_ts(&key);
+        let expected = SsTableIterator::create_and_seek_to_key(sst.clone(), key).unwrap();
+        iter.seek_to_key(key).await.unwrap();
+        assert_eq!(iter.is_valid(), expected.is_valid());
+        if expected.is_valid() {
+            assert_eq!(iter.key(), expected.key());
+        }
+    }
+    iter.seek_to_first().await.unwrap();
+    assert_eq!(iter.key(), key_of(0).as_key_slice());
+}
+
+#[test]
+fn test_sst_get() {
+    let (_dir, sst) = generate_sst();
+    check_sst_get(&sst);
+}
+
+fn check_sst_get(sst: &SsTable) {
+    for i in 0..num_of_keys() {
+        let value = sst.get(key_of(i).as_key_slice()).unwrap();
+        assert_eq!(value, Some(Bytes::from(value_of(i))));
+    }
+    for i in 0..num_of_keys() - 1 {
+        let key = format!("key_{:03}", i * 5 + 1).into_bytes();
+        let value = sst
+            .get(KeySlice::for_testing_from_slice_no_ts(&key))
+            .unwrap();
+        assert_eq!(value, None);
+    }
+    assert_eq!(
+        sst.get(KeySlice::for_testing_from_slice_no_ts(b"a"))
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        sst.get(KeySlice::for_testing_from_slice_no_ts(b"zzz"))
+            .unwrap(),
+        None
+    );
+}
+
+#[test]
+fn test_sst_get_out_of_range_skips_read() {
+    // A meta-only table has no file to read from, so any block read would panic.
+    let sst =
+        SsTable::create_meta_only(0, 0, key_of(1).into_key_bytes(), key_of(2).into_key_bytes());
+    let lower = key_of(0);
+    let upper = key_of(3);
+    assert_eq!(sst.get(lower.as_key_slice()).unwrap(), None);
+    assert_eq!(sst.get(upper.as_key_slice()).unwrap(), None);
+}
+
+#[test]
+fn test_sst_iterators_without_blocks() {
+    let sst = Arc::new(SsTable::create_meta_only(
+        0,
+        0,
+        key_of(1).into_key_bytes(),
+        key_of(2).into_key_bytes(),
+    ));
+    assert_eq!(sst.num_of_blocks(), 0);
+    let mut iter = SsTableIterator::create_and_seek_to_last(sst.clone()).unwrap();
+    assert!(!iter.is_valid());
+    iter.seek_to_last().unwrap();
+    assert!(!iter.is_valid());
+    iter.prev().unwrap();
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+    let iter =
+        SsTableIterator::create_and_seek_to_key(sst.clone(), key_of(1).as_key_slice()).unwrap();
+    assert!(!iter.is_valid());
+    assert!(
+        !ReverseSsTableIterator::create_and_seek_to_last(sst.clone())
+            .unwrap()
+            .is_valid()
+    );
+    assert!(
+        !ReverseSsTableIterator::create_and_seek_to_key(sst, key_of(1).as_key_slice())
+            .unwrap()
+            .is_valid()
+    );
+}
+
+#[test]
+fn test_sst_get_negative_cache() {
+    let (_dir, sst) = generate_sst();
+    let sst = sst.with_negative_cache(2);
+    // Absent keys within the key range that pass the bloom filter, so a lookup reads a block.
+    let absent = (0..)
+        .map(|idx| KeyVec::for_testing_from_vec_no_ts(format!("key_{:03}x", idx).into_bytes()))
+        .filter(|key| {
+            sst.bloom()
+                .is_none_or(|bloom| bloom.may_contain_key(key.as_key_slice().key_ref()))
+        })
+        .take(3)
+        .collect::<Vec<_>>();
+    let get_reads = |key: &KeyVec| {
+        let reads = crate::table::read_count();
+        assert_eq!(sst.get(key.as_key_slice()).unwrap(), None);
+        crate::table::read_count() - reads
+    };
+    assert_eq!(get_reads(&absent[0]), 1);
+    assert_eq!(get_reads(&absent[0]), 0);
+    assert_eq!(get_reads(&absent[1]), 1);
+    assert_eq!(get_reads(&absent[0]), 0);
+    // The third key evicts the least recently used one.
+    assert_eq!(get_reads(&absent[2]), 1);
+    assert_eq!(get_reads(&absent[0]), 0);
+    assert_eq!(get_reads(&absent[2]), 0);
+    assert_eq!(get_reads(&absent[1]), 1);
+    // Keys present in the SST are never cached.
+    for _ in 0..2 {
+        let reads = crate::table::read_count();
+        assert_eq!(
+            sst.get(key_of(7).as_key_slice()).unwrap(),
+            Some(Bytes::from(value_of(7)))
+        );
+        assert_eq!(crate::table::read_count() - reads, 1);
+    }
+}
+
+#[test]
+fn test_sst_get_negative_cache_versions() {
+    // Raw keys with the big-endian timestamp suffix, in the order the builder takes them.
+    let raw_key = |ts: u64| [&b"key"[..], &ts.to_be_bytes()[..]].concat();
+    let versions = if TS_ENABLED { [6, 3] } else { [3, 6] };
+    let mut builder = SsTableBuilder::new(128).disable_bloom();
+    for ts in versions {
+        builder
+            .add(
+                KeySlice::from_slice(&raw_key(ts)),
+                format!("v{}", ts).as_bytes(),
+            )
+            .unwrap();
+    }
+    let sst = builder.build_in_memory(0).unwrap().with_negative_cache(4);
+    // A missing version is cached, but does not hide the versions that exist.
+    for _ in 0..2 {
+        assert_eq!(sst.get(KeySlice::from_slice(&raw_key(5))).unwrap(), None);
+    }
+    for ts in versions {
+        assert_eq!(
+            sst.get(KeySlice::from_slice(&raw_key(ts))).unwrap(),
+            Some(Bytes::from(format!("v{}", ts)))
+        );
+    }
+}
+
+#[test]
+fn test_sst_get_negative_cache_concurrent() {
+    let (_dir, sst) = generate_sst();
+    let sst = sst.with_negative_cache(4);
+    // Concurrent lookups of more absent keys than the cache holds, so keys keep being evicted.
+    std::thread::scope(|scope| {
+        for thread in 0..4 {
+            let sst = &sst;
+            scope.spawn(move || {
+                for round in 0..200 {
+                    let key = format!("key_{:03}x", (thread + round) % 16);
+                    let key = KeyVec::for_testing_from_vec_no_ts(key.into_bytes());
+                    assert_eq!(sst.get(key.as_key_slice()).unwrap(), None);
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn test_file_object_read_into() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let data = (0..=255u8).collect::<Vec<_>>();
+    let file = FileObject::create(&path, data.clone()).unwrap();
+    let mut buf = [0u8; 16];
+    for offset in [0, 100, 240] {
+        file.read_into(offset, &mut buf).unwrap();
+        assert_eq!(&buf[..], &data[offset as usize..offset as usize + 16]);
+    }
+    assert!(file.read_into(250, &mut buf).is_err());
+}
+
+#[test]
+fn test_file_object_read_vectored() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let data = (0..=255u8).collect::<Vec<_>>();
+    let file = FileObject::create(&path, data).unwrap();
+    for ranges in [
+        vec![(10, 5), (40, 20), (200, 30)],
+        vec![(0, 16), (16, 16), (255, 1)],
+        vec![(40, 20), (10, 5), (45, 10)],
+    ] {
+        let bufs = file.read_vectored(&ranges).unwrap();
+        assert_eq!(bufs.len(), ranges.len());
+        for ((offset, len), buf) in ranges.into_iter().zip(bufs) {
+            assert_eq!(buf, file.read(offset, len).unwrap());
+        }
+    }
+}
+
+#[test]
+fn test_sst_properties() {
+    let mut builder = SsTableBuilder::new(128);
+    let mut expected = SsTableProperties::default();
+    for idx in 0..num_of_keys() {
+        let key = key_of(idx);
+        let value = if idx % 10 == 0 { vec![] } else { value_of(idx) };
+        builder.add(key.as_key_slice(), &value[..]).unwrap();
+        expected.num_entries += 1;
+        expected.num_tombstones += value.is_empty() as u64;
+        expected.raw_key_size += key.len() as u64;
+        expected.raw_value_size += value.len() as u64;
+    }
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let sst = builder.build_for_test(&path).unwrap();
+    assert_eq!(sst.properties(), &expected);
+    assert_eq!(expected.num_tombstones, 10);
+    let sst = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(sst.properties(), &expected);
+}
+
+#[test]
+fn test_file_object_create_without_sync() {
+    let (dir, sst) = generate_sst();
+    let data = sst.file.read(0, sst.table_size()).unwrap();
+    let path = dir.path().join("2.sst");
+    let file = FileObject::create_with_options(&path, data.clone(), false).unwrap();
+    assert_eq!(file.read(0, file.size()).unwrap(), data);
+    let new_sst = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(new_sst.block_metas().unwrap(), sst.block_metas().unwrap());
+    assert_eq!(
+        new_sst.get(key_of(7).as_key_slice()).unwrap(),
+        Some(Bytes::from(value_of(7)))
+    );
+}
+
+#[test]
+fn test_sst_build_streaming() {
+    // Large enough to exceed the write buffer of a streaming builder.
+    let num_keys = 50000;
+    let key_of =
+        |idx: usize| KeyVec::for_testing_from_vec_no_ts(format!("key_{:08}", idx).into_bytes());
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(4096);
+    let mut streaming_builder =
+        SsTableBuilder::new_streaming(4096, dir.path().join("2.sst")).unwrap();
+    for idx in 0..num_keys {
+        let key = key_of(idx);
+        let value = value_of(idx);
+        builder.add(key.as_key_slice(), &value[..]).unwrap();
+        streaming_builder
+            .add(key.as_key_slice(), &value[..])
+            .unwrap();
+    }
+    assert_eq!(builder.estimated_size(), streaming_builder.estimated_size());
+    // The blocks go to a temporary file until the SST is complete.
+    assert!(!dir.path().join("2.sst").exists());
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    let streamed_sst = streaming_builder.build_streaming(0, None).unwrap();
+    assert!(!dir.path().join("2.sst.tmp").exists());
+    assert!(streamed_sst.table_size() > 1 << 20);
+    assert_eq!(
+        streamed_sst
+            .file
+            .read(0, streamed_sst.table_size())
+            .unwrap(),
+        sst.file.read(0, sst.table_size()).unwrap()
+    );
+    let sst = SsTable::open_for_test(FileObject::open(&dir.path().join("2.sst")).unwrap()).unwrap();
+    assert_eq!(
+        sst.block_metas().unwrap(),
+        streamed_sst.block_metas().unwrap()
+    );
+    let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+    for idx in 0..num_keys {
+        assert_eq!(iter.key(), key_of(idx).as_key_slice());
+        assert_eq!(iter.value(), &value_of(idx)[..]);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_iterator_end_bound() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    assert!(sst.num_of_blocks() > 2);
+    let block_last_key = sst.block_meta(1).unwrap().last_key.clone();
+    let last_idx = (0..num_of_keys())
+        .find(|idx| key_of(*idx).raw_ref() == block_last_key.raw_ref())
+        .unwrap();
+    for (end_bound, expected_last_idx) in [
+        (Bound::Included(block_last_key.clone()), last_idx),
+        (Bound::Excluded(block_last_key.clone()), last_idx - 1),
+        (
+            Bound::Excluded(key_of(last_idx - 1).into_key_bytes()),
+            last_idx - 2,
+        ),
+    ] {
+        let mut iter = SsTableIterator::create_and_seek_to_key_bounded(
+            sst.clone(),
+            key_of(1).as_key_slice(),
+            end_bound,
+        )
+        .unwrap();
+        for idx in 1..=expected_last_idx {
+            assert!(iter.is_valid());
+            assert_eq!(iter.key(), key_of(idx).as_key_slice());
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    }
+}
+
+#[test]
+fn test_sst_open_from_bytes() {
+    let (_dir, sst) = generate_sst();
+    let data = sst.file.read(0, sst.table_size()).unwrap();
+    let mem_sst = SsTable::open_from_bytes(0, None, Bytes::from(data)).unwrap();
+    assert_eq!(mem_sst.block_metas().unwrap(), sst.block_metas().unwrap());
+    assert_eq!(mem_sst.table_size(), sst.table_size());
+    assert!(mem_sst.file.read(mem_sst.table_size() - 1, 2).is_err());
+    let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(mem_sst)).unwrap();
+    for idx in 0..num_of_keys() {
+        assert_eq!(iter.key(), key_of(idx).as_key_slice());
+        assert_eq!(iter.value(), &value_of(idx)[..]);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_verify() {
+    let (_dir, sst) = generate_sst();
+    let report = sst.verify().unwrap();
+    assert!(report.is_ok(), "{:?}", report.violations);
+    assert_eq!(report.block_entries.len(), sst.num_of_blocks());
+    assert_eq!(report.block_entries.iter().sum::<usize>(), num_of_keys());
+
+    // Corrupt the first key of block 1.
+    let mut data = sst.file.read(0, sst.table_size()).unwrap();
+    let offset = sst.block_meta(1).unwrap().offset;
+    data[offset + 2] ^= 0xff;
+    let sst = SsTable::open_from_bytes(0, None, Bytes::from(data)).unwrap();
+    let report = sst.verify().unwrap();
+    assert!(!report.is_ok());
+    assert!(report
+        .violations
+        .iter()
+        .all(|violation| violation.block_idx == 1));
+    assert_eq!(report.violations[0].offset, offset);
+}
+
+#[test]
+fn test_sst_verify_versions() {
+    // Several versions of each key, as raw keys with the big-endian timestamp suffix, in the order
+    // the builder takes them.
+    let mut keys = (0..20)
+        .flat_map(|idx| {
+            (1..4).map(move |ts| {
+                [
+                    format!("key_{:02}", idx).as_bytes(),
+                    &[0, 0, 0, 0, 0, 0, 0, ts],
+                ]
+                .concat()
+            })
+        })
+        .collect::<Vec<_>>();
+    keys.sort_by(|a, b| KeySlice::from_slice(a).cmp(&KeySlice::from_slice(b)));
+    let mut builder = SsTableBuilder::new(128);
+    for key in &keys {
+        builder.add(KeySlice::from_slice(key), b"value").unwrap();
+    }
+    let sst = builder.build_in_memory(0).unwrap();
+    assert!(sst.num_of_blocks() > 1);
+    let report = sst.verify().unwrap();
+    assert!(report.is_ok(), "{:?}", report.violations);
+    assert_eq!(report.block_entries.iter().sum::<usize>(), keys.len());
+}
+
+#[test]
+fn test_sst_check() {
+    let (_dir, sst) = generate_sst();
+    sst.check().unwrap();
+
+    // Truncate the entry count of block 2 so that its offsets run past the block.
+    let mut data = sst.file.read(0, sst.table_size()).unwrap();
+    let offset = sst.block_meta(3).unwrap().offset;
+    data[offset - 2] = 0xff;
+    data[offset - 1] = 0xff;
+    let sst = SsTable::open_from_bytes(0, None, Bytes::from(data)).unwrap();
+    let err = sst.check().unwrap_err().to_string();
+    assert!(err.contains("block 2 "), "{}", err);
+}
+
+#[test]
+fn test_block_meta_decode_errors() {
+    let (_dir, sst) = generate_sst();
+    let mut buf = Vec::new();
+    BlockMeta::encode_block_meta(&sst.block_metas().unwrap(), &mut buf);
+    assert_eq!(
+        BlockMeta::decode_block_meta(&buf).unwrap(),
+        sst.block_metas().unwrap()
+    );
+
+    // Truncated in the middle of the last key.
+    assert!(BlockMeta::decode_block_meta(&buf[..buf.len() - 1]).is_err());
+    // Truncated in the middle of the header.
+    assert!(BlockMeta::decode_block_meta(&buf[..2]).is_err());
+
+    // The first key shares a prefix with the last key of a block before the first one. The
+    // header is followed by the offset of the first block and the shared length of its first key.
+    let mut corrupted = buf.clone();
+    assert_eq!(corrupted[9], 0);
+    corrupted[9] = 1;
+    assert!(BlockMeta::decode_block_meta(&corrupted).is_err());
+
+    // The length of the first key exceeds the encoded metas.
+    let mut corrupted = Vec::new();
+    BlockMeta::encode_block_meta(&sst.block_metas().unwrap()[..1], &mut corrupted);
+    corrupted[10] = 0x7f;
+    assert!(BlockMeta::decode_block_meta(&corrupted).is_err());
+
+    // Offsets must be strictly increasing.
+    let mut reversed = Vec::new();
+    let metas = sst
+        .block_metas()
+        .unwrap()
+        .iter()
+        .rev()
+        .cloned()
+        .collect::<Vec<_>>();
+    BlockMeta::encode_block_meta(&metas, &mut reversed);
+    assert!(BlockMeta::decode_block_meta(&reversed).is_err());
+}
+
+#[test]
+fn test_sst_entries() {
+    let (_dir, sst) = generate_sst();
+    let entries = Arc::new(sst)
+        .iter()
+        .unwrap()
+        .into_entries()
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(entries.len(), num_of_keys());
+    for (idx, (key, value)) in entries.into_iter().enumerate() {
+        assert_eq!(key.as_key_slice(), key_of(idx).as_key_slice());
+        assert_eq!(value, Bytes::from(value_of(idx)));
+    }
+}
+
+#[test]
+fn test_block_meta_trailing_data() {
+    let (_dir, sst) = generate_sst();
+    let mut buf = Vec::new();
+    BlockMeta::encode_block_meta(&sst.block_metas().unwrap(), &mut buf);
+    buf.extend(b"trailing garbage after the metas");
+    assert_eq!(
+        BlockMeta::decode_block_meta(&buf).unwrap(),
+        sst.block_metas().unwrap()
+    );
+
+    let mut buf = Vec::new();
+    BlockMeta::encode_block_meta(&[], &mut buf);
+    buf.extend([0xff; 16]);
+    assert!(BlockMeta::decode_block_meta(&buf).unwrap().is_empty());
+}
+
+#[test]
+fn test_sst_builder_unsorted_keys() {
+    let mut builder = SsTableBuilder::new(128);
+    builder.add(key_of(1).as_key_slice(), b"1").unwrap();
+    let err = builder.add(key_of(0).as_key_slice(), b"0").unwrap_err();
+    assert!(err.to_string().contains("ascending order"), "{}", err);
+    // Duplicate keys are rejected as well.
+    assert!(builder.add(key_of(1).as_key_slice(), b"1").is_err());
+    builder.add(key_of(2).as_key_slice(), b"2").unwrap();
+    assert_eq!(builder.num_entries(), 2);
+
+    let sst = builder
+        .build_for_test(tempdir().unwrap().path().join("1.sst"))
+        .unwrap();
+    assert_eq!(sst.first_key().as_key_slice(), key_of(1).as_key_slice());
+    assert_eq!(sst.last_key().as_key_slice(), key_of(2).as_key_slice());
+}
+
+#[test]
+fn test_sst_builder_mvcc_key_order() {
+    use crate::table::is_next_key;
+
+    // Raw keys with the big-endian timestamp suffix, as added once timestamps are enabled.
+    let raw_key = |key: &[u8], ts: u64| [key, &ts.to_be_bytes()[..]].concat();
+    let key = raw_key(b"b", 5);
+    // An older version of the same key may follow once timestamps are enabled, although its
+    // timestamp suffix is smaller as bytes.
+    assert!(is_next_key(&raw_key(b"b", 6), &key, true));
+    assert!(!is_next_key(&raw_key(b"b", 6), &key, false));
+    assert!(!is_next_key(&raw_key(b"b", 5), &key, true));
+    assert!(!is_next_key(&raw_key(b"b", 4), &key, true));
+    assert!(is_next_key(&raw_key(b"a", 0), &key, true));
+    assert!(is_next_key(&raw_key(b"a", u64::MAX), &key, true));
+    assert!(!is_next_key(&raw_key(b"c", 9), &key, true));
+    // A user key that extends another one follows all of its versions.
+    assert!(is_next_key(&raw_key(b"b", 0), &raw_key(b"ba", 7), true));
+    // Without timestamps, raw keys are compared as bytes.
+    assert!(is_next_key(b"a", b"b", false));
+    assert!(!is_next_key(b"b", b"b", false));
+}
+
+#[test]
+fn test_read_exact_with_short_reads() {
+    use std::io::{Error, ErrorKind};
+
+    let data = (0..100u8).collect::<Vec<_>>();
+    let mut calls = 0;
+    // Serve at most 3 bytes per call and fail every other call with `Interrupted`.
+    let mut read_at = |buf: &mut [u8], offset: u64| {
+        calls += 1;
+        if calls % 2 == 0 {
+            return Err(Error::from(ErrorKind::Interrupted));
+        }
+        let offset = (offset as usize).min(data.len());
+        let n = buf.len().min(3).min(data.len() - offset);
+        buf[..n].copy_from_slice(&data[offset..offset + n]);
+        Ok(n)
+    };
+
+    let mut buf = vec![0; 20];
+    crate::table::read_exact_with(&mut buf, 10, &mut read_at).unwrap();
+    assert_eq!(buf, &data[10..30]);
+
+    let mut buf = vec![0; 20];
+    let err = crate::table::read_exact_with(&mut buf, 90, &mut read_at).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+    let mut buf = vec![0; 20];
+    let err = crate::table::read_exact_with(&mut buf, 0, |_: &mut [u8], _| {
+        Err(Error::from(ErrorKind::PermissionDenied))
+    })
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn test_file_object_create_atomic() {
+    let (dir, sst) = generate_sst();
+    let data = sst.file.read(0, sst.table_size()).unwrap();
+    let path = dir.path().join("2.sst");
+    let file = FileObject::create_atomic(&path, data.clone()).unwrap();
+    assert_eq!(file.read(0, file.size()).unwrap(), data);
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    assert!(!dir.path().join("2.sst.tmp").exists());
+
+    // Renaming onto a non-empty directory fails after the data has been written.
+    let path = dir.path().join("3.sst");
+    std::fs::create_dir(&path).unwrap();
+    std::fs::write(path.join("other"), b"other").unwrap();
+    assert!(FileObject::create_atomic(&path, data).is_err());
+    assert!(path.is_dir());
+    assert!(!dir.path().join("3.sst.tmp").exists());
+}
+
+#[test]
+fn test_sst_builder_num_entries() {
+    let mut builder = SsTableBuilder::new(128);
+    assert_eq!(builder.num_entries(), 0);
+    for idx in 0..num_of_keys() {
+        builder
+            .add(key_of(idx).as_key_slice(), &value_of(idx))
+            .unwrap();
+        assert_eq!(builder.num_entries(), idx + 1);
+    }
+}
+
+#[test]
+fn test_sst_read_blocks() {
+    let (_dir, sst) = generate_sst();
+    let num_blocks = sst.num_of_blocks();
+    let blocks = sst.read_blocks(1..num_blocks).unwrap();
+    assert_eq!(blocks.len(), num_blocks - 1);
+    for (idx, block) in (1..num_blocks).zip(blocks) {
+        assert_eq!(block.encode(), sst.read_block(idx).unwrap().encode());
+    }
+    assert!(sst.read_blocks(2..2).unwrap().is_empty());
+    assert!(sst.read_blocks(0..num_blocks + 1).is_err());
+}
+
+#[test]
+fn test_sst_par_scan() {
+    let dir = tempdir().unwrap();
+    for (compression, name) in [(Compression::None, "1.sst"), (Compression::Lz4, "2.sst")] {
+        let mut builder = SsTableBuilder::new(256).with_compression(compression);
+        for idx in 0..num_of_keys() {
+            let value = format!("value_{:03}_{}", idx, "x".repeat(50));
+            builder
+                .add(key_of(idx).as_key_slice(), value.as_bytes())
+                .unwrap();
+        }
+        let path = dir.path().join(name);
+        builder.build_for_test(&path).unwrap();
+        let sst = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+        let num_blocks = sst.num_of_blocks();
+        assert!(num_blocks > 8);
+
+        let blocks = sst.par_scan().unwrap();
+        assert_eq!(blocks.len(), num_blocks);
+        for (idx, block) in blocks.iter().enumerate() {
+            assert_eq!(block.encode(), sst.read_block(idx).unwrap().encode());
+        }
+    }
+}
+
+#[test]
+fn test_sst_sequential_iterator() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    let num_blocks = sst.num_of_blocks();
+    assert!(num_blocks > 8);
+
+    let count_reads = |iter: SsTableIterator| {
+        let reads = crate::table::read_count();
+        let entries = iter
+            .into_entries()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        (entries, crate::table::read_count() - reads)
+    };
+    let reads = crate::table::read_count();
+    let iter = SsTableIterator::create_sequential(sst.clone(), 8).unwrap();
+    let first_reads = crate::table::read_count() - reads;
+    let (sequential, sequential_reads) = count_reads(iter);
+    assert_eq!(first_reads + sequential_reads, num_blocks.div_ceil(8));
+
+    let iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    let (entries, reads) = count_reads(iter);
+    assert_eq!(reads, num_blocks - 1);
+    assert_eq!(sequential, entries);
+    assert_eq!(entries.len(), num_of_keys());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_file_object_open_direct() {
+    let (dir, sst) = generate_sst();
+    let path = dir.path().join("1.sst");
+    let buffered = FileObject::open(&path).unwrap();
+    let direct = FileObject::open_direct(&path).unwrap();
+    assert_eq!(direct.size(), buffered.size());
+    assert_eq!(
+        direct.read(0, direct.size()).unwrap(),
+        buffered.read(0, buffered.size()).unwrap()
+    );
+    // Unaligned ranges, including one ending at the end of the file.
+    for (offset, len) in [
+        (1, 10),
+        (buffered.size() / 2, 200),
+        (buffered.size() - 7, 7),
+    ] {
+        assert_eq!(
+            direct.read(offset, len).unwrap(),
+            buffered.read(offset, len).unwrap()
+        );
+    }
+    let direct_sst = SsTable::open_for_test(direct).unwrap();
+    assert_eq!(
+        direct_sst.block_metas().unwrap(),
+        sst.block_metas().unwrap()
+    );
+    for idx in 0..num_of_keys() {
+        assert_eq!(
+            direct_sst.get(key_of(idx).as_key_slice()).unwrap(),
+            Some(Bytes::from(value_of(idx)))
+        );
+    }
+}
+
+#[test]
+fn test_sst_compression() {
+    let dir = tempdir().unwrap();
+    let build = |compression: Compression, name: &str| {
+        let mut builder = SsTableBuilder::new(4096).with_compression(compression);
+        for idx in 0..num_of_keys() {
+            let value = format!("value_{:03}_{}", idx, "x".repeat(200));
+            builder
+                .add(key_of(idx).as_key_slice(), value.as_bytes())
+                .unwrap();
+        }
+        let path = dir.path().join(name);
+        builder.build_for_test(&path).unwrap();
+        Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap())
+    };
+    let plain = build(Compression::None, "1.sst");
+    let compressed = build(Compression::Lz4, "2.sst");
+    assert!(compressed.table_size() < plain.table_size());
+    assert!(compressed
+        .block_metas()
+        .unwrap()
+        .iter()
+        .all(|meta| meta.compression == Compression::Lz4));
+
+    let entries = |sst: Arc<SsTable>| {
+        sst.iter()
+            .unwrap()
+            .into_entries()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap()
+    };
+    assert_eq!(entries(compressed.clone()), entries(plain));
+    assert!(compressed.verify().unwrap().is_ok());
+}
+
+#[test]
+fn test_sst_builder_finish_block() {
+    let mut builder = SsTableBuilder::new(4096);
+    builder.finish_block();
+    for idx in 0..num_of_keys() {
+        builder
+            .add(key_of(idx).as_key_slice(), &value_of(idx))
+            .unwrap();
+        builder.finish_block();
+        builder.finish_block();
+    }
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    assert_eq!(sst.num_of_blocks(), num_of_keys());
+    for (idx, meta) in sst.block_metas().unwrap().iter().enumerate() {
+        assert_eq!(meta.first_key.as_key_slice(), key_of(idx).as_key_slice());
+        assert_eq!(meta.last_key.as_key_slice(), key_of(idx).as_key_slice());
+    }
+}
+
+#[test]
+fn test_sst_sequential_iterator_advice() {
+    use crate::table::Advice;
+
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    let num_blocks = sst.num_of_blocks();
+    crate::table::take_advice_log();
+
+    let iter = SsTableIterator::create_sequential(sst.clone(), 4).unwrap();
+    assert_eq!(iter.into_entries().count(), num_of_keys());
+    assert!(crate::table::take_advice_log().is_empty());
+
+    let iter = SsTableIterator::create_sequential_with_advice(sst.clone(), 4).unwrap();
+    assert_eq!(iter.into_entries().count(), num_of_keys());
+    let mut expected = vec![(0, sst.block_meta_offset as u64, Advice::Sequential)];
+    for window in (4..num_blocks).step_by(4) {
+        let start = sst.block_meta(window - 4).unwrap().offset as u64;
+        let end = sst.block_meta(window).unwrap().offset as u64;
+        expected.push((start, end - start, Advice::DontNeed));
+    }
+    assert_eq!(crate::table::take_advice_log(), expected);
+}
+
+#[test]
+fn test_file_object_error_context() {
+    let (dir, sst) = generate_sst();
+    let path = dir.path().join("1.sst");
+    let file = FileObject::open(&path).unwrap();
+    let err = format!("{:#}", file.read(file.size() - 2, 10).unwrap_err());
+    assert!(err.contains(&format!("{:?}", path)), "{}", err);
+    assert!(
+        err.contains(&format!("offset {} len 10", file.size() - 2)),
+        "{}",
+        err
+    );
+
+    let err = format!("{:#}", sst.read_block(sst.num_of_blocks()).err().unwrap());
+    assert!(err.contains("out of bounds of SST 0"), "{}", err);
+
+    let missing = dir.path().join("missing.sst");
+    let err = format!("{:#}", FileObject::open(&missing).err().unwrap());
+    assert!(err.contains(&format!("{:?}", missing)), "{}", err);
+
+    let meta_only = SsTable::create_meta_only(
+        7,
+        100,
+        key_of(0).into_key_bytes(),
+        key_of(1).into_key_bytes(),
+    );
+    assert!(meta_only.read_block(0).is_err());
+    assert!(meta_only.file.read(0, 10).is_err());
+}
+
+#[test]
+fn test_sst_build_from_iter() {
+    use super::harness::MockIterator;
+
+    let i1 = MockIterator::new(vec![
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("c"), Bytes::from("3.1")),
+        (Bytes::from("e"), Bytes::new()),
+    ]);
+    let i2 = MockIterator::new(vec![
+        (Bytes::from("a"), Bytes::from("1.2")),
+        (Bytes::from("b"), Bytes::from("2.2")),
+        (Bytes::from("d"), Bytes::from("4.2")),
+    ]);
+    let merged = vec![
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("b"), Bytes::from("2.2")),
+        (Bytes::from("c"), Bytes::from("3.1")),
+        (Bytes::from("d"), Bytes::from("4.2")),
+        (Bytes::from("e"), Bytes::new()),
+    ];
+    let iter = MergeIterator::create(vec![Box::new(i1), Box::new(i2)]);
+    let dir = tempdir().unwrap();
+    let sst = SsTableBuilder::new(16)
+        .build_from_iter(iter, 1, None, dir.path().join("1.sst"))
+        .unwrap();
+    assert_eq!(sst.sst_id(), 1);
+    assert!(sst.num_of_blocks() > 1);
+    let entries = Arc::new(sst)
+        .iter()
+        .unwrap()
+        .into_entries()
+        .map(|entry| entry.map(|(key, value)| (key.into_inner(), value)))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(entries, merged);
+}
+
+#[test]
+fn test_sst_builder_estimated_file_size() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        builder
+            .add(key_of(idx).as_key_slice(), &value_of(idx))
+            .unwrap();
+    }
+    let estimate = builder.estimated_file_size();
+    assert!(estimate > builder.estimated_size());
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    let actual = sst.table_size() as usize;
+    assert!(
+        estimate.abs_diff(actual) * 50 <= actual,
+        "estimate {} actual {}",
+        estimate,
+        actual
+    );
+}
+
+#[test]
+fn test_block_meta_large_offsets() {
+    let metas = (0..4u64)
+        .map(|idx| BlockMeta {
+            offset: (u32::MAX as u64 + 1 + idx * (1 << 32)) as usize,
+            first_key: key_of(idx as usize * 2).into_key_bytes(),
+            last_key: key_of(idx as usize * 2 + 1).into_key_bytes(),
+            compression: Compression::None,
+        })
+        .collect::<Vec<_>>();
+    let mut buf = Vec::new();
+    BlockMeta::encode_block_meta(&metas, &mut buf);
+    assert_eq!(BlockMeta::decode_block_meta(&buf).unwrap(), metas);
+}
+
+#[test]
+fn test_sst_format_version() {
+    let (_dir, sst) = generate_sst();
+    let mut data = sst.file.read(0, sst.table_size()).unwrap();
+    assert!(SsTable::open_from_bytes(0, None, Bytes::from(data.clone())).is_ok());
+    let len = data.len();
+    data[len - 1] ^= 0xff;
+    let err = SsTable::open_from_bytes(0, None, Bytes::from(data))
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("format version"), "{}", err);
+    assert!(SsTable::open_from_bytes(0, None, Bytes::from_static(b"tiny")).is_err());
+}
+
+#[test]
+fn test_sst_build_empty() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let err = SsTableBuilder::new(128)
+        .build_for_test(&path)
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("empty"), "{}", err);
+    assert!(!path.exists());
+
+    let builder = SsTableBuilder::new_streaming(128, &path).unwrap();
+    assert!(builder.build_streaming(1, None).is_err());
+    assert!(!path.exists());
+    assert!(!dir.path().join("1.sst.tmp").exists());
+}
+
+#[test]
+fn test_sst_build_single_entry() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let mut builder = SsTableBuilder::new(128);
+    builder.add(key_of(0).as_key_slice(), &value_of(0)).unwrap();
+    builder.build_for_test(&path).unwrap();
+    let sst = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(sst.num_of_blocks(), 1);
+    assert_eq!(sst.first_key().as_key_slice(), key_of(0).as_key_slice());
+    assert_eq!(sst.last_key().as_key_slice(), key_of(0).as_key_slice());
+    assert_eq!(
+        sst.get(key_of(0).as_key_slice()).unwrap(),
+        Some(Bytes::from(value_of(0)))
+    );
+}
+
+#[test]
+fn test_sst_build_with_stats() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(4096).with_bloom_fp_rate(0.01);
+    for idx in 0..4 {
+        builder
+            .add(key_of(idx).as_key_slice(), &value_of(idx))
+            .unwrap();
+    }
+    let (sst, stats) = builder
+        .build_with_stats(1, None, dir.path().join("1.sst"))
+        .unwrap();
+    let data_size = sst.block_meta_offset;
+    assert_eq!(
+        stats,
+        SsTableBuildStats {
+            num_blocks: 1,
+            num_entries: 4,
+            data_size,
+            // 4 keys at 10 bits per key round up to the minimum of 64 bits, plus one byte for `k`.
+            bloom_size: 9,
+            max_ts: 0,
+        }
+    );
+    assert_eq!(data_size, sst.read_block(0).unwrap().encode().len());
+}
+
+#[test]
+fn test_sst_iterator_reverse() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    assert!(sst.num_of_blocks() > 1);
+    let mut iter = SsTableIterator::create_and_seek_to_last(sst.clone()).unwrap();
+    for idx in (0..num_of_keys()).rev() {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), key_of(idx).as_key_slice());
+        assert_eq!(iter.value(), value_of(idx));
+        iter.prev().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    // Change direction in the middle of a scan.
+    let mut iter = SsTableIterator::create_and_seek_to_key(sst, key_of(50).as_key_slice()).unwrap();
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), key_of(49).as_key_slice());
+    iter.next().unwrap();
+    iter.next().unwrap();
+    assert_eq!(iter.key(), key_of(51).as_key_slice());
+}
+
+#[test]
+fn test_multi_sst_builder() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_path_buf();
+    let mut next_id = 10;
+    let mut builder = MultiSsTableBuilder::new(
+        128,
+        512,
+        move || {
+            next_id += 1;
+            next_id
+        },
+        move |id| path.join(format!("{}.sst", id)),
+    );
+    for idx in 0..num_of_keys() {
+        builder
+            .add(key_of(idx).as_key_slice(), &value_of(idx))
+            .unwrap();
+    }
+    let ssts = builder.finish().unwrap();
+    assert!(ssts.len() > 1);
+    for (idx, sst) in ssts.iter().enumerate() {
+        assert_eq!(sst.sst_id(), 11 + idx);
+        assert!(dir.path().join(format!("{}.sst", sst.sst_id())).exists());
+    }
+    for pair in ssts.windows(2) {
+        assert!(pair[0].last_key() < pair[1].first_key());
+    }
+    let entries = ssts
+        .into_iter()
+        .flat_map(|sst| sst.iter().unwrap().into_entries())
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(entries.len(), num_of_keys());
+    for (idx, (key, value)) in entries.into_iter().enumerate() {
+        assert_eq!(key.as_key_slice(), key_of(idx).as_key_slice());
+        assert_eq!(value, Bytes::from(value_of(idx)));
+    }
+}
+
+#[test]
+fn test_sst_iterator_end_bound_mid_block() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    let first_idx_of = |block_idx: usize| {
+        let first_key = &sst.block_meta(block_idx).unwrap().first_key;
+        (0..num_of_keys())
+            .find(|idx| key_of(*idx).raw_ref() == first_key.raw_ref())
+            .unwrap()
+    };
+    // A key in the middle of block 1.
+    let mid_idx = first_idx_of(1) + 1;
+    assert!(mid_idx + 1 < first_idx_of(2));
+    for (end_bound, expected_last_idx) in [
+        (Bound::Included(key_of(mid_idx).into_key_bytes()), mid_idx),
+        (
+            Bound::Excluded(key_of(mid_idx).into_key_bytes()),
+            mid_idx - 1,
+        ),
+    ] {
+        let mut iter = SsTableIterator::create_and_seek_to_key_bounded(
+            sst.clone(),
+            key_of(0).as_key_slice(),
+            end_bound,
+        )
+        .unwrap();
+        let reads = crate::table::read_count();
+        for idx in 0..=expected_last_idx {
+            assert!(iter.is_valid());
+            assert_eq!(iter.key(), key_of(idx).as_key_slice());
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+        iter.next().unwrap();
+        assert!(!iter.is_valid());
+        // Only block 1 is read after the seek, and block 2 is never loaded.
+        assert_eq!(crate::table::read_count() - reads, 1);
+    }
+}
+
+#[test]
+fn test_sst_seek_reads_one_block() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    assert!(sst.num_of_blocks() > 2);
+    let key = sst.block_meta(2).unwrap().first_key.clone();
+    let reads = crate::table::read_count();
+    let iter = SsTableIterator::create_and_seek_to_key(sst.clone(), key.as_key_slice()).unwrap();
+    assert_eq!(iter.key(), key.as_key_slice());
+    assert_eq!(crate::table::read_count() - reads, 1);
+
+    let reads = crate::table::read_count();
+    let iter = SsTableIterator::create_and_seek_to_last(sst).unwrap();
+    assert_eq!(iter.key(), key_of(num_of_keys() - 1).as_key_slice());
+    assert_eq!(crate::table::read_count() - reads, 1);
+}
+
+#[test]
+fn test_sst_compaction_scan_bypasses_cache() {
+    use crate::lsm_storage::BlockCache;
+
+    let (dir, _) = generate_sst();
+    let cache = Arc::new(BlockCache::new(4));
+    let file = FileObject::open(&dir.path().join("1.sst")).unwrap();
+    let sst = Arc::new(SsTable::open(0, Some(cache.clone()), file).unwrap());
+    assert!(sst.num_of_blocks() > 4);
+
+    // Block 1 is hot.
+    let hot = sst.read_block_cached(1).unwrap();
+    let iter = SsTableIterator::create_for_compaction(sst.clone()).unwrap();
+    assert_eq!(iter.into_entries().count(), num_of_keys());
+    cache.sync();
+    assert_eq!(cache.entry_count(), 1);
+    assert!(Arc::ptr_eq(&cache.get(&(0, 1)).unwrap(), &hot));
+
+    // A regular scan fills the cache.
+    let iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    assert_eq!(iter.into_entries().count(), num_of_keys());
+    cache.sync();
+    assert!(cache.entry_count() > 1);
+}
+
+#[test]
+fn test_sst_iterator_num_active_iterators() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    let iters = vec![
+        Box::new(SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap()),
+        Box::new(
+            SsTableIterator::create_and_seek_to_key(sst.clone(), key_of(50).as_key_slice())
+                .unwrap(),
+        ),
+        Box::new(
+            SsTableIterator::create_and_seek_to_key(sst.clone(), key_of(99).as_key_slice())
+                .unwrap(),
+        ),
+    ];
+    let mut iter = MergeIterator::create(iters);
+    assert_eq!(iter.num_active_iterators(), 3);
+    while iter.is_valid() {
+        iter.next().unwrap();
+    }
+    assert_eq!(iter.num_active_iterators(), 0);
+
+    let mut iter = SsTableIterator::create_and_seek_to_key(sst, key_of(99).as_key_slice()).unwrap();
+    assert_eq!(iter.num_active_iterators(), 1);
+    iter.next().unwrap();
+    assert_eq!(iter.num_active_iterators(), 0);
+}
+
+#[test]
+fn test_sst_iterator_prefetch() {
+    use std::time::{Duration, Instant};
+
+    use crate::lsm_storage::BlockCache;
+
+    let (dir, _) = generate_sst();
+    let data = Bytes::from(std::fs::read(dir.path().join("1.sst")).unwrap());
+    let scan = |prefetch: bool| {
+        let cache = Arc::new(BlockCache::new(1024));
+        let file = FileObject::from_bytes_with_latency(data.clone(), Duration::from_millis(4));
+        let sst = Arc::new(SsTable::open(0, Some(cache.clone()), file).unwrap());
+        let start = Instant::now();
+        let mut iter =
+            SsTableIterator::create_and_seek_to_first_with_prefetch(sst.clone(), prefetch).unwrap();
+        for idx in 0..num_of_keys() {
+            assert_eq!(iter.key(), key_of(idx).as_key_slice());
+            assert_eq!(iter.value(), value_of(idx));
+            // Simulate some work on each entry, during which the next block can be fetched.
+            std::thread::sleep(Duration::from_millis(2));
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+        let elapsed = start.elapsed();
+        cache.sync();
+        assert_eq!(cache.entry_count() as usize, sst.num_of_blocks());
+        elapsed
+    };
+    let without_prefetch = scan(false);
+    let with_prefetch = scan(true);
+    assert!(
+        with_prefetch * 4 < without_prefetch * 3,
+        "prefetch took {:?}, without prefetch {:?}",
+        with_prefetch,
+        without_prefetch
+    );
+}
+
+#[test]
+fn test_sst_seek_within_loaded_block() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    let last = sst.block_meta(0).unwrap().last_key.clone();
+    let reads = crate::table::read_count();
+    iter.seek_to_key(last.as_key_slice()).unwrap();
+    assert_eq!(iter.key(), last.as_key_slice());
+    iter.seek_to_key(key_of(0).as_key_slice()).unwrap();
+    assert_eq!(iter.key(), key_of(0).as_key_slice());
+    assert_eq!(crate::table::read_count(), reads);
+
+    // Seeking outside of the loaded block reads the target block.
+    iter.seek_to_key(key_of(50).as_key_slice()).unwrap();
+    assert_eq!(iter.key(), key_of(50).as_key_slice());
+    assert_eq!(crate::table::read_count(), reads + 1);
+}
+
+#[test]
+fn test_sst_iterator_current_block_idx() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    let mut blk_idx = 0;
+    for _ in 0..num_of_keys() {
+        assert_eq!(iter.current_block_idx(), blk_idx);
+        iter.next().unwrap();
+        if iter.is_valid() && iter.key() > sst.block_meta(blk_idx).unwrap().last_key.as_key_slice()
+        {
+            blk_idx += 1;
+        }
+    }
+    assert_eq!(blk_idx, sst.num_of_blocks() - 1);
+}
+
+#[test]
+fn test_sst_dump() {
+    let (_dir, sst) = generate_sst();
+
+    let mut out = Vec::new();
+    sst.dump(&mut out, DumpFormat::Human).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains(&format!("{} blocks", sst.num_of_blocks())));
+    assert!(out.contains("block 0: offset 0, first key key_000"));
+    assert!(!out.contains("value_"));
+
+    let mut out = Vec::new();
+    sst.dump_with_entries(&mut out, DumpFormat::Human).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("key_005 (7 bytes) => value_0000000001 (16 bytes)"));
+
+    let mut out = Vec::new();
+    sst.dump_with_entries(&mut out, DumpFormat::Jsonl).unwrap();
+    let lines = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    let count = |ty: &str| lines.iter().filter(|line| line["type"] == ty).count();
+    assert_eq!(count("footer"), 1);
+    assert_eq!(count("block"), sst.num_of_blocks());
+    assert_eq!(count("entry"), num_of_keys());
+    let entry = lines.iter().find(|line| line["type"] == "entry").unwrap();
+    assert_eq!(entry["key"], "key_000");
+    assert_eq!(entry["value_size"], 16);
+}
+
+#[test]
+fn test_sst_open_positional_key_range() {
+    let (dir, sst) = generate_sst();
+    let path = dir.path().join("1.sst");
+    let mut data = std::fs::read(&path).unwrap();
+    // Make the first key of block 1 sort before the first key of the table, as happens for a later
+    // block holding older versions of a key once keys carry descending timestamps. The new key
+    // shares as many bytes with its neighbours as the old one, so the encoded metas keep their size.
+    let mut metas = sst.block_metas().unwrap();
+    metas[1].first_key = KeyVec::for_testing_from_vec_no_ts(b"key_0!!".to_vec()).into_key_bytes();
+    let mut encoded = Vec::new();
+    BlockMeta::encode_block_meta(&metas, &mut encoded);
+    let meta_range = sst.block_meta_offset..sst.block_meta_offset + encoded.len();
+    let mut original = Vec::new();
+    BlockMeta::encode_block_meta(&sst.block_metas().unwrap(), &mut original);
+    assert_eq!(data[meta_range.clone()], original[..]);
+    data[meta_range].copy_from_slice(&encoded);
+    let sst = SsTable::open_for_test(FileObject::from_bytes(Bytes::from(data))).unwrap();
+    assert_eq!(sst.block_meta(1).unwrap().first_key.raw_ref(), b"key_0!!");
+    assert_eq!(sst.first_key().as_key_slice(), key_of(0).as_key_slice());
+    assert_eq!(
+        sst.last_key().as_key_slice(),
+        key_of(num_of_keys() - 1).as_key_slice()
+    );
+}
+
+#[test]
+fn test_sst_reverse_merge() {
+    let dir = tempdir().unwrap();
+    let build = |id: usize, tag: &str, idxs: Vec<usize>| {
+        let mut builder = SsTableBuilder::new(128);
+        for idx in idxs {
+            let value = format!("{}{}", tag, idx);
+            builder
+                .add(key_of(idx).as_key_slice(), value.as_bytes())
+                .unwrap();
+        }
+        let path = dir.path().join(format!("{}.sst", id));
+        Arc::new(builder.build(id, None, path).unwrap())
+    };
+    let ssts = [
+        build(0, "a", (0..num_of_keys()).step_by(2).collect()),
+        build(1, "b", (0..num_of_keys()).step_by(3).collect()),
+        build(2, "c", (40..60).collect()),
+    ];
+    let expected_value = |idx: usize| match idx {
+        _ if idx.is_multiple_of(2) => format!("a{}", idx),
+        _ if idx.is_multiple_of(3) => format!("b{}", idx),
+        _ => format!("c{}", idx),
+    };
+    let expected = (0..num_of_keys())
+        .rev()
+        .filter(|idx| idx.is_multiple_of(2) || idx.is_multiple_of(3) || (40..60).contains(idx))
+        .collect::<Vec<_>>();
+
+    let iters = ssts
+        .iter()
+        .map(|sst| Box::new(ReverseSsTableIterator::create_and_seek_to_last(sst.clone()).unwrap()))
+        .collect();
+    let mut iter = MergeIterator::create_reverse(iters);
+    for &idx in &expected {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), key_of(idx).as_key_slice());
+        assert_eq!(iter.value(), expected_value(idx).as_bytes());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    // Seek to a key that none of the SSTs holds, and prefer the last SST on ties.
+    let iters = ssts
+        .iter()
+        .rev()
+        .map(|sst| {
+            Box::new(
+                ReverseSsTableIterator::create_and_seek_to_key(
+                    sst.clone(),
+                    key_of(55).as_key_slice(),
+                )
+                .unwrap(),
+            )
+        })
+        .collect();
+    let mut iter = MergeIterator::create_reverse(iters);
+    assert_eq!(iter.key(), key_of(55).as_key_slice());
+    assert_eq!(iter.value(), b"c55");
+    iter.next().unwrap();
+    assert_eq!(iter.key(), key_of(54).as_key_slice());
+    assert_eq!(iter.value(), b"c54");
+    let remaining = std::iter::from_fn(|| {
+        let key = iter.is_valid().then(|| iter.key().to_key_vec());
+        iter.next().unwrap();
+        key
+    })
+    .count();
+    assert_eq!(remaining, expected.iter().filter(|&&idx| idx <= 54).count());
+
+    let iter =
+        ReverseSsTableIterator::create_and_seek_to_key(ssts[2].clone(), key_of(39).as_key_slice())
+            .unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_concat_iterator() {
+    let dir = tempdir().unwrap();
+    let ranges = [0..10, 20..30, 40..50];
+    let ssts = ranges
+        .iter()
+        .enumerate()
+        .map(|(id, range)| {
+            let mut builder = SsTableBuilder::new(128);
+            for idx in range.clone() {
+                builder
+                    .add(key_of(idx).as_key_slice(), &value_of(idx))
+                    .unwrap();
+            }
+            let path = dir.path().join(format!("{}.sst", id));
+            Arc::new(builder.build(id, None, path).unwrap())
+        })
+        .collect::<Vec<_>>();
+    let all_keys = ranges.iter().cloned().flatten().collect::<Vec<_>>();
+    let check = |mut iter: SstConcatIterator, expected: &[usize]| {
+        for &idx in expected {
+            assert!(iter.is_valid());
+            assert_eq!(iter.key(), key_of(idx).as_key_slice());
+            assert_eq!(iter.value(), value_of(idx));
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    };
+
+    check(
+        SstConcatIterator::create_and_seek_to_first(ssts.clone()).unwrap(),
+        &all_keys,
+    );
+    let seek = |key: KeyVec| {
+        SstConcatIterator::create_and_seek_to_key(ssts.clone(), key.as_key_slice()).unwrap()
+    };
+    // Inside an SST.
+    check(seek(key_of(25)), &all_keys[15..]);
+    // Between two SSTs, both right after the last key of an SST and right before the first key of
+    // the next one.
+    check(seek(key_of(10)), &all_keys[10..]);
+    check(seek(key_of(19)), &all_keys[10..]);
+    // Before and after all SSTs.
+    check(
+        seek(KeyVec::for_testing_from_vec_no_ts(b"a".to_vec())),
+        &all_keys,
+    );
+    check(
+        seek(KeyVec::for_testing_from_vec_no_ts(b"key_".to_vec())),
+        &all_keys,
+    );
+    check(seek(key_of(50)), &[]);
+
+    check(
+        SstConcatIterator::create_and_seek_to_first(vec![]).unwrap(),
+        &[],
+    );
+    check(
+        SstConcatIterator::create_and_seek_to_key(vec![], key_of(0).as_key_slice()).unwrap(),
+        &[],
+    );
+}
+
+fn generate_sst_in_memory() -> SsTable {
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        builder
+            .add(key_of(idx).as_key_slice(), &value_of(idx)[..])
+            .unwrap();
+    }
+    builder.build_in_memory(1).unwrap()
+}
+
+#[test]
+fn test_sst_in_memory() {
+    let (_dir, disk_sst) = generate_sst();
+    let sst = generate_sst_in_memory();
+    assert_eq!(sst.block_metas().unwrap(), disk_sst.block_metas().unwrap());
+    assert_eq!(sst.table_size(), disk_sst.table_size());
+    assert_eq!(
+        sst.file.read(0, sst.table_size()).unwrap(),
+        disk_sst.file.read(0, disk_sst.table_size()).unwrap()
+    );
+
+    check_sst_iterator(generate_sst_in_memory());
+    check_sst_seek_key(generate_sst_in_memory());
+    check_sst_get(&sst);
+    let reopened = SsTable::open_for_test(generate_sst_in_memory().file).unwrap();
+    assert_eq!(reopened.block_metas().unwrap(), sst.block_metas().unwrap());
+    check_sst_iterator(reopened);
+
+    let builder = SsTableBuilder::new(128);
+    assert!(builder.build_in_memory(1).is_err());
+}
+
+#[test]
+fn test_block_meta_prefix_encoding() {
+    let prefix = "a/long/common/prefix/shared/by/every/key/of/the/table/".repeat(4);
+    let key = |idx: usize| {
+        KeyVec::for_testing_from_vec_no_ts(format!("{}{:06}", prefix, idx).into_bytes())
+            .into_key_bytes()
+    };
+    let metas = (0..1000)
+        .map(|idx| BlockMeta {
+            offset: idx * 4096 + idx % 7,
+            first_key: key(idx * 10),
+            last_key: key(idx * 10 + 9),
+            compression: Compression::None,
+        })
+        .collect::<Vec<_>>();
+    let mut buf = Vec::new();
+    BlockMeta::encode_block_meta(&metas, &mut buf);
+    assert_eq!(BlockMeta::decode_block_meta(&buf).unwrap(), metas);
+    let encoded_len = metas
+        .iter()
+        .enumerate()
+        .map(|(idx, meta)| {
+            let prev = idx.checked_sub(1).map(|idx| &metas[idx]);
+            BlockMeta::encoded_len(
+                prev,
+                meta.offset,
+                meta.first_key.raw_ref(),
+                meta.last_key.raw_ref(),
+            )
+        })
+        .sum::<usize>();
+    assert_eq!(buf.len(), 8 + encoded_len);
+
+    // Storing the offsets as u64 and both keys in full would take over 400 bytes per block.
+    let plain_len = metas
+        .iter()
+        .map(|meta| 8 + 2 + meta.first_key.len() + 2 + meta.last_key.len() + 1)
+        .sum::<usize>();
+    assert!(plain_len > 400 * metas.len());
+    assert!(buf.len() * 20 < plain_len, "{} {}", buf.len(), plain_len);
+}
+
+#[test]
+fn test_sst_parallel_compression() {
+    let dir = tempdir().unwrap();
+    let fill = |mut builder: SsTableBuilder| {
+        // A seeded xorshift, so that the random values are the same for every builder.
+        let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+        let mut random_byte = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        };
+        for idx in 0..num_of_keys() * 10 {
+            let mut key = format!("key_{:05}", idx).into_bytes();
+            // Mix compressible and incompressible blocks, so some of them are stored as they are.
+            // An incompressible entry has random key and value bytes, and is larger than a block,
+            // so it fills a block of its own that LZ4 cannot shrink.
+            let value = if idx % 40 == 39 {
+                key.extend((0..64).map(|_| random_byte()));
+                (0..2048).map(|_| random_byte()).collect::<Vec<_>>()
+            } else {
+                format!("value_{:05}_{}", idx, "x".repeat(200)).into_bytes()
+            };
+            builder
+                .add(KeySlice::for_testing_from_slice_no_ts(&key), &value)
+                .unwrap();
+        }
+        builder
+    };
+    let new_builder = |num_workers: usize| {
+        SsTableBuilder::new(1024)
+            .with_compression(Compression::Lz4)
+            .with_compression_workers(num_workers)
+    };
+    let build = |num_workers: usize| {
+        let path = dir.path().join(format!("{}.sst", num_workers));
+        let sst = fill(new_builder(num_workers))
+            .build_for_test(&path)
+            .unwrap();
+        (sst.block_metas().unwrap(), std::fs::read(&path).unwrap())
+    };
+    let (metas, expected) = build(0);
+    assert!(metas
+        .iter()
+        .any(|meta| meta.compression == Compression::Lz4));
+    assert!(metas
+        .iter()
+        .any(|meta| meta.compression == Compression::None));
+    for num_workers in [1, 2, 4] {
+        let (actual_metas, actual) = build(num_workers);
+        assert_eq!(actual_metas, metas);
+        assert!(actual == expected, "{} workers", num_workers);
+    }
+
+    let mut builder = fill(new_builder(4));
+    assert!(builder.estimated_size() > 0);
+    builder.finish_block();
+    let sst = builder.build_in_memory(1).unwrap();
+    assert_eq!(sst.file.read(0, sst.table_size()).unwrap(), expected);
+
+    let path = dir.path().join("streaming.sst");
+    let mut builder = SsTableBuilder::new_streaming(1024, &path)
+        .unwrap()
+        .with_compression(Compression::Lz4)
+        .with_compression_workers(4);
+    builder = fill(builder);
+    let sst = builder.build_streaming(1, None).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), expected);
+    assert!(Arc::new(sst).verify().unwrap().is_ok());
+}
+
+#[test]
+fn test_sst_add_from_iter() {
+    let build = |idxs: Vec<usize>, tag: &str| {
+        let mut builder = SsTableBuilder::new(128);
+        for idx in idxs {
+            let value = format!("{}_{}", tag, idx);
+            builder
+                .add(key_of(idx).as_key_slice(), value.as_bytes())
+                .unwrap();
+        }
+        Arc::new(builder.build_in_memory(0).unwrap())
+    };
+    // the newer SST overrides every third key of the older one
+    let newer = build((0..num_of_keys()).step_by(3).collect(), "new");
+    let older = build((0..num_of_keys()).collect(), "old");
+    let merge = || {
+        MergeIterator::create(vec![
+            Box::new(SsTableIterator::create_and_seek_to_first(newer.clone()).unwrap()),
+            Box::new(SsTableIterator::create_and_seek_to_first(older.clone()).unwrap()),
+        ])
+    };
+    let expected = (0..num_of_keys())
+        .map(|idx| {
+            let tag = if idx % 3 == 0 { "new" } else { "old" };
+            (
+                key_of(idx).into_key_bytes(),
+                Bytes::from(format!("{}_{}", tag, idx)),
+            )
+        })
+        .collect::<Vec<_>>();
+    let entries = |sst: SsTable| {
+        Arc::new(sst)
+            .iter()
+            .unwrap()
+            .into_entries()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap()
+    };
+
+    let mut iter = merge();
+    let mut builder = SsTableBuilder::new(128);
+    assert_eq!(
+        builder.add_from_iter(&mut iter, None).unwrap(),
+        num_of_keys()
+    );
+    assert!(!iter.is_valid());
+    assert_eq!(entries(builder.build_in_memory(1).unwrap()), expected);
+
+    // with a budget, the pairs are split into SSTs of about that size
+    let limit = 512;
+    let mut iter = merge();
+    let mut ssts = Vec::new();
+    while iter.is_valid() {
+        let mut builder = SsTableBuilder::new(128);
+        let num_added = builder.add_from_iter(&mut iter, Some(limit)).unwrap();
+        assert!(num_added > 0);
+        if iter.is_valid() {
+            assert!(builder.estimated_size() >= limit);
+        }
+        ssts.push(builder.build_in_memory(ssts.len()).unwrap());
+    }
+    assert!(ssts.len() > 2);
+    let split = ssts.into_iter().flat_map(entries).collect::<Vec<_>>();
+    assert_eq!(split, expected);
+
+    let mut builder = SsTableBuilder::new(128);
+    assert_eq!(
+        builder
+            .add_from_iter(&mut MergeIterator::<SsTableIterator>::create(vec![]), None)
+            .unwrap(),
+        0
+    );
+}
+
+#[test]
+fn test_sst_iterator_count() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    let iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    assert_eq!(iter.remaining_upper_bound(), Some(num_of_keys()));
+    assert_eq!(iter.count().unwrap(), num_of_keys());
+
+    let iter =
+        SsTableIterator::create_and_seek_to_key(sst.clone(), key_of(90).as_key_slice()).unwrap();
+    assert!(iter.remaining_upper_bound().unwrap() >= num_of_keys() - 90);
+    assert_eq!(iter.count().unwrap(), num_of_keys() - 90);
+
+    let iter = SsTableIterator::create_and_seek_to_key(
+        sst.clone(),
+        KeySlice::for_testing_from_slice_no_ts(b"zzz"),
+    )
+    .unwrap();
+    assert_eq!(iter.remaining_upper_bound(), Some(0));
+    assert_eq!(iter.count().unwrap(), 0);
+
+    // the two SSTs hold the same keys, so the merged count is half of the upper bound
+    let iter = MergeIterator::create(vec![
+        Box::new(SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap()),
+        Box::new(SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap()),
+    ]);
+    assert_eq!(iter.remaining_upper_bound(), Some(2 * num_of_keys()));
+    assert_eq!(iter.count().unwrap(), num_of_keys());
+
+    let iter = SstConcatIterator::create_and_seek_to_first(vec![sst]).unwrap();
+    assert_eq!(iter.remaining_upper_bound(), Some(num_of_keys()));
+    assert_eq!(iter.count().unwrap(), num_of_keys());
+}
+
+#[test]
+fn test_block_cache_capacity_bytes() {
+    use crate::lsm_storage::block_cache_with_capacity_bytes;
+
+    let (dir, sst) = generate_sst();
+    let block_size = sst.read_block(0).unwrap().memory_usage() as u64;
+    let capacity = block_size * 4;
+    let cache = Arc::new(block_cache_with_capacity_bytes(capacity));
+    let data = Bytes::from(std::fs::read(dir.path().join("1.sst")).unwrap());
+    let sst = SsTable::open(0, Some(cache.clone()), FileObject::from_bytes(data)).unwrap();
+    assert!(sst.num_of_blocks() > 8);
+    for _ in 0..2 {
+        for idx in 0..sst.num_of_blocks() {
+            let block = sst.read_block_cached(idx).unwrap();
+            assert_eq!(block.data, sst.read_block(idx).unwrap().data);
+            cache.sync();
+            assert!(
+                cache.weighted_size() <= capacity,
+                "{} bytes cached with a capacity of {}",
+                cache.weighted_size(),
+                capacity
+            );
+        }
+    }
+    assert!(cache.entry_count() > 0);
+    assert!(cache.entry_count() < sst.num_of_blocks() as u64);
+}
+
+#[test]
+fn test_block_cache_mixed_block_sizes() {
+    use crate::lsm_storage::block_cache_with_capacity_bytes;
+
+    let dir = tempdir().unwrap();
+    let encode = |block_size: usize| {
+        let mut builder = SsTableBuilder::new(block_size);
+        for idx in 0..num_of_keys() {
+            builder
+                .add(key_of(idx).as_key_slice(), &value_of(idx))
+                .unwrap();
+        }
+        let path = dir.path().join(format!("{}.sst", block_size));
+        builder.build_for_test(&path).unwrap();
+        Bytes::from(std::fs::read(path).unwrap())
+    };
+    let (small_data, large_data) = (encode(64), encode(4096));
+    let small_size = SsTable::open(1, None, FileObject::from_bytes(small_data.clone()))
+        .unwrap()
+        .read_block(0)
+        .unwrap()
+        .memory_usage() as u64;
+    let large_size = SsTable::open(2, None, FileObject::from_bytes(large_data.clone()))
+        .unwrap()
+        .read_block(0)
+        .unwrap()
+        .memory_usage() as u64;
+    assert!(large_size > small_size * 20);
+
+    // Two jumbo blocks take as much of the budget as dozens of tiny ones.
+    let capacity = large_size * 2;
+    let cache = Arc::new(block_cache_with_capacity_bytes(capacity));
+    let small = SsTable::open(1, Some(cache.clone()), FileObject::from_bytes(small_data)).unwrap();
+    let large = SsTable::open(2, Some(cache.clone()), FileObject::from_bytes(large_data)).unwrap();
+    let blocks = (0..small.num_of_blocks())
+        .map(|idx| (&small, idx))
+        .chain((0..large.num_of_blocks()).map(|idx| (&large, idx)))
+        .collect::<Vec<_>>();
+    for _ in 0..2 {
+        for (sst, idx) in blocks.iter().rev().chain(blocks.iter()) {
+            sst.read_block_cached(*idx).unwrap();
+            cache.sync();
+            assert!(
+                cache.weighted_size() <= capacity,
+                "{} bytes cached with a capacity of {}",
+                cache.weighted_size(),
+                capacity
+            );
+        }
+    }
+    let cached_bytes = blocks
+        .iter()
+        .filter_map(|(sst, idx)| cache.get(&(sst.sst_id(), *idx)))
+        .map(|block| block.memory_usage() as u64)
+        .sum::<u64>();
+    assert_eq!(cached_bytes, cache.weighted_size());
+}
+
+/// An SST with one key per block, so that its index spans several partitions. The keys are even
+/// numbers, leaving a gap before each block.
+fn generate_many_block_sst(path: &std::path::Path) -> (SsTable, Vec<String>) {
+    let keys = (0..1000)
+        .map(|idx| format!("key_{:05}", idx * 2))
+        .collect::<Vec<_>>();
+    let mut builder = SsTableBuilder::new(16);
+    for key in &keys {
+        builder
+            .add(KeySlice::for_testing_from_slice_no_ts(key.as_bytes()), b"v")
+            .unwrap();
+    }
+    (builder.build_for_test(path).unwrap(), keys)
+}
+
+#[test]
+fn test_sst_two_level_index() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let (built, keys) = generate_many_block_sst(&path);
+    let sst = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(sst.num_of_blocks(), keys.len());
+    assert!(sst.block_index.num_partitions() > 4);
+    assert_eq!(sst.first_key().raw_ref(), keys[0].as_bytes());
+    assert_eq!(sst.last_key().raw_ref(), keys[keys.len() - 1].as_bytes());
+
+    // Opening only decodes the top-level index, and a lookup the partition it lands on.
+    let full_usage = built.meta_memory_usage();
+    let open_usage = sst.meta_memory_usage();
+    assert!(
+        open_usage * 10 < full_usage,
+        "{} {}",
+        open_usage,
+        full_usage
+    );
+    let key = KeySlice::for_testing_from_slice_no_ts(keys[500].as_bytes());
+    assert_eq!(sst.find_block_idx(key).unwrap(), 500);
+    let partition_usage = sst.meta_memory_usage();
+    assert!(partition_usage > open_usage);
+    assert!(partition_usage * 2 < full_usage);
+
+    for (idx, key) in keys.iter().enumerate() {
+        let key = KeySlice::for_testing_from_slice_no_ts(key.as_bytes());
+        assert_eq!(sst.find_block_idx(key).unwrap(), idx);
+        assert_eq!(sst.get(key).unwrap(), Some(Bytes::from_static(b"v")));
+        // A key in the gap before the block lands on it too.
+        let gap_key = format!("key_{:05}", idx * 2 + 1);
+        let gap_key = KeySlice::for_testing_from_slice_no_ts(gap_key.as_bytes());
+        assert_eq!(
+            sst.find_block_idx(gap_key).unwrap(),
+            (idx + 1).min(keys.len() - 1)
+        );
+        assert_eq!(sst.get(gap_key).unwrap(), None);
+    }
+    let first = KeySlice::for_testing_from_slice_no_ts(b"a");
+    assert_eq!(sst.find_block_idx(first).unwrap(), 0);
+    let last = KeySlice::for_testing_from_slice_no_ts(b"z");
+    assert_eq!(sst.find_block_idx(last).unwrap(), keys.len() - 1);
+
+    assert_eq!(sst.meta_memory_usage(), full_usage);
+    assert_eq!(sst.block_metas().unwrap(), built.block_metas().unwrap());
+    let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+    for key in &keys {
+        assert_eq!(iter.key().raw_ref(), key.as_bytes());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_corrupted_index_partition() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let (built, keys) = generate_many_block_sst(&path);
+    // Claim that partition 2 has no blocks, leaving the bytes of its metas unexpected.
+    let (offset, _) = built.block_index.location(2);
+    let mut data = std::fs::read(&path).unwrap();
+    data[offset..offset + 4].copy_from_slice(&0u32.to_be_bytes());
+
+    // Only the lookups landing on the partition fail.
+    let sst = SsTable::open_for_test(FileObject::from_bytes(Bytes::from(data))).unwrap();
+    let key = KeySlice::for_testing_from_slice_no_ts(keys[0].as_bytes());
+    assert_eq!(sst.get(key).unwrap(), Some(Bytes::from_static(b"v")));
+    let block_idx = sst.block_index.partition_blocks(2).start;
+    let key = KeySlice::for_testing_from_slice_no_ts(keys[block_idx].as_bytes());
+    let err = format!("{:#}", sst.get(key).unwrap_err());
+    assert!(err.contains("index partition 2"), "{}", err);
+    assert!(sst.read_block(block_idx).is_err());
+    assert!(sst.verify().is_err());
+}
+
+#[test]
+fn test_sst_block_sparse_index() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(4096).with_block_sparse_index(4);
+    for idx in 0..num_of_keys() {
+        builder
+            .add(key_of(idx).as_key_slice(), &value_of(idx))
+            .unwrap();
+    }
+    let path = dir.path().join("1.sst");
+    builder.build_for_test(&path).unwrap();
+    let sst = Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+    assert!(!sst.read_block(0).unwrap().sparse_index.is_empty());
+    assert!(sst.verify().unwrap().is_ok());
+    for idx in 0..num_of_keys() {
+        let key = key_of(idx);
+        assert_eq!(
+            sst.get(key.as_key_slice()).unwrap(),
+            Some(Bytes::from(value_of(idx)))
+        );
+        let iter =
+            SsTableIterator::create_and_seek_to_key(sst.clone(), key.as_key_slice()).unwrap();
+        assert_eq!(iter.key(), key.as_key_slice());
+    }
+}
+
+#[test]
+fn test_sst_advance_by() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        builder
+            .add(key_of(idx).as_key_slice(), &value_of(idx))
+            .unwrap();
+    }
+    let path = dir.path().join("1.sst");
+    builder.build_for_test(&path).unwrap();
+    let sst = Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+    assert!(sst.num_of_blocks() > 4);
+    let first_block_len = sst.read_block(0).unwrap().offsets.len();
+    for start in [0, first_block_len - 1] {
+        for n in [
+            0,
+            1,
+            first_block_len - 1,
+            first_block_len,
+            3 * first_block_len + 1,
+        ] {
+            let mut expected =
+                SsTableIterator::create_and_seek_to_key(sst.clone(), key_of(start).as_key_slice())
+                    .unwrap();
+            for _ in 0..n {
+                expected.next().unwrap();
+            }
+            let mut iter =
+                SsTableIterator::create_and_seek_to_key(sst.clone(), key_of(start).as_key_slice())
+                    .unwrap();
+            iter.advance_by(n).unwrap();
+            assert!(iter.is_valid());
+            assert_eq!(iter.key(), expected.key(), "start {} n {}", start, n);
+            assert_eq!(iter.key(), key_of(start + n).as_key_slice());
+            assert_eq!(iter.value(), expected.value());
+        }
+    }
+
+    // Advancing past the end exhausts the iterator.
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    iter.advance_by(num_of_keys() - 1).unwrap();
+    assert_eq!(iter.key(), key_of(num_of_keys() - 1).as_key_slice());
+    iter.advance_by(1).unwrap();
+    assert!(!iter.is_valid());
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    iter.advance_by(num_of_keys() + 10).unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_range() {
+    use std::ops::RangeBounds;
+
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        builder
+            .add(key_of(idx).as_key_slice(), &value_of(idx))
+            .unwrap();
+    }
+    let path = dir.path().join("1.sst");
+    builder.build_for_test(&path).unwrap();
+    let sst = Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+    assert!(sst.num_of_blocks() > 2);
+
+    // Existing keys, keys between two entries, and keys before and after all entries.
+    let keys = [
+        key_of(0).raw_ref().to_vec(),
+        key_of(3).raw_ref().to_vec(),
+        b"key_012".to_vec(),
+        key_of(num_of_keys() - 1).raw_ref().to_vec(),
+        b"key_".to_vec(),
+        b"key_999".to_vec(),
+    ];
+    let bounds = |key: &[u8]| {
+        [
+            Bound::Unbounded,
+            Bound::Included(key.to_vec()),
+            Bound::Excluded(key.to_vec()),
+        ]
+    };
+    fn as_slice(bound: &Bound<Vec<u8>>) -> Bound<KeySlice<'_>> {
+        bound
+            .as_ref()
+            .map(|key| KeySlice::for_testing_from_slice_no_ts(key))
+    }
+    for lower_key in &keys {
+        for upper_key in &keys {
+            for lower in bounds(lower_key) {
+                for upper in bounds(upper_key) {
+                    let expected = (0..num_of_keys())
+                        .filter(|&idx| {
+                            let key = key_of(idx).raw_ref().to_vec();
+                            RangeBounds::<Vec<u8>>::contains(&(lower.clone(), upper.clone()), &key)
+                        })
+                        .collect::<Vec<_>>();
+                    let mut iter = sst
+                        .clone()
+                        .range(as_slice(&lower), as_slice(&upper))
+                        .unwrap();
+                    for idx in expected {
+                        assert!(iter.is_valid(), "{:?} {:?}", lower, upper);
+                        assert_eq!(iter.key(), key_of(idx).as_key_slice());
+                        assert_eq!(iter.value(), value_of(idx));
+                        iter.next().unwrap();
+                    }
+                    assert!(!iter.is_valid(), "{:?} {:?}", lower, upper);
+                }
+            }
+        }
+    }
+}
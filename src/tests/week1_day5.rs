@@ -8,7 +8,9 @@ use tempfile::tempdir;
 
 use super::*;
 use crate::{
-    iterators::two_merge_iterator::TwoMergeIterator,
+    iterators::{
+        merge_iterator::MergeIterator, two_merge_iterator::TwoMergeIterator, StorageIterator,
+    },
     lsm_storage::{LsmStorageInner, LsmStorageOptions},
 };
 
@@ -262,3 +264,273 @@ fn test_task3_storage_get() {
     assert_eq!(storage.get(b"--").unwrap(), None);
     assert_eq!(storage.get(b"555").unwrap(), None);
 }
+
+#[test]
+fn test_two_merge_heterogeneous() {
+    // A merges several sources like the memtables do, and B stands for the SSTs.
+    let a = MergeIterator::create(vec![
+        Box::new(MockIterator::new(vec![
+            (Bytes::from("a"), Bytes::from("1.a")),
+            (Bytes::from("d"), Bytes::from("4.a")),
+        ])),
+        Box::new(MockIterator::new(vec![
+            (Bytes::from("c"), Bytes::from("3.a")),
+            (Bytes::from("f"), Bytes::from("6.a")),
+        ])),
+    ]);
+    let b = MockIterator::new(vec![
+        (Bytes::from("b"), Bytes::from("2.b")),
+        (Bytes::from("c"), Bytes::from("3.b")),
+        (Bytes::from("e"), Bytes::from("5.b")),
+        (Bytes::from("f"), Bytes::from("6.b")),
+        (Bytes::from("g"), Bytes::from("7.b")),
+    ]);
+    let mut iter = TwoMergeIterator::create(a, b).unwrap();
+    check_iter_result_by_key(
+        &mut iter,
+        vec![
+            (Bytes::from("a"), Bytes::from("1.a")),
+            (Bytes::from("b"), Bytes::from("2.b")),
+            (Bytes::from("c"), Bytes::from("3.a")),
+            (Bytes::from("d"), Bytes::from("4.a")),
+            (Bytes::from("e"), Bytes::from("5.b")),
+            (Bytes::from("f"), Bytes::from("6.a")),
+            (Bytes::from("g"), Bytes::from("7.b")),
+        ],
+    );
+}
+
+#[test]
+fn test_two_merge_errors() {
+    // B fails while being moved past the key A starts at.
+    let a = MockIterator::new(vec![(Bytes::from("a"), Bytes::from("1.a"))]);
+    let b = MockIterator::new_with_error(
+        vec![
+            (Bytes::from("a"), Bytes::from("1.b")),
+            (Bytes::from("b"), Bytes::from("2.b")),
+        ],
+        1,
+    );
+    assert!(TwoMergeIterator::create(a, b).is_err());
+
+    // A fails while advancing.
+    let a = MockIterator::new_with_error(
+        vec![
+            (Bytes::from("a"), Bytes::from("1.a")),
+            (Bytes::from("c"), Bytes::from("3.a")),
+            (Bytes::from("e"), Bytes::from("5.a")),
+        ],
+        2,
+    );
+    let b = MockIterator::new(vec![
+        (Bytes::from("b"), Bytes::from("2.b")),
+        (Bytes::from("c"), Bytes::from("3.b")),
+        (Bytes::from("d"), Bytes::from("4.b")),
+    ]);
+    let mut iter = TwoMergeIterator::create(a, b).unwrap();
+    assert_eq!(iter.num_active_iterators(), 2);
+    iter.next().unwrap();
+    iter.next().unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), b"c");
+    assert_eq!(iter.value(), b"3.a");
+    assert!(iter.next().is_err());
+
+    // B fails while being skipped over a key both sides hold.
+    let a = MockIterator::new(vec![
+        (Bytes::from("a"), Bytes::from("1.a")),
+        (Bytes::from("c"), Bytes::from("3.a")),
+    ]);
+    let b = MockIterator::new_with_error(
+        vec![
+            (Bytes::from("b"), Bytes::from("2.b")),
+            (Bytes::from("c"), Bytes::from("3.b")),
+            (Bytes::from("d"), Bytes::from("4.b")),
+        ],
+        2,
+    );
+    let mut iter = TwoMergeIterator::create(a, b).unwrap();
+    iter.next().unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), b"b");
+    assert!(iter.next().is_err());
+}
+
+#[test]
+fn test_storage_scan_tombstones() {
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    let sst = generate_sst(
+        10,
+        dir.path().join("10.sst"),
+        vec![
+            (Bytes::from_static(b"a"), Bytes::from_static(b"1")),
+            (Bytes::from_static(b"b"), Bytes::from_static(b"1")),
+            (Bytes::from_static(b"c"), Bytes::from_static(b"1")),
+        ],
+        Some(storage.block_cache.clone()),
+    );
+    {
+        let mut state = storage.state.write();
+        let mut snapshot = state.as_ref().clone();
+        snapshot.l0_sstables.push(sst.sst_id());
+        snapshot.sstables.insert(sst.sst_id(), sst.into());
+        *state = snapshot.into();
+    }
+    storage.delete(b"a").unwrap();
+    storage.put(b"b", b"2").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.put(b"a", b"3").unwrap();
+    storage.delete(b"b").unwrap();
+    storage.delete(b"d").unwrap();
+    // the newest tombstone of each key hides the older values in the memtables and the SST
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("a"), Bytes::from("3")),
+            (Bytes::from("c"), Bytes::from("1")),
+        ],
+    );
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan(Bound::Excluded(b"a"), Bound::Unbounded)
+            .unwrap(),
+        vec![(Bytes::from("c"), Bytes::from("1"))],
+    );
+    // the first key after the skipped tombstone is already out of the upper bound
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan(Bound::Included(b"b"), Bound::Excluded(b"c"))
+            .unwrap(),
+        vec![],
+    );
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    assert_eq!(storage.get(b"d").unwrap(), None);
+}
+
+#[test]
+fn test_storage_scan_leading_tombstones() {
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    for key in ["a", "b", "c", "d", "e"] {
+        storage.put(key.as_bytes(), b"1").unwrap();
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    for key in ["a", "b", "c"] {
+        storage.delete(key.as_bytes()).unwrap();
+    }
+    // the scan is positioned on three tombstones in a row before the first live key
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    assert!(iter.is_valid());
+    assert_eq!(iter.key(), b"d");
+    check_lsm_iter_result_by_key(
+        &mut iter,
+        vec![
+            (Bytes::from("d"), Bytes::from("1")),
+            (Bytes::from("e"), Bytes::from("1")),
+        ],
+    );
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan(Bound::Included(b"a"), Bound::Included(b"d"))
+            .unwrap(),
+        vec![(Bytes::from("d"), Bytes::from("1"))],
+    );
+}
+
+fn wide_value_storage(dir: &tempfile::TempDir, num_keys: usize) -> Arc<LsmStorageInner> {
+    let storage =
+        Arc::new(LsmStorageInner::open(dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    for idx in 0..num_keys {
+        let key = format!("key_{:05}", idx);
+        storage.put(key.as_bytes(), &[b'v'; 1024]).unwrap();
+        if idx % 3 == 0 {
+            storage.delete(key.as_bytes()).unwrap();
+        }
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    storage
+}
+
+#[test]
+fn test_storage_scan_keys() {
+    let dir = tempdir().unwrap();
+    let storage = wide_value_storage(&dir, 300);
+    // Deletes in the memtable shadow live keys of the SST, and puts shadow its tombstones.
+    for idx in (0..300).step_by(5) {
+        let key = format!("key_{:05}", idx);
+        if idx % 2 == 0 {
+            storage.delete(key.as_bytes()).unwrap();
+        } else {
+            storage.put(key.as_bytes(), b"new").unwrap();
+        }
+    }
+    for (lower, upper) in [
+        (Bound::Unbounded, Bound::Unbounded),
+        (
+            Bound::Excluded(&b"key_00010"[..]),
+            Bound::Included(&b"key_00200"[..]),
+        ),
+    ] {
+        let mut expected = Vec::new();
+        let mut iter = storage.scan(lower, upper).unwrap();
+        while iter.is_valid() {
+            expected.push(Bytes::copy_from_slice(iter.key()));
+            iter.next().unwrap();
+        }
+        let mut keys = Vec::new();
+        let mut iter = storage.scan_keys(lower, upper).unwrap();
+        while iter.is_valid() {
+            assert!(iter.value().is_empty());
+            keys.push(Bytes::copy_from_slice(iter.key()));
+            iter.next().unwrap();
+        }
+        assert_eq!(keys, expected);
+    }
+    assert_eq!(
+        storage
+            .scan_keys(Bound::Unbounded, Bound::Unbounded)
+            .unwrap()
+            .count()
+            .unwrap(),
+        // 200 live keys, 20 of them deleted and 10 tombstones overwritten
+        200 - 20 + 10
+    );
+}
+
+#[test]
+#[ignore = "benchmark, run with `cargo test --release -- --ignored bench_scan_keys`"]
+fn bench_scan_keys() {
+    use std::time::Instant;
+
+    let dir = tempdir().unwrap();
+    let storage = wide_value_storage(&dir, 20_000);
+    for _ in 0..3 {
+        let start = Instant::now();
+        let count = storage
+            .scan(Bound::Unbounded, Bound::Unbounded)
+            .unwrap()
+            .count()
+            .unwrap();
+        let scan = start.elapsed();
+        let start = Instant::now();
+        let key_count = storage
+            .scan_keys(Bound::Unbounded, Bound::Unbounded)
+            .unwrap()
+            .count()
+            .unwrap();
+        let scan_keys = start.elapsed();
+        assert_eq!(count, key_count);
+        println!(
+            "{} keys with 1 KiB values: scan {:?}, scan_keys {:?}",
+            count, scan, scan_keys
+        );
+    }
+}
@@ -7,8 +7,9 @@ use self::harness::{check_lsm_iter_result_by_key, sync};
 
 use super::*;
 use crate::{
-    iterators::StorageIterator,
+    iterators::{instrumented_iterator::IterStats, StorageIterator},
     lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm},
+    table::SsTable,
 };
 
 #[test]
@@ -195,3 +196,213 @@ fn test_task3_sst_filter() {
         .unwrap();
     assert!(min_num <= iter.num_active_iterators() && iter.num_active_iterators() < max_num);
 }
+
+#[test]
+fn test_flush_empty_memtable() {
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    {
+        let state = storage.state.read();
+        assert!(state.imm_memtables.is_empty());
+        assert!(state.l0_sstables.is_empty());
+    }
+    let has_sst = std::fs::read_dir(dir.path())
+        .unwrap()
+        .any(|entry| entry.unwrap().path().extension() == Some("sst".as_ref()));
+    assert!(!has_sst);
+}
+
+/// Take an SST out of the LSM state, as a compaction would for its inputs.
+fn remove_sst_from_state(storage: &LsmStorageInner, sst_id: usize) -> Arc<SsTable> {
+    let mut guard = storage.state.write();
+    let mut snapshot = guard.as_ref().clone();
+    snapshot.l0_sstables.retain(|id| *id != sst_id);
+    let sst = snapshot.sstables.remove(&sst_id).unwrap();
+    *guard = Arc::new(snapshot);
+    sst
+}
+
+#[test]
+fn test_sst_deleted_after_last_reader() {
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    storage.put(b"1", b"v1").unwrap();
+    storage.put(b"2", b"v2").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    let sst_id = storage.state.read().l0_sstables[0];
+    let path = storage.path_of_sst(sst_id);
+
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    // simulate a compaction that replaces the SST
+    remove_sst_from_state(&storage, sst_id).mark_for_deletion();
+    assert!(storage.state.read().l0_sstables.is_empty());
+    assert!(storage.state.read().sstables.is_empty());
+    assert!(path.exists());
+    assert_eq!(iter.key(), b"1");
+    iter.next().unwrap();
+    assert_eq!(iter.key(), b"2");
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+    assert!(path.exists());
+    drop(iter);
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_meta_memory_usage() {
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    assert_eq!(storage.meta_memory_usage(), 0);
+    let flush = |keys: &[&[u8]]| {
+        for key in keys {
+            storage.put(key, b"value").unwrap();
+        }
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+        let sst_id = storage.state.read().l0_sstables[0];
+        storage.state.read().sstables[&sst_id].clone()
+    };
+
+    let sst1 = flush(&[b"key_1", b"key_2"]);
+    let usage1 = sst1.meta_memory_usage();
+    let bloom = sst1.bloom_stats().memory_usage;
+    assert!(bloom > 0);
+    // one block with both keys as its first and last keys, the summary of its index partition
+    // with the same keys, plus the SST key range and the bloom
+    assert_eq!(
+        usage1,
+        2 * std::mem::size_of::<crate::table::BlockMeta>() + 6 * b"key_1".len() + bloom
+    );
+    assert_eq!(storage.meta_memory_usage(), usage1);
+
+    let sst2 = flush(&[b"a_much_longer_key_1", b"a_much_longer_key_2"]);
+    let usage2 = sst2.meta_memory_usage();
+    assert!(usage2 > usage1);
+    assert_eq!(storage.meta_memory_usage(), usage1 + usage2);
+
+    remove_sst_from_state(&storage, sst1.sst_id());
+    assert_eq!(storage.meta_memory_usage(), usage2);
+    remove_sst_from_state(&storage, sst2.sst_id());
+    assert_eq!(storage.meta_memory_usage(), 0);
+}
+
+#[test]
+fn test_scan_with_stats() {
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    let key = |idx: usize| format!("k{:02}", idx).into_bytes();
+    // The older SST holds 20 keys, the newer one overwrites 10 of them and deletes 5, and the
+    // memtable overwrites one more.
+    for idx in 0..20 {
+        storage.put(&key(idx), b"v1").unwrap();
+    }
+    sync(&storage);
+    for idx in 0..10 {
+        storage.put(&key(idx), b"v2").unwrap();
+    }
+    for idx in 10..15 {
+        storage.delete(&key(idx)).unwrap();
+    }
+    sync(&storage);
+    storage.put(&key(15), b"v3").unwrap();
+    let block_bytes = {
+        let state = storage.state.read();
+        state
+            .l0_sstables
+            .iter()
+            .map(|id| {
+                let block = state.sstables[id].read_block(0).unwrap();
+                (block.data.len() + block.offsets.len() * 2) as u64
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let (mut iter, stats) = storage
+        .scan_with_stats(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    let mut count = 0;
+    while iter.is_valid() {
+        count += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(count, 15);
+    assert_eq!(
+        stats.stats(),
+        IterStats {
+            entries_yielded: 15,
+            // 36 entries read: 5 deletes, and the older versions of 16 keys
+            entries_skipped: 21,
+            blocks_read: 2,
+            bytes_read: block_bytes.iter().sum(),
+            seek_count: 0,
+            read_amplification: 3,
+        }
+    );
+
+    // Only the older SST holds keys from k16 on.
+    let (mut iter, stats) = storage
+        .scan_with_stats(Bound::Included(b"k16"), Bound::Unbounded)
+        .unwrap();
+    assert_eq!(stats.stats().read_amplification, 1);
+    assert_eq!(iter.key(), b"k16");
+    iter.next().unwrap();
+    assert_eq!(
+        stats.stats(),
+        IterStats {
+            entries_yielded: 1,
+            entries_skipped: 0,
+            blocks_read: 1,
+            bytes_read: block_bytes[1],
+            seek_count: 1,
+            read_amplification: 1,
+        }
+    );
+}
+
+#[test]
+fn test_scan_excluded_lower_with_stats() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        block_size: 64,
+        ..LsmStorageOptions::default_for_week1_test()
+    };
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    for idx in 0..50 {
+        storage
+            .put(format!("k{:02}", idx).as_bytes(), b"value")
+            .unwrap();
+    }
+    sync(&storage);
+    let sst = {
+        let state = storage.state.read();
+        state.sstables[&state.l0_sstables[0]].clone()
+    };
+    assert!(sst.num_of_blocks() > 1);
+    let block_bytes = |idx: usize| {
+        let block = sst.read_block(idx).unwrap();
+        (block.data.len() + block.offsets.len() * 2) as u64
+    };
+    // Excluding the last key of the first block, the scan steps from the first block into the
+    // second one while it is created, and both are counted.
+    let last_key = sst.block_meta(0).unwrap().last_key.raw_ref().to_vec();
+    let (iter, stats) = storage
+        .scan_with_stats(Bound::Excluded(&last_key), Bound::Unbounded)
+        .unwrap();
+    assert!(iter.key() > &last_key[..]);
+    assert_eq!(stats.stats().blocks_read, 2);
+    assert_eq!(stats.stats().bytes_read, block_bytes(0) + block_bytes(1));
+    assert_eq!(stats.stats().seek_count, 1);
+}
@@ -1,8 +1,17 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
 use tempfile::tempdir;
 
 use crate::{
-    key::{KeySlice, TS_ENABLED},
-    table::{bloom::Bloom, FileObject, SsTable, SsTableBuilder},
+    key::{split_ts, KeySlice, TS_ENABLED},
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, ReadMetrics},
+    table::{
+        bloom::Bloom, BloomKind, BloomOptions, FileObject, Hasher, SsTable, SsTableBuilder,
+        SsTableOpenOptions,
+    },
+    tests::harness::check_lsm_iter_result_by_key,
 };
 
 fn key_of(idx: usize) -> Vec<u8> {
@@ -52,7 +61,9 @@ fn test_task2_sst_decode() {
     for idx in 0..num_of_keys() {
         let key = key_of(idx);
         let value = value_of(idx);
-        builder.add(KeySlice::for_testing_from_slice_no_ts(&key[..]), &value[..]);
+        builder
+            .add(KeySlice::for_testing_from_slice_no_ts(&key[..]), &value[..])
+            .unwrap();
     }
     let dir = tempdir().unwrap();
     let path = dir.path().join("1.sst");
@@ -70,22 +81,771 @@ fn test_task3_block_key_compression() {
     for idx in 0..num_of_keys() {
         let key = key_of(idx);
         let value = value_of(idx);
-        builder.add(KeySlice::for_testing_from_slice_no_ts(&key[..]), &value[..]);
+        builder
+            .add(KeySlice::for_testing_from_slice_no_ts(&key[..]), &value[..])
+            .unwrap();
     }
     let dir = tempdir().unwrap();
     let path = dir.path().join("1.sst");
     let sst = builder.build_for_test(path).unwrap();
     if TS_ENABLED {
         assert!(
-            sst.block_meta.len() <= 34,
+            sst.num_of_blocks() <= 34,
             "you have {} blocks, expect 34",
-            sst.block_meta.len()
+            sst.num_of_blocks()
         );
     } else {
         assert!(
-            sst.block_meta.len() <= 25,
+            sst.num_of_blocks() <= 25,
             "you have {} blocks, expect 25",
-            sst.block_meta.len()
+            sst.num_of_blocks()
         );
     }
 }
+
+fn build_sst_with_bloom(path: &std::path::Path, bloom_options: BloomOptions) -> SsTable {
+    let mut builder = SsTableBuilder::new(128).with_bloom(bloom_options);
+    for idx in 0..num_of_keys() {
+        let key = key_of(idx);
+        let value = value_of(idx);
+        builder
+            .add(KeySlice::for_testing_from_slice_no_ts(&key[..]), &value[..])
+            .unwrap();
+    }
+    builder.build_for_test(path).unwrap()
+}
+
+#[test]
+fn test_sst_without_bloom() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let sst = build_sst_with_bloom(
+        &path,
+        BloomOptions {
+            enabled: false,
+            ..Default::default()
+        },
+    );
+    assert!(sst.bloom.is_none());
+    let sst = SsTable::open(0, None, FileObject::open(&path).unwrap()).unwrap();
+    assert!(sst.bloom.is_none());
+    for idx in 0..num_of_keys() {
+        let key = key_of(idx);
+        let value = sst
+            .get(KeySlice::for_testing_from_slice_no_ts(&key[..]))
+            .unwrap();
+        assert_eq!(value.as_deref(), Some(&value_of(idx)[..]));
+    }
+    let key = key_of(num_of_keys());
+    assert!(sst
+        .get(KeySlice::for_testing_from_slice_no_ts(&key[..]))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_sst_bloom_options() {
+    let dir = tempdir().unwrap();
+    let sst = build_sst_with_bloom(
+        &dir.path().join("1.sst"),
+        BloomOptions {
+            min_num_keys: num_of_keys() + 1,
+            ..Default::default()
+        },
+    );
+    assert!(sst.bloom.is_none());
+    let path = dir.path().join("2.sst");
+    build_sst_with_bloom(
+        &path,
+        BloomOptions {
+            bits_per_key: Some(20),
+            min_num_keys: num_of_keys(),
+            ..Default::default()
+        },
+    );
+    let sst = SsTable::open(0, None, FileObject::open(&path).unwrap()).unwrap();
+    let bloom = sst.bloom.as_ref().unwrap();
+    assert_eq!(bloom.filter.len(), num_of_keys() * 20 / 8);
+}
+
+#[test]
+fn test_sst_recompute_bloom() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    build_sst_with_bloom(
+        &path,
+        BloomOptions {
+            enabled: false,
+            ..Default::default()
+        },
+    );
+    let mut sst = SsTable::open(0, None, FileObject::open(&path).unwrap()).unwrap();
+    let absent_keys = (num_of_keys()..num_of_keys() * 10).map(key_of);
+    assert!(absent_keys.clone().all(|key| sst.key_may_exist(&key)));
+
+    sst.recompute_bloom(0.01).unwrap();
+    assert!(sst.bloom.is_some());
+    for idx in 0..num_of_keys() {
+        assert!(sst.key_may_exist(&key_of(idx)));
+    }
+    let false_positives = absent_keys.filter(|key| sst.key_may_exist(key)).count();
+    assert!(
+        false_positives < num_of_keys(),
+        "too many false positives: {}",
+        false_positives
+    );
+}
+
+#[test]
+fn test_sst_bloom_fp_rate() {
+    let dir = tempdir().unwrap();
+    let build = |rate: f64, name: &str| {
+        let mut builder = SsTableBuilder::new(128).with_bloom_fp_rate(rate);
+        for idx in 0..num_of_keys() {
+            let key = key_of(idx);
+            let value = value_of(idx);
+            builder
+                .add(KeySlice::for_testing_from_slice_no_ts(&key[..]), &value[..])
+                .unwrap();
+        }
+        builder.build_for_test(dir.path().join(name)).unwrap()
+    };
+    let loose = build(0.1, "1.sst");
+    let tight = build(0.001, "2.sst");
+    let loose_bits = loose.bloom.as_ref().unwrap().filter.len() * 8;
+    let tight_bits = tight.bloom.as_ref().unwrap().filter.len() * 8;
+    assert!(tight_bits > loose_bits, "{} <= {}", tight_bits, loose_bits);
+}
+
+#[test]
+#[should_panic(expected = "false positive rate")]
+fn test_sst_bloom_fp_rate_out_of_range() {
+    let _ = SsTableBuilder::new(128).with_bloom_fp_rate(1.0);
+}
+
+#[test]
+fn test_sst_disable_bloom() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let mut builder = SsTableBuilder::new(128).disable_bloom();
+    for idx in 0..num_of_keys() {
+        let key = key_of(idx);
+        let value = value_of(idx);
+        builder
+            .add(KeySlice::for_testing_from_slice_no_ts(&key[..]), &value[..])
+            .unwrap();
+    }
+    let sst = builder.build_for_test(&path).unwrap();
+    assert!(sst.bloom.is_none());
+    let sst = SsTable::open(0, None, FileObject::open(&path).unwrap()).unwrap();
+    assert!(sst.bloom.is_none());
+    assert!(sst.key_may_exist(&key_of(num_of_keys())));
+}
+
+fn tenant_key(tenant: usize, object: usize) -> Vec<u8> {
+    format!("tenant_{:03}/object_{:05}", tenant, object).into_bytes()
+}
+
+const TENANT_PREFIX_LEN: usize = "tenant_000/".len();
+
+#[test]
+fn test_sst_prefix_bloom() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let mut builder = SsTableBuilder::new(128).with_bloom(BloomOptions {
+        prefix_len: Some(TENANT_PREFIX_LEN),
+        ..Default::default()
+    });
+    // Only even tenants are stored in the SST.
+    for tenant in (0..100).step_by(2) {
+        for object in 0..10 {
+            let key = tenant_key(tenant, object);
+            builder
+                .add(KeySlice::for_testing_from_slice_no_ts(&key), b"value")
+                .unwrap();
+        }
+    }
+    builder.build_for_test(&path).unwrap();
+    let sst = SsTable::open(0, None, FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(
+        sst.bloom.as_ref().unwrap().prefix_len,
+        Some(TENANT_PREFIX_LEN)
+    );
+
+    let tenant_prefix = |tenant: usize| tenant_key(tenant, 0)[..TENANT_PREFIX_LEN].to_vec();
+    for tenant in (0..100).step_by(2) {
+        assert!(sst.may_contain_prefix(&tenant_prefix(tenant)));
+        assert!(sst.may_contain_prefix(&tenant_key(tenant, 3)));
+        assert!(sst.key_may_exist(&tenant_key(tenant, 3)));
+    }
+    let false_positives = (1..100)
+        .step_by(2)
+        .filter(|&tenant| sst.may_contain_prefix(&tenant_prefix(tenant)))
+        .count();
+    assert!(
+        false_positives < 5,
+        "too many false positives: {}",
+        false_positives
+    );
+    // A prefix shorter than the indexed prefixes cannot be checked.
+    assert!(sst.may_contain_prefix(b"tenant_"));
+
+    // Without a prefix length, the filter cannot rule out any prefix.
+    let path = dir.path().join("2.sst");
+    let sst = build_sst_with_bloom(&path, BloomOptions::default());
+    assert!(sst.bloom.as_ref().unwrap().prefix_len.is_none());
+    assert!(sst.may_contain_prefix(&tenant_prefix(1)));
+}
+
+#[test]
+fn test_storage_scan_prefix() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        bloom_options: BloomOptions {
+            prefix_len: Some(TENANT_PREFIX_LEN),
+            ..Default::default()
+        },
+        ..LsmStorageOptions::default_for_week1_test()
+    };
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    // Tenants 1 and 3 share an SST whose key range covers tenant 2, which is in another SST.
+    for tenant in [1, 3, 2] {
+        for object in 0..10 {
+            storage.put(&tenant_key(tenant, object), b"value").unwrap();
+        }
+        if tenant != 1 {
+            storage
+                .force_freeze_memtable(&storage.state_lock.lock())
+                .unwrap();
+            storage.force_flush_next_imm_memtable().unwrap();
+        }
+    }
+    let snapshot = storage.state.read().clone();
+    let shared = &snapshot.sstables[&snapshot.l0_sstables[1]];
+    assert!(!shared.may_contain_prefix(&tenant_key(2, 0)[..TENANT_PREFIX_LEN]));
+
+    let mut iter = storage
+        .scan_prefix(&tenant_key(2, 0)[..TENANT_PREFIX_LEN])
+        .unwrap();
+    check_lsm_iter_result_by_key(
+        &mut iter,
+        (0..10)
+            .map(|object| (Bytes::from(tenant_key(2, object)), Bytes::from("value")))
+            .collect(),
+    );
+    let mut iter = storage.scan_prefix(b"tenant_003/object_0000").unwrap();
+    check_lsm_iter_result_by_key(
+        &mut iter,
+        (0..10)
+            .map(|object| (Bytes::from(tenant_key(3, object)), Bytes::from("value")))
+            .collect(),
+    );
+    let mut iter = storage.scan_prefix(b"tenant_004/").unwrap();
+    check_lsm_iter_result_by_key(&mut iter, vec![]);
+}
+
+#[test]
+fn test_storage_scan_prefix_edge_cases() {
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    let keys: [&[u8]; 7] = [
+        b"a",
+        b"a\xff",
+        b"a\xff\x01",
+        b"b",
+        b"\xff",
+        b"\xff\xff",
+        b"\xff\xff\x00",
+    ];
+    for (idx, key) in keys.iter().enumerate() {
+        storage.put(key, b"value").unwrap();
+        // Half of the keys go to an SST.
+        if idx == 3 {
+            storage
+                .force_freeze_memtable(&storage.state_lock.lock())
+                .unwrap();
+            storage.force_flush_next_imm_memtable().unwrap();
+        }
+    }
+    let expected = |prefix: &[u8]| {
+        keys.iter()
+            .filter(|key| key.starts_with(prefix))
+            .map(|key| (Bytes::copy_from_slice(key), Bytes::from("value")))
+            .collect::<Vec<_>>()
+    };
+    // The empty prefix and prefixes of only 0xff have no successor, so they scan to the end.
+    for prefix in [&b""[..], b"a\xff", b"\xff", b"\xff\xff", b"\xff\xff\xff"] {
+        let mut iter = storage.scan_prefix(prefix).unwrap();
+        check_lsm_iter_result_by_key(&mut iter, expected(prefix));
+    }
+    assert_eq!(expected(b"").len(), keys.len());
+}
+
+/// Return the offset of the bloom section of an encoded SST.
+fn bloom_offset(data: &[u8]) -> usize {
+    let footer = &data[data.len() - 12..];
+    let properties_offset = u64::from_be_bytes(footer[..8].try_into().unwrap()) as usize;
+    u64::from_be_bytes(
+        data[properties_offset - 8..properties_offset]
+            .try_into()
+            .unwrap(),
+    ) as usize
+}
+
+#[test]
+fn test_sst_bloom_checksum() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    build_sst_with_bloom(&path, BloomOptions::default());
+    let mut data = std::fs::read(&path).unwrap();
+    let offset = bloom_offset(&data);
+    data[offset + 3] ^= 0xff;
+    let file = |data: &[u8]| FileObject::from_bytes(Bytes::copy_from_slice(data));
+
+    // The SST is opened without the corrupted bloom filter, and gets read the data blocks.
+    let sst = SsTable::open(0, None, file(&data)).unwrap();
+    assert!(sst.bloom.is_none());
+    for idx in 0..num_of_keys() {
+        let key = key_of(idx);
+        let value = sst
+            .get(KeySlice::for_testing_from_slice_no_ts(&key[..]))
+            .unwrap();
+        assert_eq!(value.as_deref(), Some(&value_of(idx)[..]));
+    }
+
+    let strict = SsTableOpenOptions {
+        strict_bloom_checksum: true,
+        ..Default::default()
+    };
+    let err = SsTable::open_with_options(0, None, file(&data), &strict)
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("checksum mismatch"), "{:#}", err);
+
+    // So is a corrupted checksum over an intact bloom filter.
+    let mut data = std::fs::read(&path).unwrap();
+    let properties_offset =
+        u64::from_be_bytes(data[data.len() - 12..data.len() - 4].try_into().unwrap()) as usize;
+    data[properties_offset - 9] ^= 0xff;
+    assert!(SsTable::open_with_options(0, None, file(&data), &strict).is_err());
+    assert!(SsTable::open(0, None, file(&data)).unwrap().bloom.is_none());
+
+    // A bloom offset pointing past the bloom section is rejected.
+    let properties_offset =
+        u64::from_be_bytes(data[data.len() - 12..data.len() - 4].try_into().unwrap()) as usize;
+    let file_size = data.len() as u64;
+    data[properties_offset - 8..properties_offset].copy_from_slice(&file_size.to_be_bytes());
+    assert!(SsTable::open(0, None, file(&data)).is_err());
+}
+
+#[test]
+fn test_storage_bloom_stats() {
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    // Only even keys are stored, so odd keys are absent but within the key range of the SST.
+    for idx in (0..num_of_keys()).step_by(2) {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    assert_eq!(storage.bloom_stats().checks, 0);
+
+    for idx in 0..num_of_keys() {
+        let value = storage.get(&key_of(idx)).unwrap();
+        if idx % 2 == 0 {
+            assert_eq!(value.as_deref(), Some(&value_of(idx)[..]));
+        } else {
+            assert!(value.is_none());
+        }
+    }
+    // Keys outside of the key range of the SST are not checked against the bloom filter.
+    assert!(storage.get(b"zzz").unwrap().is_none());
+
+    let stats = storage.bloom_stats();
+    let absent = num_of_keys() / 2;
+    assert_eq!(stats.checks, num_of_keys() as u64 - 1);
+    assert_eq!(stats.negatives + stats.false_positives, absent as u64 - 1);
+    assert!(stats.negatives > stats.false_positives, "{:?}", stats);
+
+    let snapshot = storage.state.read().clone();
+    let sst = &snapshot.sstables[&snapshot.l0_sstables[0]];
+    assert_eq!(sst.bloom_stats(), stats);
+    let bloom = sst.bloom.as_ref().unwrap();
+    assert_eq!(stats.memory_usage, bloom.memory_usage());
+    assert!(bloom.memory_usage() > bloom.filter.len());
+}
+
+#[test]
+fn test_sst_bloom_user_key() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        let key = key_of(idx);
+        let value = value_of(idx);
+        builder
+            .add(
+                KeySlice::for_testing_from_slice_with_ts(&key[..], 5),
+                &value[..],
+            )
+            .unwrap();
+    }
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    // The bloom filter is probed with the user key, whatever the read timestamp is.
+    for idx in 0..num_of_keys() {
+        let key = key_of(idx);
+        assert!(sst.key_may_exist(&key));
+        let value = sst
+            .get(KeySlice::for_testing_from_slice_with_ts(&key[..], 10))
+            .unwrap();
+        assert_eq!(value.as_deref(), Some(&value_of(idx)[..]));
+    }
+}
+
+#[test]
+fn test_bloom_user_key_of_ts_key() {
+    // Raw keys with the timestamp suffix, as written once timestamps are enabled.
+    let raw_key_with_ts = |key: &[u8], ts: u64| [key, &ts.to_be_bytes()[..]].concat();
+    let key = key_of(7);
+    let written = raw_key_with_ts(&key, 5);
+    let read = raw_key_with_ts(&key, 10);
+    assert_ne!(Bloom::hash_key(&written), Bloom::hash_key(&read));
+    let (written_user_key, written_ts) = split_ts(&written);
+    let (read_user_key, read_ts) = split_ts(&read);
+    assert_eq!((written_user_key, written_ts), (&key[..], 5));
+    assert_eq!((read_user_key, read_ts), (&key[..], 10));
+    // The filter built from the user key of the written version answers "maybe" at any read
+    // timestamp.
+    let bloom = Bloom::build_from_key_hashes(&[Bloom::hash_key(written_user_key)], 10);
+    assert!(bloom.may_contain_key(read_user_key));
+    if TS_ENABLED {
+        assert_eq!(KeySlice::from_slice(&written).key_ref(), &key[..]);
+        assert_eq!(KeySlice::from_slice(&read).key_ref(), &key[..]);
+    }
+}
+
+#[test]
+fn test_bloom_may_contain_key() {
+    let keys = (0..1000).map(key_of).collect::<Vec<_>>();
+    let key_hashes = keys
+        .iter()
+        .map(|key| Bloom::hash_key(key))
+        .collect::<Vec<_>>();
+    for false_positive_rate in [0.1, 0.01] {
+        let bits_per_key = Bloom::bloom_bits_per_key(keys.len(), false_positive_rate);
+        let bloom = Bloom::build_from_key_hashes(&key_hashes, bits_per_key);
+        for key in &keys {
+            assert!(bloom.may_contain_key(key));
+            assert!(bloom.may_contain(Bloom::hash_key(key)));
+        }
+        let num_absent = 20000;
+        let false_positives = (0..num_absent)
+            .filter(|idx| bloom.may_contain_key(format!("absent_{}", idx).as_bytes()))
+            .count();
+        let rate = false_positives as f64 / num_absent as f64;
+        assert!(
+            rate < false_positive_rate * 2.0,
+            "false positive rate {} with a target of {}",
+            rate,
+            false_positive_rate
+        );
+    }
+
+    let mut builder = SsTableBuilder::new(128);
+    for key in &keys {
+        builder
+            .add(KeySlice::for_testing_from_slice_no_ts(key), b"value")
+            .unwrap();
+    }
+    let sst = builder.build_in_memory(0).unwrap();
+    let bloom = sst.bloom().unwrap();
+    assert!(keys.iter().all(|key| bloom.may_contain_key(key)));
+
+    let mut builder = SsTableBuilder::new(128).disable_bloom();
+    builder
+        .add(KeySlice::for_testing_from_slice_no_ts(&keys[0]), b"value")
+        .unwrap();
+    assert!(builder.build_in_memory(1).unwrap().bloom().is_none());
+}
+
+fn fnv1a(key: &[u8]) -> u32 {
+    key.iter().fold(0x811c9dc5, |hash: u32, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+#[test]
+fn test_bloom_custom_hasher() {
+    let hasher: Arc<dyn Hasher> = Arc::new(fnv1a);
+    let mut builder = SsTableBuilder::new(128).with_hasher(hasher.clone());
+    for idx in 0..num_of_keys() {
+        builder
+            .add(
+                KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+                &value_of(idx),
+            )
+            .unwrap();
+    }
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let sst = builder.build(0, None, &path).unwrap();
+
+    let bloom = sst.bloom().unwrap();
+    for idx in 0..num_of_keys() {
+        assert!(bloom.may_contain(fnv1a(&key_of(idx))));
+        assert_eq!(
+            sst.get(KeySlice::for_testing_from_slice_no_ts(&key_of(idx)))
+                .unwrap(),
+            Some(Bytes::from(value_of(idx)))
+        );
+    }
+    let absent = (0..1000)
+        .filter(|idx| !sst.key_may_exist(format!("absent_{}", idx).as_bytes()))
+        .count();
+    assert!(absent > 900, "only {} absent keys rejected", absent);
+
+    let options = SsTableOpenOptions {
+        bloom_hasher: Some(hasher),
+        ..Default::default()
+    };
+    let sst =
+        SsTable::open_with_options(0, None, FileObject::open(&path).unwrap(), &options).unwrap();
+    assert!(sst.bloom().is_some());
+    for idx in 0..num_of_keys() {
+        assert!(sst.key_may_exist(&key_of(idx)));
+    }
+
+    // Opened with the default hasher, the SST drops the bloom filter instead of rejecting keys.
+    let sst = SsTable::open(0, None, FileObject::open(&path).unwrap()).unwrap();
+    assert!(sst.bloom().is_none());
+    for idx in 0..num_of_keys() {
+        assert_eq!(
+            sst.get(KeySlice::for_testing_from_slice_no_ts(&key_of(idx)))
+                .unwrap(),
+            Some(Bytes::from(value_of(idx)))
+        );
+    }
+}
+
+#[test]
+fn test_bloom_encode_version() {
+    let key_hashes = (0..100)
+        .map(|idx| Bloom::hash_key(&key_of(idx)))
+        .collect::<Vec<_>>();
+    let bloom = Bloom::build_from_key_hashes(&key_hashes, 10);
+    let mut buf = Vec::new();
+    bloom.encode(&mut buf);
+    assert_eq!(buf[0], 1);
+    assert_eq!(buf.len(), 1 + bloom.filter.len() + 1);
+
+    // a version 1 filter laid out by hand
+    let mut v1 = vec![1];
+    v1.extend_from_slice(&bloom.filter);
+    v1.push(bloom.k);
+    let decoded = Bloom::decode(&v1).unwrap();
+    assert_eq!(decoded.filter, bloom.filter);
+    assert_eq!(decoded.k, bloom.k);
+    assert!(key_hashes.iter().all(|hash| decoded.may_contain(*hash)));
+
+    let mut bogus = buf.clone();
+    bogus[0] = 7;
+    let err = Bloom::decode(&bogus).err().unwrap();
+    assert!(err.to_string().contains("version 7"), "{}", err);
+    assert!(Bloom::decode(&[]).is_err());
+    assert!(Bloom::decode(&[1]).is_err());
+}
+
+#[test]
+fn test_bloom_union() {
+    let hashes = |keys: std::ops::Range<usize>| {
+        keys.map(|idx| Bloom::hash_key(&key_of(idx)))
+            .collect::<Vec<_>>()
+    };
+    let first = Bloom::build_from_key_hashes(&hashes(0..1000), 10);
+    let second = Bloom::build_from_key_hashes(&hashes(1000..2000), 10);
+    let union = first.union(&second).unwrap();
+    assert_eq!(union.filter.len(), first.filter.len());
+    assert_eq!(union.k, first.k);
+    for idx in 0..2000 {
+        assert!(union.may_contain_key(&key_of(idx)));
+    }
+    // The union holds twice the keys the filters were sized for, but still rejects most others.
+    let false_positives = (0..10000)
+        .filter(|idx| union.may_contain_key(format!("absent_{}", idx).as_bytes()))
+        .count();
+    assert!(
+        false_positives < 2000,
+        "{} false positives",
+        false_positives
+    );
+
+    let larger = Bloom::build_from_key_hashes(&hashes(0..2000), 10);
+    assert!(first.union(&larger).is_err());
+    // Same number of bits, but fewer hash functions.
+    let fewer_hashes = Bloom::build_from_key_hashes(&hashes(0..2000), 5);
+    assert_eq!(fewer_hashes.filter.len(), first.filter.len());
+    assert!(first.union(&fewer_hashes).is_err());
+    // Same shape, but the keys were hashed with another hasher.
+    let custom_keys = (1000..2000)
+        .map(|idx| fnv1a(&key_of(idx)))
+        .collect::<Vec<_>>();
+    let custom = Bloom::build_from_key_hashes(&custom_keys, 10).with_hasher(Arc::new(fnv1a));
+    assert_eq!(custom.filter.len(), first.filter.len());
+    let err = first.union(&custom).err().unwrap();
+    assert!(err.to_string().contains("hashers"), "{}", err);
+}
+
+#[test]
+fn test_blocked_bloom() {
+    let keys = (0..10000).map(key_of).collect::<Vec<_>>();
+    let key_hashes = keys
+        .iter()
+        .map(|key| Bloom::hash_key(key))
+        .collect::<Vec<_>>();
+    let bits_per_key = Bloom::bloom_bits_per_key(keys.len(), 0.01);
+    let bloom =
+        Bloom::build_from_key_hashes_with_kind(&key_hashes, bits_per_key, BloomKind::Blocked);
+    assert_eq!(bloom.filter.len() % 64, 0);
+    assert!(keys.iter().all(|key| bloom.may_contain_key(key)));
+    let false_positives = (0..20000)
+        .filter(|idx| bloom.may_contain_key(format!("absent_{}", idx).as_bytes()))
+        .count();
+    assert!(false_positives < 600, "{} false positives", false_positives);
+
+    let mut buf = Vec::new();
+    bloom.encode(&mut buf);
+    let decoded = Bloom::decode(&buf).unwrap();
+    assert_eq!(decoded.kind, BloomKind::Blocked);
+    assert!(keys.iter().all(|key| decoded.may_contain_key(key)));
+    let standard = Bloom::build_from_key_hashes(&key_hashes, bits_per_key);
+    assert!(bloom.union(&standard).is_err());
+
+    // An SST built with a blocked bloom filter is reopened with it.
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let mut builder = SsTableBuilder::new(128).with_bloom(BloomOptions {
+        kind: BloomKind::Blocked,
+        ..Default::default()
+    });
+    for idx in 0..num_of_keys() {
+        builder
+            .add(
+                KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+                &value_of(idx),
+            )
+            .unwrap();
+    }
+    let estimated_size = builder.estimated_file_size();
+    let sst = builder.build(0, None, &path).unwrap();
+    assert_eq!(sst.table_size() as usize, estimated_size);
+    let sst = SsTable::open(0, None, FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(sst.bloom().unwrap().kind, BloomKind::Blocked);
+    for idx in 0..num_of_keys() {
+        assert!(sst.key_may_exist(&key_of(idx)));
+    }
+}
+
+#[test]
+#[ignore = "benchmark, run with `cargo test --release -- --ignored bloom_probe`"]
+fn bench_bloom_probe() {
+    use std::time::Instant;
+
+    let num_keys = 4_000_000;
+    let key_hashes = (0..num_keys)
+        .map(|idx: u32| Bloom::hash_key(&idx.to_be_bytes()))
+        .collect::<Vec<_>>();
+    let bits_per_key = Bloom::bloom_bits_per_key(num_keys as usize, 0.01);
+    let probes = (0..num_keys)
+        .map(|idx: u32| Bloom::hash_key(&(idx + num_keys).to_be_bytes()))
+        .collect::<Vec<_>>();
+    for kind in [BloomKind::Standard, BloomKind::Blocked] {
+        let bloom = Bloom::build_from_key_hashes_with_kind(&key_hashes, bits_per_key, kind);
+        let start = Instant::now();
+        let false_positives = probes.iter().filter(|h| bloom.may_contain(**h)).count();
+        let elapsed = start.elapsed();
+        println!(
+            "{:?}: {} bytes, {:.1} ns per probe, false positive rate {:.4}",
+            kind,
+            bloom.filter.len(),
+            elapsed.as_nanos() as f64 / probes.len() as f64,
+            false_positives as f64 / probes.len() as f64
+        );
+    }
+}
+
+#[test]
+fn test_sst_bloom_observed_fp_rate() {
+    let mut builder = SsTableBuilder::new(128).with_bloom_fp_rate(0.01);
+    for idx in 0..2000 {
+        builder
+            .add(
+                KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+                &value_of(idx),
+            )
+            .unwrap();
+    }
+    let sst = builder.build_in_memory(0).unwrap();
+    assert_eq!(sst.bloom_stats().false_positive_rate(), None);
+    // Stored keys are multiples of 5, so the keys in between are absent but within the key range.
+    for idx in 0..2000 * 5 {
+        let key = format!("key_{:010}", idx).into_bytes();
+        let value = sst
+            .get(KeySlice::for_testing_from_slice_no_ts(&key))
+            .unwrap();
+        assert_eq!(value.is_some(), idx % 5 == 0);
+    }
+    let stats = sst.bloom_stats();
+    assert_eq!(stats.negatives + stats.false_positives, 2000 * 4 - 4);
+    let rate = stats.false_positive_rate().unwrap();
+    assert!(rate < 0.02, "observed false positive rate {}", rate);
+}
+
+#[test]
+fn test_storage_read_metrics() {
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    // Three L0 SSTs over the same key range, each with a third of the keys.
+    for sst in 0..3 {
+        for idx in (sst..num_of_keys()).step_by(3) {
+            storage.put(&key_of(idx), &value_of(idx)).unwrap();
+        }
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    assert_eq!(storage.read_metrics(), ReadMetrics::default());
+
+    // The memtable and the SST holding the key are probed, the bloom filters rule out the others.
+    let idx = num_of_keys() / 2;
+    assert_eq!(
+        storage.get(&key_of(idx)).unwrap().as_deref(),
+        Some(&value_of(idx)[..])
+    );
+    let bloom = storage.bloom_stats();
+    assert_eq!((bloom.checks, bloom.negatives), (3, 2));
+    let metrics = storage.read_metrics();
+    assert_eq!((metrics.gets, metrics.get_probes), (1, 2));
+    assert_eq!(metrics.max_read_amp, 2);
+    assert_eq!(metrics.avg_get_read_amp(), Some(2.0));
+    assert_eq!(metrics.avg_scan_read_amp(), None);
+
+    // A key in the memtable is found without probing the SSTs.
+    storage.put(b"0", b"v").unwrap();
+    assert!(storage.get(b"0").unwrap().is_some());
+    assert_eq!(storage.read_metrics().get_probes, 3);
+
+    // A scan merges the memtable and every SST overlapping its range.
+    storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    storage
+        .scan(Bound::Included(b"zzz"), Bound::Unbounded)
+        .unwrap();
+    let metrics = storage.read_metrics();
+    assert_eq!((metrics.scans, metrics.scan_probes), (2, 5));
+    assert_eq!(metrics.max_read_amp, 4);
+    assert_eq!(metrics.avg_scan_read_amp(), Some(2.5));
+}